@@ -0,0 +1,434 @@
+//! Path-level access rules within volumes (see `common::auth::PathRule`).
+
+use crate::trash::is_trash_path;
+use common::auth::{PathRule, RuleEffect};
+use common::User;
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    volume: String,
+    // `None` for a pattern that failed to compile: deny rules then match
+    // everything and allow rules nothing, so a typo never opens access.
+    matcher: Option<GlobMatcher>,
+    effect: RuleEffect,
+    read: bool,
+    write: bool,
+}
+
+impl CompiledRule {
+    fn matches(&self, path: &str) -> bool {
+        let Some(matcher) = &self.matcher else {
+            return self.effect == RuleEffect::Deny;
+        };
+        // A rule on a directory covers everything below it.
+        ancestors(path).any(|p| matcher.is_match(p))
+    }
+}
+
+/// `a/b/c` yields `a/b/c`, `a/b` and `a`.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(path), |p| p.rsplit_once('/').map(|(parent, _)| parent))
+        .filter(|p| !p.is_empty())
+}
+
+pub fn compile_glob(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    GlobBuilder::new(pattern.trim_matches('/'))
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+}
+
+/// Rejects rules that would be ignored or misread: unknown volumes, empty
+/// subjects, bad access strings and patterns that do not compile.
+pub fn validate_rule(rule: &PathRule, volumes: &[&str]) -> Result<(), String> {
+    if !volumes.contains(&rule.volume.as_str()) {
+        return Err(format!("Unknown volume '{}'", rule.volume));
+    }
+    if rule.subjects.is_empty() {
+        return Err(format!("Rule for '{}' has no subjects", rule.pattern));
+    }
+    if !matches!(rule.access.as_str(), "r" | "w" | "rw") {
+        return Err(format!(
+            "Access for '{}' must be 'r', 'w' or 'rw'",
+            rule.pattern
+        ));
+    }
+    compile_glob(&rule.pattern)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid pattern '{}': {}", rule.pattern, e))
+}
+
+/// Normalises a request path to the form rules are matched against:
+/// `/`-separated, relative to the volume root, without `.` or empty segments.
+pub fn normalize(path: &str) -> String {
+    path.split(['/', '\\'])
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The normalised path of `file` relative to the volume root.
+pub fn relative_path(root: &Path, file: &Path) -> String {
+    file.strip_prefix(root)
+        .unwrap_or(file)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(segment) => Some(segment.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// What one user may do where, combining volume permissions with the path
+/// rules that apply to them. Built per request by `auth::require_auth`.
+#[derive(Debug, Clone)]
+pub struct PathAcl {
+    permissions: HashMap<String, String>,
+    rules: Vec<CompiledRule>,
+}
+
+impl PathAcl {
+    pub fn new(user: &User, rules: &[PathRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter(|rule| rule.applies_to(user))
+            .map(|rule| CompiledRule {
+                volume: rule.volume.clone(),
+                matcher: compile_glob(&rule.pattern)
+                    .map_err(|e| eprintln!("Invalid path rule '{}': {}", rule.pattern, e))
+                    .ok(),
+                effect: rule.effect,
+                read: rule.access.contains('r'),
+                write: rule.access.contains('w'),
+            })
+            .collect();
+        Self {
+            permissions: user.permissions.clone(),
+            rules,
+        }
+    }
+
+    /// Whether any rule narrows this user's access to the volume. Callers can
+    /// skip per-file checks of whole directory trees when there is none.
+    pub fn restricts(&self, volume: &str) -> bool {
+        self.rules.iter().any(|rule| rule.volume == volume)
+    }
+
+    fn decide(&self, volume: &str, path: &str, write: bool) -> bool {
+        // The trash is only reachable through its own endpoints.
+        if is_trash_path(path) {
+            return false;
+        }
+        let path = normalize(path);
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| rule.volume == volume && if write { rule.write } else { rule.read })
+            .find(|rule| rule.matches(&path))
+            .is_none_or(|rule| rule.effect == RuleEffect::Allow)
+    }
+
+    pub fn can_read(&self, volume: &str, path: &str) -> bool {
+        self.permissions
+            .get(volume)
+            .is_some_and(|mode| mode.contains('r'))
+            && self.decide(volume, path, false)
+    }
+
+    pub fn can_write(&self, volume: &str, path: &str) -> bool {
+        self.permissions
+            .get(volume)
+            .is_some_and(|mode| mode.contains('w'))
+            && self.can_read(volume, path)
+            && self.decide(volume, path, true)
+    }
+
+    /// Whether `path` and, for a directory, everything inside it is writable.
+    /// Walks the directory, so call it from blocking code.
+    pub fn can_write_tree(&self, volume: &str, root: &Path, path: &str) -> bool {
+        if !self.can_write(volume, path) {
+            return false;
+        }
+        if !self.restricts(volume) {
+            return true;
+        }
+        walkdir::WalkDir::new(root.join(path))
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .all(|entry| self.can_write(volume, &relative_path(root, entry.path())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::http::{Method, StatusCode};
+
+    fn user(name: &str, groups: &[&str]) -> User {
+        User {
+            username: name.to_string(),
+            permissions: HashMap::from([
+                ("team".to_string(), "rw".to_string()),
+                ("docs".to_string(), "r".to_string()),
+            ]),
+            admin: false,
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+
+    fn rule(pattern: &str, subjects: &[&str], effect: RuleEffect, access: &str) -> PathRule {
+        PathRule {
+            volume: "team".to_string(),
+            pattern: pattern.to_string(),
+            subjects: subjects.iter().map(|s| s.to_string()).collect(),
+            effect,
+            access: access.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_later_rules_override_earlier_ones() {
+        let rules = vec![
+            rule("hr", &["*"], RuleEffect::Deny, "rw"),
+            rule("hr", &["@hr"], RuleEffect::Allow, "rw"),
+            rule("finance/**", &["*"], RuleEffect::Deny, "w"),
+            rule("finance/budget.md", &["carol"], RuleEffect::Allow, "w"),
+        ];
+
+        let bob = PathAcl::new(&user("bob", &[]), &rules);
+        assert!(!bob.can_read("team", "hr"));
+        assert!(!bob.can_read("team", "hr/salaries.md"));
+        assert!(!bob.can_read("team", "./hr//salaries.md"));
+        assert!(bob.can_read("team", "hrx.md"));
+        assert!(bob.can_read("team", "finance/budget.md"));
+        assert!(!bob.can_write("team", "finance/budget.md"));
+        assert!(bob.can_write("team", "finance"));
+        assert!(bob.can_write("team", "index.md"));
+        assert!(!bob.can_write("docs", "index.md"));
+        assert!(bob.can_read("docs", "hr/anything.md"));
+        assert!(bob.restricts("team") && !bob.restricts("docs"));
+
+        let alice = PathAcl::new(&user("alice", &["hr"]), &rules);
+        assert!(alice.can_write("team", "hr/salaries.md"));
+
+        let carol = PathAcl::new(&user("carol", &[]), &rules);
+        assert!(carol.can_write("team", "finance/budget.md"));
+        assert!(!carol.can_write("team", "finance/q3.md"));
+    }
+
+    #[test]
+    fn test_invalid_patterns_fail_closed() {
+        let rules = vec![
+            rule("[", &["*"], RuleEffect::Deny, "r"),
+            rule("[", &["bob"], RuleEffect::Allow, "r"),
+        ];
+        let bob = PathAcl::new(&user("bob", &[]), &rules);
+        assert!(!bob.can_read("team", "index.md"));
+        assert!(validate_rule(&rules[0], &["team"]).is_err());
+        assert!(validate_rule(&rule("hr/*.md", &["*"], RuleEffect::Deny, "rw"), &["team"]).is_ok());
+        assert!(validate_rule(&rule("hr", &["*"], RuleEffect::Deny, "x"), &["team"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_path_rules_enforced() {
+        use common::auth::{PathRule, RuleEffect};
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let personal = dir.path().join("personal");
+        for file in [
+            "hr/salaries.md",
+            "notes/a.md",
+            "notes/locked.md",
+            "secret.md",
+        ] {
+            let path = personal.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "salary data").unwrap();
+        }
+        let rule = |pattern: &str, subject: &str, effect, access: &str| PathRule {
+            volume: "personal".to_string(),
+            pattern: pattern.to_string(),
+            subjects: vec![subject.to_string()],
+            effect,
+            access: access.to_string(),
+        };
+        let rules = vec![
+            rule("hr", "*", RuleEffect::Deny, "rw"),
+            rule("hr", "@hr", RuleEffect::Allow, "rw"),
+            rule("secret.md", "*", RuleEffect::Deny, "r"),
+            rule("notes/locked.md", "alice", RuleEffect::Deny, "w"),
+        ];
+        state
+            .users
+            .update(move |store| store.path_rules = rules)
+            .await
+            .unwrap();
+        let app = crate::app(state.clone());
+        let cookie = login_cookie(&app).await;
+
+        for (method, uri, expected) in [
+            (
+                Method::GET,
+                "/api/wiki/personal/hr/salaries.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::GET,
+                "/api/wiki/personal/hr//salaries.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::PUT,
+                "/api/wiki/personal/hr/new.md",
+                StatusCode::FORBIDDEN,
+            ),
+            // The `.md` fallback resolves to a denied file.
+            (
+                Method::GET,
+                "/api/wiki/personal/secret",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::GET,
+                "/api/wiki/personal/notes/locked.md",
+                StatusCode::OK,
+            ),
+            (
+                Method::PUT,
+                "/api/wiki/personal/notes/locked.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::POST,
+                "/api/upload/personal/hr/photo.png",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::DELETE,
+                "/api/wiki/personal/notes",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::DELETE,
+                "/api/wiki/personal/notes/a.md",
+                StatusCode::OK,
+            ),
+        ] {
+            assert_eq!(
+                send(&app, &cookie, method.clone(), uri).await,
+                expected,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+        assert!(personal.join("notes/locked.md").exists());
+
+        let (status, _) = post_json(
+            &app,
+            &cookie,
+            "/api/rename/personal/notes",
+            r#"{"new_path":"archive"}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = post_json(
+            &app,
+            &cookie,
+            "/api/rename/personal/index.md",
+            r#"{"new_path":"hr/index.md"}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(personal.join("index.md").exists());
+
+        let (_, tree) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/tree?volume=personal",
+            String::new(),
+        )
+        .await;
+        let names: Vec<&str> = tree
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| node["path"].as_str().unwrap())
+            .collect();
+        assert!(!names.contains(&"hr") && !names.contains(&"secret.md"));
+        assert!(names.contains(&"notes"));
+
+        let (_, results) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/search?q=salary&volume=personal",
+            String::new(),
+        )
+        .await;
+        let paths: Vec<&str> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, vec!["notes/locked.md"]);
+
+        // Group membership applies to the live session.
+        state
+            .users
+            .update(|store| {
+                store.users.get_mut("alice").unwrap().groups = vec!["hr".to_string()];
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            send(
+                &app,
+                &cookie,
+                Method::GET,
+                "/api/wiki/personal/hr/salaries.md"
+            )
+            .await,
+            StatusCode::OK
+        );
+
+        state
+            .users
+            .update(|store| store.users.get_mut("alice").unwrap().admin = true)
+            .await
+            .unwrap();
+        for (rules, expected) in [
+            (
+                r#"[{"volume":"nope","pattern":"a","subjects":["*"],"effect":"deny","access":"r"}]"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                r#"[{"volume":"personal","pattern":"[","subjects":["*"],"effect":"deny","access":"r"}]"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            ("[]", StatusCode::OK),
+        ] {
+            let (status, _) = send_json(
+                &app,
+                &cookie,
+                Method::PUT,
+                "/api/admin/path-rules",
+                rules.to_string(),
+            )
+            .await;
+            assert_eq!(status, expected, "{}", rules);
+        }
+        assert_eq!(
+            send(&app, &cookie, Method::GET, "/api/wiki/personal/secret.md").await,
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,474 @@
+//! Account management for administrators, under `/api/admin`.
+//!
+//! Changes are written to the users file. Permission changes apply to live
+//! sessions on their next request (see `auth::session_user`); disabling an
+//! account or changing its password also ends its sessions.
+
+use crate::acl::validate_rule;
+use crate::audit::AuditFilter;
+use crate::auth::AuthSource;
+use crate::session_store::record_username;
+use crate::tokens::{internal_error, ApiResult};
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use common::auth::{hash_password, PathRule, UserRecord};
+use common::{AuditEvent, CreateUserRequest, UpdateUserRequest, User, UserAccount};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Admin endpoints need an administrator at a browser; API tokens are refused
+/// so a leaked token cannot hand out access.
+fn require_admin(user: &User, source: AuthSource) -> ApiResult<()> {
+    if user.admin && source.is_interactive() {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::FORBIDDEN,
+            "Administrator access required".to_string(),
+        ))
+    }
+}
+
+fn not_found(username: &str) -> (StatusCode, String) {
+    (
+        StatusCode::NOT_FOUND,
+        format!("No such user '{}'", username),
+    )
+}
+
+/// Permissions may only name configured volumes, with mode `r` or `rw`.
+fn validate_permissions(state: &AppState, permissions: &HashMap<String, String>) -> ApiResult<()> {
+    for (volume, mode) in permissions {
+        if !state.volumes.contains_key(volume) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown volume '{}'", volume),
+            ));
+        }
+        if mode != "r" && mode != "rw" {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Invalid mode '{}' for volume '{}'", mode, volume),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_groups(groups: &[String]) -> ApiResult<()> {
+    match groups
+        .iter()
+        .find(|g| g.is_empty() || g.starts_with('@') || g.chars().any(char::is_whitespace))
+    {
+        Some(group) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid group name '{}'", group),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn account(username: &str, record: &UserRecord, sessions: usize) -> UserAccount {
+    UserAccount {
+        username: username.to_string(),
+        permissions: record.permissions.clone(),
+        admin: record.admin,
+        groups: record.groups.clone(),
+        disabled: record.disabled,
+        two_factor: record.totp_enabled(),
+        sessions,
+    }
+}
+
+/// Live sessions per username.
+async fn session_counts(state: &AppState) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    match state.sessions.records().await {
+        Ok(records) => {
+            for record in &records {
+                if let Some(username) = record_username(record) {
+                    *counts.entry(username.to_string()).or_default() += 1;
+                }
+            }
+        }
+        Err(e) => eprintln!("Failed to read sessions: {}", e),
+    }
+    counts
+}
+
+async fn end_sessions(state: &AppState, username: &str) -> ApiResult<usize> {
+    state
+        .sessions
+        .delete_user_sessions(username)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to delete sessions of '{}': {}", username, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to end sessions".to_string(),
+            )
+        })
+}
+
+async fn hash_password_blocking(password: String) -> ApiResult<String> {
+    if password.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Password must not be empty".to_string(),
+        ));
+    }
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(internal_error)
+}
+
+pub async fn list_users(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+) -> ApiResult<Json<Vec<UserAccount>>> {
+    require_admin(&user, source)?;
+    let counts = session_counts(&state).await;
+    let accounts = state
+        .users
+        .with_store(|store| {
+            store
+                .users
+                .iter()
+                .map(|(username, record)| {
+                    account(username, record, counts.get(username).copied().unwrap_or(0))
+                })
+                .collect()
+        })
+        .await;
+    Ok(Json(accounts))
+}
+
+pub async fn create_user(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Json(payload): Json<CreateUserRequest>,
+) -> ApiResult<(StatusCode, Json<UserAccount>)> {
+    require_admin(&user, source)?;
+    let username = payload.username.trim().to_string();
+    if username.is_empty() || username.chars().any(char::is_control) {
+        return Err((StatusCode::BAD_REQUEST, "Invalid username".to_string()));
+    }
+    validate_permissions(&state, &payload.permissions)?;
+    validate_groups(&payload.groups)?;
+
+    let record = UserRecord {
+        password_hash: hash_password_blocking(payload.password).await?,
+        permissions: payload.permissions,
+        api_tokens: Vec::new(),
+        totp: None,
+        admin: payload.admin,
+        groups: payload.groups,
+        disabled: false,
+    };
+
+    let created = account(&username, &record, 0);
+    let inserted = state
+        .users
+        .update(move |store| {
+            if store.users.contains_key(&username) {
+                return false;
+            }
+            store.users.insert(username, record);
+            true
+        })
+        .await
+        .map_err(internal_error)?;
+    if !inserted {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("User '{}' already exists", created.username),
+        ));
+    }
+    Ok((StatusCode::CREATED, Json(created)))
+}
+
+pub async fn update_user(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Path(username): Path<String>,
+    Json(payload): Json<UpdateUserRequest>,
+) -> ApiResult<Json<UserAccount>> {
+    require_admin(&user, source)?;
+    // Keeps at least the caller able to manage accounts.
+    if username == user.username && (payload.admin == Some(false) || payload.disabled == Some(true))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You cannot disable or demote your own account".to_string(),
+        ));
+    }
+    if let Some(permissions) = &payload.permissions {
+        validate_permissions(&state, permissions)?;
+    }
+    if let Some(groups) = &payload.groups {
+        validate_groups(groups)?;
+    }
+    let password_hash = match payload.password {
+        Some(password) => Some(hash_password_blocking(password).await?),
+        None => None,
+    };
+    let end_existing_sessions = payload.disabled == Some(true) || password_hash.is_some();
+
+    let name = username.clone();
+    let updated = state
+        .users
+        .update(move |store| {
+            let record = store.users.get_mut(&name)?;
+            if let Some(permissions) = payload.permissions {
+                record.permissions = permissions;
+            }
+            if let Some(admin) = payload.admin {
+                record.admin = admin;
+            }
+            if let Some(groups) = payload.groups {
+                record.groups = groups;
+            }
+            if let Some(disabled) = payload.disabled {
+                record.disabled = disabled;
+            }
+            if let Some(hash) = password_hash {
+                record.password_hash = hash;
+            }
+            Some(record.clone())
+        })
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| not_found(&username))?;
+
+    let sessions = if end_existing_sessions {
+        end_sessions(&state, &username).await?;
+        0
+    } else {
+        session_counts(&state)
+            .await
+            .get(&username)
+            .copied()
+            .unwrap_or(0)
+    };
+    Ok(Json(account(&username, &updated, sessions)))
+}
+
+/// Ends every session of the user. Their API tokens keep working.
+pub async fn force_logout(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Path(username): Path<String>,
+) -> ApiResult<StatusCode> {
+    require_admin(&user, source)?;
+    if state.users.with_user(&username, |_| ()).await.is_none() {
+        return Err(not_found(&username));
+    }
+    end_sessions(&state, &username).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_path_rules(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+) -> ApiResult<Json<Vec<PathRule>>> {
+    require_admin(&user, source)?;
+    let rules = state
+        .users
+        .with_store(|store| store.path_rules.clone())
+        .await;
+    Ok(Json(rules))
+}
+
+/// Replaces the whole rule list, since the order of rules matters.
+pub async fn set_path_rules(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Json(rules): Json<Vec<PathRule>>,
+) -> ApiResult<Json<Vec<PathRule>>> {
+    require_admin(&user, source)?;
+    let volumes: Vec<&str> = state.volumes.keys().map(String::as_str).collect();
+    for rule in &rules {
+        validate_rule(rule, &volumes).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+    let saved = rules.clone();
+    state
+        .users
+        .update(move |store| store.path_rules = rules)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(saved))
+}
+
+/// The newest audit events matching the query parameters, newest first.
+pub async fn audit_log(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Query(filter): Query<AuditFilter>,
+) -> ApiResult<Json<Vec<AuditEvent>>> {
+    require_admin(&user, source)?;
+    let events = state.audit.query(&filter).await.map_err(internal_error)?;
+    Ok(Json(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::load_user_store;
+    use crate::csrf;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::http::{header, Method};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_admin_manages_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let app = crate::app(state.clone());
+
+        let cookie = login_cookie(&app).await;
+        let (status, _) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/admin/users",
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Promotion applies to the existing session.
+        state
+            .users
+            .update(|store| store.users.get_mut("alice").unwrap().admin = true)
+            .await
+            .unwrap();
+        let (status, body) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/admin/users",
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["username"], "alice");
+        assert_eq!(body[0]["sessions"], 1);
+
+        let bob = r#"{"username":"bob","password":"pw2","permissions":{"personal":"r"}}"#;
+        let (status, _) = post_json(&app, &cookie, "/api/admin/users", bob.to_string()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = post_json(&app, &cookie, "/api/admin/users", bob.to_string()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = post_json(
+            &app,
+            &cookie,
+            "/api/admin/users",
+            r#"{"username":"carol","password":"pw","permissions":{"nope":"r"}}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let bob_cookie = login_as(&app, "bob", "pw2").await;
+        let page = "/api/wiki/personal/index.md";
+        assert_eq!(
+            send(&app, &bob_cookie, Method::PUT, page).await,
+            StatusCode::FORBIDDEN
+        );
+        let (status, _) = post_json(&app, &bob_cookie, "/api/admin/users", bob.to_string()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send_json(
+            &app,
+            &cookie,
+            Method::PUT,
+            "/api/admin/users/bob",
+            r#"{"permissions":{"personal":"rw"}}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["sessions"], 1);
+        assert_eq!(
+            send(&app, &bob_cookie, Method::GET, "/api/tree").await,
+            StatusCode::OK
+        );
+        let req = axum::http::Request::put(page)
+            .header(header::COOKIE, &bob_cookie)
+            .header(csrf::CSRF_HEADER, csrf_token(&bob_cookie))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"path":"index.md","content":"by bob"}"#))
+            .unwrap();
+        assert_eq!(
+            app.clone().oneshot(req).await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        let (status, _) =
+            post_json(&app, &cookie, "/api/admin/users/bob/logout", String::new()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            send(&app, &bob_cookie, Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let bob_cookie = login_as(&app, "bob", "pw2").await;
+        let (status, body) = send_json(
+            &app,
+            &cookie,
+            Method::PUT,
+            "/api/admin/users/bob",
+            r#"{"disabled":true}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["disabled"], true);
+        assert_eq!(
+            send(&app, &bob_cookie, Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+        let req = axum::http::Request::post("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"username":"bob","password":"pw2"}"#))
+            .unwrap();
+        assert_eq!(
+            app.clone().oneshot(req).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let (status, _) = send_json(
+            &app,
+            &cookie,
+            Method::PUT,
+            "/api/admin/users/alice",
+            r#"{"admin":false}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send_json(
+            &app,
+            &cookie,
+            Method::PUT,
+            "/api/admin/users/nobody",
+            "{}".to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Everything was persisted to the users file.
+        let store = load_user_store(&dir.path().join("users.json"), Some("test-secret")).unwrap();
+        assert!(store.users["alice"].admin);
+        assert!(store.users["bob"].disabled);
+        assert_eq!(store.users["bob"].permissions["personal"], "rw");
+    }
+}
@@ -0,0 +1,123 @@
+//! Crash-safe file writes. The new contents go to a temporary file next to
+//! the target, are flushed to disk, and then renamed over the target, so a
+//! reader (or a commit) sees either the old file or the new one, never a
+//! truncated mix.
+
+use axum::http::StatusCode;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes `contents` to `path` atomically, keeping the permissions of the
+/// file it replaces. Writing through a symlink replaces its target.
+pub async fn write(
+    path: impl Into<PathBuf>,
+    contents: impl AsRef<[u8]> + Send + 'static,
+) -> io::Result<()> {
+    let path = path.into();
+    tokio::task::spawn_blocking(move || write_blocking(&path, contents.as_ref()))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// `write` for code that already runs on a blocking thread.
+pub fn write_blocking(path: &Path, contents: &[u8]) -> io::Result<()> {
+    replace(path, |file| file.write_all(contents))
+}
+
+/// Copies the file at `from` to `to` atomically, streaming it rather than
+/// reading it into memory. Call it from blocking code.
+pub fn copy_blocking(from: &Path, to: &Path) -> io::Result<()> {
+    let mut source = File::open(from)?;
+    replace(to, |file| io::copy(&mut source, file).map(|_| ()))
+}
+
+/// Replaces `path` with a file that `fill` writes, by way of a temporary file.
+fn replace(path: &Path, fill: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let path = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_path_buf(),
+    };
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Not a file path",
+        ));
+    };
+    // Hidden, so the tree and search skip it if a crash leaves it behind.
+    let temp = dir.join(format!(
+        ".{}.{:08x}.tmp",
+        name.to_string_lossy(),
+        rand::random::<u32>()
+    ));
+
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        if let Ok(meta) = fs::metadata(&path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        fill(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)?;
+        // Make the rename itself durable. Directories cannot be opened for
+        // syncing everywhere, so failing to is not an error.
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// The response for a failed write: `507 Insufficient Storage` when the disk
+/// or quota is full, `500` otherwise.
+pub fn write_error(e: &io::Error) -> (StatusCode, String) {
+    match e.kind() {
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => (
+            StatusCode::INSUFFICIENT_STORAGE,
+            "Not enough disk space to save the file".to_string(),
+        ),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_replaces_contents_and_keeps_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page.md");
+        write(&path, "first").await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+            write(&path, "second").await.unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        write(&path, "third").await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third");
+
+        // No temporary files are left behind.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_disk_full_is_reported_as_insufficient_storage() {
+        let full = io::Error::from(io::ErrorKind::StorageFull);
+        assert_eq!(write_error(&full).0, StatusCode::INSUFFICIENT_STORAGE);
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(write_error(&denied).0, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,443 @@
+//! Append-only audit log, one JSON object per line.
+//!
+//! Logins are recorded by the auth handlers; every other mutating API request
+//! is recorded by `record_mutations` once its response is known.
+
+use crate::acl::normalize;
+use crate::auth::peer_addr;
+use crate::AppState;
+use axum::{
+    extract::{Path, Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use common::{AuditEvent, User};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+/// Default and maximum number of events returned by a query.
+const QUERY_DEFAULT_LIMIT: usize = 100;
+const QUERY_MAX_LIMIT: usize = 1000;
+
+pub struct AuditLog {
+    path: PathBuf,
+    // Serialises appends so concurrent lines never interleave.
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Uses `AUDIT_LOG`, defaulting to `audit.log` in the working directory.
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("AUDIT_LOG").unwrap_or_else(|_| "audit.log".to_string()))
+    }
+
+    /// Appends an event. Failures are reported on stderr but never fail the
+    /// request that triggered them.
+    pub async fn record(&self, event: AuditEvent) {
+        let mut line = match serde_json::to_string(&event) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to serialise audit event: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        let result = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            // tokio finishes writes in the background unless flushed.
+            file.flush().await
+        }
+        .await;
+        if let Err(e) = result {
+            eprintln!("Failed to write audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// The newest events matching `filter`, newest first.
+    pub async fn query(&self, filter: &AuditFilter) -> std::io::Result<Vec<AuditEvent>> {
+        let limit = filter
+            .limit
+            .unwrap_or(QUERY_DEFAULT_LIMIT)
+            .clamp(1, QUERY_MAX_LIMIT);
+        // Holding the lock means the last line is never half written.
+        let _guard = self.lock.lock().await;
+        let file = match tokio::fs::File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut matches = VecDeque::with_capacity(limit);
+        let mut lines = BufReader::new(file).lines();
+        while let Some(line) = lines.next_line().await? {
+            let Ok(event) = serde_json::from_str::<AuditEvent>(&line) else {
+                continue;
+            };
+            if filter.matches(&event) {
+                if matches.len() == limit {
+                    matches.pop_front();
+                }
+                matches.push_back(event);
+            }
+        }
+        Ok(matches.into_iter().rev().collect())
+    }
+}
+
+/// Query parameters of `GET /api/admin/audit`. Every field is optional.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditFilter {
+    pub user: Option<String>,
+    /// An action such as `page.write`, or a prefix such as `git`.
+    pub action: Option<String>,
+    pub volume: Option<String>,
+    /// A file, or a folder to match everything inside it.
+    pub path: Option<String>,
+    pub outcome: Option<String>,
+    /// Unix seconds, inclusive.
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        let equals =
+            |wanted: &Option<String>, actual: &Option<String>| wanted.is_none() || wanted == actual;
+        let action = self.action.as_deref().is_none_or(|action| {
+            event
+                .action
+                .strip_prefix(action)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        });
+        let path = self.path.as_deref().is_none_or(|wanted| {
+            let wanted = normalize(wanted);
+            event.path.as_deref().is_some_and(|path| {
+                path.strip_prefix(wanted.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+        });
+        equals(&self.user, &event.user)
+            && equals(&self.volume, &event.volume)
+            && equals(&self.outcome, &event.outcome)
+            && action
+            && path
+            && self.since.is_none_or(|since| event.timestamp >= since)
+            && self.until.is_none_or(|until| event.timestamp <= until)
+    }
+}
+
+/// Set as a response extension by handlers that have more to record than the
+/// request path, such as the target of a rename or the id of a commit.
+#[derive(Debug, Clone)]
+pub struct Detail(pub String);
+
+/// Set as a response extension by handlers whose request changed nothing
+/// despite its method, such as a dry run, so that it is not recorded.
+#[derive(Debug, Clone)]
+pub struct Unrecorded;
+
+/// Records every request that is not a plain read, with its outcome.
+pub async fn record_mutations(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    Extension(user): Extension<User>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if req.method().is_safe() {
+        return next.run(req).await;
+    }
+    let ip = state
+        .login_throttle
+        .config()
+        .client_ip(req.headers(), peer_addr(&req));
+    let (action, target) = action_name(req.method(), req.uri().path());
+
+    let mut response = next.run(req).await;
+    if response.extensions_mut().remove::<Unrecorded>().is_some() {
+        return response;
+    }
+    let status = response.status();
+    let mut event = event(&action, Some(&user.username), ip);
+    event.volume = params.get("volume").cloned();
+    event.path = params
+        .get("path")
+        .map(|path| normalize(path))
+        .filter(|path| !path.is_empty());
+    event.detail = response
+        .extensions_mut()
+        .remove::<Detail>()
+        .map(|detail| detail.0)
+        .or(target);
+    event.outcome = Some(outcome(status).to_string());
+    event.status = Some(status.as_u16());
+    state.audit.record(event).await;
+    response
+}
+
+fn outcome(status: StatusCode) -> &'static str {
+    match status {
+        s if s.is_success() || s.is_redirection() => "success",
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => "denied",
+        s if s.is_client_error() => "rejected",
+        _ => "error",
+    }
+}
+
+/// Names the action behind an API path (relative to `/api`), plus the account
+/// it targets for admin requests.
+fn action_name(method: &Method, path: &str) -> (String, Option<String>) {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let target = |name: &str| Some(format!("user {}", name));
+    let action = match (method.as_str(), segments.as_slice()) {
+        ("PUT", ["wiki", ..]) => "page.write".to_string(),
+        ("DELETE", ["wiki", ..]) => "page.delete".to_string(),
+        ("POST", ["rename", ..]) => "page.rename".to_string(),
+        ("POST", ["copy", ..]) => "page.copy".to_string(),
+        ("POST", ["templates", ..]) => "page.create".to_string(),
+        ("POST", ["upload", ..]) => "file.upload".to_string(),
+        ("POST", ["trash", _, _, "restore"]) => "trash.restore".to_string(),
+        ("DELETE", ["trash", _, _]) => "trash.purge".to_string(),
+        ("DELETE", ["trash", _]) => "trash.empty".to_string(),
+        (_, ["git", _, operation]) => format!("git.{}", operation),
+        (_, ["logout"]) => "logout".to_string(),
+        ("DELETE", ["sessions"]) => "session.revoke_others".to_string(),
+        ("DELETE", ["sessions", _]) => "session.revoke".to_string(),
+        ("POST", ["tokens"]) => "token.create".to_string(),
+        ("DELETE", ["tokens", _]) => "token.revoke".to_string(),
+        (_, ["2fa", step]) => format!("2fa.{}", step.replace('-', "_")),
+        ("POST", ["admin", "users"]) => "admin.user.create".to_string(),
+        ("PUT", ["admin", "users", name]) => {
+            return ("admin.user.update".to_string(), target(name))
+        }
+        ("POST", ["admin", "users", name, "logout"]) => {
+            return ("admin.user.logout".to_string(), target(name))
+        }
+        ("PUT", ["admin", "path-rules"]) => "admin.path_rules.update".to_string(),
+        _ => format!("{} /{}", method, segments.join("/")),
+    };
+    (action, None)
+}
+
+/// An event stamped with the current time.
+pub fn event(action: &str, user: Option<&str>, ip: Option<IpAddr>) -> AuditEvent {
+    AuditEvent {
+        timestamp: time::OffsetDateTime::now_utc().unix_timestamp(),
+        action: action.to_string(),
+        user: user.map(str::to_string),
+        ip: ip.map(|ip| ip.to_string()),
+        volume: None,
+        path: None,
+        detail: None,
+        outcome: None,
+        status: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+
+    #[test]
+    fn test_action_names() {
+        for (method, path, action) in [
+            (Method::PUT, "/wiki/personal/a/b.md", "page.write"),
+            (Method::POST, "/rename/personal/a.md", "page.rename"),
+            (Method::POST, "/copy/personal/a.md", "page.copy"),
+            (Method::POST, "/templates/personal/a.md", "page.create"),
+            (Method::POST, "/git/personal/commit", "git.commit"),
+            (
+                Method::POST,
+                "/trash/personal/1-ab/restore",
+                "trash.restore",
+            ),
+            (Method::DELETE, "/trash/personal", "trash.empty"),
+            (Method::POST, "/2fa/recovery-codes", "2fa.recovery_codes"),
+            (Method::POST, "/admin/users/bob/logout", "admin.user.logout"),
+            (Method::PATCH, "/unknown", "PATCH /unknown"),
+        ] {
+            assert_eq!(action_name(&method, path).0, action);
+        }
+        assert_eq!(
+            action_name(&Method::PUT, "/admin/users/bob").1.as_deref(),
+            Some("user bob")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_filters_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = AuditLog::new(dir.path().join("audit.log"));
+        assert!(log.query(&AuditFilter::default()).await.unwrap().is_empty());
+
+        for (i, (action, user, path)) in [
+            ("page.write", "alice", "notes/a.md"),
+            ("page.delete", "bob", "notes"),
+            ("git.commit", "alice", ""),
+            ("page.write", "alice", "notesx.md"),
+            ("page.write", "alice", "notes/b.md"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut event = event(action, Some(user), None);
+            event.timestamp = i as i64;
+            event.volume = Some("personal".to_string());
+            event.path = Some(path.to_string()).filter(|p| !p.is_empty());
+            log.record(event).await;
+        }
+
+        let query = |filter: AuditFilter| {
+            let log = &log;
+            async move {
+                log.query(&filter)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|e| e.timestamp)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(query(AuditFilter::default()).await, vec![4, 3, 2, 1, 0]);
+        let page = || AuditFilter {
+            action: Some("page".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(page()).await, vec![4, 3, 1, 0]);
+        let in_notes = AuditFilter {
+            path: Some("/notes/".to_string()),
+            user: Some("alice".to_string()),
+            ..page()
+        };
+        assert_eq!(query(in_notes).await, vec![4, 0]);
+        let window = AuditFilter {
+            since: Some(1),
+            until: Some(3),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(query(window).await, vec![3, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        git2::Repository::init(dir.path().join("personal")).unwrap();
+        let app = crate::app(state.clone());
+        let cookie = login_cookie(&app).await;
+
+        let (status, _) = send_json(
+            &app,
+            &cookie,
+            Method::PUT,
+            "/api/wiki/personal/notes/a.md",
+            r#"{"path":"notes/a.md","content":"hello"}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            send(&app, &cookie, Method::PUT, "/api/wiki/work/index.md").await,
+            StatusCode::FORBIDDEN
+        );
+        let (status, _) = post_json(
+            &app,
+            &cookie,
+            "/api/rename/personal/notes/a.md",
+            r#"{"new_path":"notes/b.md"}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post_json(
+            &app,
+            &cookie,
+            "/api/git/personal/commit",
+            r#"{"message":"Add notes","files":["notes/b.md"],"author_name":"Someone Else","author_email":"else@example.com"}"#
+                .to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // The committer is the logged-in account, whatever author was sent.
+        let repo = git2::Repository::open(dir.path().join("personal")).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.author().name(), Some("Someone Else"));
+        assert_eq!(head.committer().name(), Some("alice"));
+
+        state
+            .users
+            .update(|store| store.users.get_mut("alice").unwrap().admin = true)
+            .await
+            .unwrap();
+        let (status, events) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/admin/audit?user=alice&action=page",
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let summary: Vec<(&str, &str, &str, &str)> = events
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| {
+                (
+                    e["action"].as_str().unwrap(),
+                    e["volume"].as_str().unwrap(),
+                    e["path"].as_str().unwrap(),
+                    e["outcome"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("page.rename", "personal", "notes/a.md", "success"),
+                ("page.write", "work", "index.md", "denied"),
+                ("page.write", "personal", "notes/a.md", "success"),
+            ]
+        );
+        assert_eq!(events[0]["detail"], "to notes/b.md");
+
+        let (_, events) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/admin/audit?action=git.commit&volume=personal",
+            String::new(),
+        )
+        .await;
+        assert_eq!(events.as_array().unwrap().len(), 1);
+        let detail = events[0]["detail"].as_str().unwrap();
+        assert!(
+            detail.starts_with(&format!("commit {}", head.id())),
+            "{}",
+            detail
+        );
+    }
+}
@@ -1,18 +1,197 @@
-use crate::AppState;
-use axum::Json;
+use crate::acl::PathAcl;
+use crate::sessions::{SessionMeta, SESSION_META_KEY};
+use crate::throttle::FailureOutcome;
+use crate::{atomic, audit, csrf, sessions, two_factor, AppState};
+use axum::extract::Query;
 use axum::{
-    extract::{Path, Request, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
-use common::User;
+use axum::{Extension, Json};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::auth::{decrypt_store, encrypt_store, AuthError, UserRecord, UserStore};
+use common::{AuthMode, LoginResponse, ShareLink, User};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
 use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path as FsPath, PathBuf};
 use std::sync::Arc;
+use std::time::SystemTime;
+use subtle::ConstantTimeEq;
+use tokio::sync::RwLock;
 use tower_sessions::Session;
 
 pub const USER_SESSION_KEY: &str = "user";
 
+/// Reads and decrypts a users file. A missing file is treated as an empty store.
+pub fn load_user_store(path: &FsPath, secret: Option<&str>) -> Result<UserStore, AuthError> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(UserStore::default()),
+        Err(e) => return Err(AuthError::Format(format!("{}: {}", path.display(), e))),
+    };
+    let secret = secret.ok_or_else(|| {
+        AuthError::Format(format!(
+            "{} exists but AUTH_SECRET is not set",
+            path.display()
+        ))
+    })?;
+    decrypt_store(&data, secret)
+}
+
+/// Encrypts and writes a users file. The write is atomic, so a crash or a
+/// full disk leaves the previous file in place rather than a truncated one.
+pub fn save_user_store(path: &FsPath, secret: &str, store: &UserStore) -> Result<(), AuthError> {
+    let data = encrypt_store(store, secret)?;
+    atomic::write_blocking(path, data.as_bytes())
+        .map_err(|e| AuthError::Format(format!("{}: {}", path.display(), e)))
+}
+
+/// The decrypted users file. It is reloaded when the file changes on disk,
+/// so edits made with `wiki-auth` apply without a restart.
+pub struct UserDirectory {
+    pub path: PathBuf,
+    secret: Option<String>,
+    loaded: RwLock<LoadedStore>,
+}
+
+struct LoadedStore {
+    store: UserStore,
+    modified: Option<SystemTime>,
+}
+
+fn file_modified(path: &FsPath) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl UserDirectory {
+    pub fn new(path: PathBuf, secret: Option<String>, store: UserStore) -> Self {
+        let modified = file_modified(&path);
+        Self {
+            path,
+            secret,
+            loaded: RwLock::new(LoadedStore { store, modified }),
+        }
+    }
+
+    /// Loads the store named by `USERS_FILE` (default `users.json`), decrypted with `AUTH_SECRET`.
+    pub fn from_env() -> Result<Self, AuthError> {
+        let path =
+            PathBuf::from(std::env::var("USERS_FILE").unwrap_or_else(|_| "users.json".to_string()));
+        let secret = std::env::var("AUTH_SECRET").ok();
+        let store = load_user_store(&path, secret.as_deref())?;
+        Ok(Self::new(path, secret, store))
+    }
+
+    /// Re-reads the users file if its modification time changed. A file that
+    /// fails to decrypt is logged and the previous contents stay in effect.
+    async fn refresh(&self) {
+        let Some(secret) = self.secret.clone() else {
+            return;
+        };
+        let modified = file_modified(&self.path);
+        if modified.is_none() || self.loaded.read().await.modified == modified {
+            return;
+        }
+
+        let mut loaded = self.loaded.write().await;
+        if loaded.modified == modified {
+            return;
+        }
+        let path = self.path.clone();
+        match tokio::task::spawn_blocking(move || load_user_store(&path, Some(&secret))).await {
+            Ok(Ok(store)) => loaded.store = store,
+            Ok(Err(e)) => eprintln!("Failed to reload {}: {}", self.path.display(), e),
+            Err(e) => eprintln!("Failed to reload {}: {}", self.path.display(), e),
+        }
+        loaded.modified = modified;
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.refresh().await;
+        self.loaded.read().await.store.users.is_empty()
+    }
+
+    pub async fn authenticate(&self, username: &str, password: &str) -> Option<User> {
+        self.refresh().await;
+        let store = self.loaded.read().await.store.clone();
+        let username = username.to_string();
+        let password = password.to_string();
+        // PBKDF2 is deliberately slow, keep it off the async executor.
+        tokio::task::spawn_blocking(move || store.authenticate(&username, &password))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn authenticate_token(&self, token: &str) -> Option<User> {
+        self.refresh().await;
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        self.loaded
+            .read()
+            .await
+            .store
+            .authenticate_token(token, now)
+    }
+
+    /// Runs `f` against the record for `username`, without modifying anything.
+    pub async fn with_user<T>(
+        &self,
+        username: &str,
+        f: impl FnOnce(&UserRecord) -> T,
+    ) -> Option<T> {
+        self.refresh().await;
+        self.loaded.read().await.store.users.get(username).map(f)
+    }
+
+    /// Runs `f` against the whole store, without modifying anything.
+    pub async fn with_store<T>(&self, f: impl FnOnce(&UserStore) -> T) -> T {
+        self.refresh().await;
+        f(&self.loaded.read().await.store)
+    }
+
+    /// The user's volume permissions combined with the path rules that apply to them.
+    pub async fn path_acl(&self, user: &User) -> PathAcl {
+        self.with_store(|store| PathAcl::new(user, &store.path_rules))
+            .await
+    }
+
+    /// Applies `f` to a copy of the store, writes it to disk and only then
+    /// makes it visible to other requests.
+    pub async fn update<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut UserStore) -> T,
+    ) -> Result<T, AuthError> {
+        let secret = self.secret.clone().ok_or_else(|| {
+            AuthError::Format("AUTH_SECRET must be set to modify the users file".to_string())
+        })?;
+        // Pick up edits made with wiki-auth so they are not overwritten.
+        self.refresh().await;
+        let mut loaded = self.loaded.write().await;
+        let mut updated = loaded.store.clone();
+        let result = f(&mut updated);
+        if updated == loaded.store {
+            return Ok(result);
+        }
+
+        let path = self.path.clone();
+        let updated = tokio::task::spawn_blocking(move || {
+            save_user_store(&path, &secret, &updated).map(|_| updated)
+        })
+        .await
+        .map_err(|e| AuthError::Format(e.to_string()))??;
+        loaded.store = updated;
+        loaded.modified = file_modified(&self.path);
+        Ok(result)
+    }
+}
+
 #[derive(Deserialize)]
 pub struct LoginRequest {
     pub username: String,
@@ -20,92 +199,785 @@ pub struct LoginRequest {
     pub stay_signed_in: Option<bool>,
 }
 
+/// Tells the login page which sign-in methods apply to this request.
+pub async fn auth_mode(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Json<AuthMode> {
+    let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr);
+    let proxy_user = proxy_user(&state, &headers, peer)
+        .await
+        .ok()
+        .flatten()
+        .map(|user| user.username);
+    Json(AuthMode {
+        password_login: password_login_enabled(&state),
+        proxy_user,
+    })
+}
+
+fn password_login_enabled(state: &AppState) -> bool {
+    state
+        .proxy
+        .as_ref()
+        .is_none_or(|proxy| proxy.password_login)
+}
+
 pub async fn login(
     State(state): State<Arc<AppState>>,
     session: Session,
+    headers: HeaderMap,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
     Json(payload): Json<LoginRequest>,
-) -> Result<StatusCode, StatusCode> {
+) -> Result<Json<LoginResponse>, Response> {
+    if !password_login_enabled(&state) {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+
+    let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr);
+    let ip = state.login_throttle.config().client_ip(&headers, peer);
+    check_throttle(&state, &payload.username, ip).await?;
+
+    let user = if state.users.is_empty().await {
+        legacy_env_login(&state, &payload).map_err(IntoResponse::into_response)?
+    } else {
+        state
+            .users
+            .authenticate(&payload.username, &payload.password)
+            .await
+    };
+
+    let Some(user) = user else {
+        login_failed(&state, &payload.username, ip, "login.failed").await;
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    };
+    let stay_signed_in = payload.stay_signed_in.unwrap_or(false);
+
+    let totp_enabled = state
+        .users
+        .with_user(&user.username, |record| record.totp_enabled())
+        .await
+        .unwrap_or(false);
+    if totp_enabled {
+        two_factor::begin_pending_login(&session, &user.username, stay_signed_in)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        return Ok(Json(LoginResponse {
+            totp_required: true,
+        }));
+    }
+
+    state.login_throttle.record_success(&user.username);
+    state
+        .audit
+        .record(audit::event("login.success", Some(&user.username), ip))
+        .await;
+    start_session(
+        &session,
+        user,
+        stay_signed_in,
+        SessionMeta::new(&headers, ip),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+    Ok(Json(LoginResponse::default()))
+}
+
+/// Rejects the attempt with `429 Too Many Requests` and a `Retry-After`
+/// header while the username or address is backing off or locked out.
+pub(crate) async fn check_throttle(
+    state: &AppState,
+    username: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), Response> {
+    let Err(retry_after) = state.login_throttle.check(username, ip) else {
+        return Ok(());
+    };
+    state
+        .audit
+        .record(audit::event("login.throttled", Some(username), ip))
+        .await;
+
+    // Round up so clients never retry a moment too early.
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Err((
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.to_string())],
+        "Too many failed login attempts, try again later",
+    )
+        .into_response())
+}
+
+/// Counts a failed password or code and records it in the audit log.
+pub(crate) async fn login_failed(
+    state: &AppState,
+    username: &str,
+    ip: Option<IpAddr>,
+    action: &str,
+) {
+    let outcome = state.login_throttle.record_failure(username, ip);
+    state
+        .audit
+        .record(audit::event(action, Some(username), ip))
+        .await;
+    if let FailureOutcome::LockedOut(duration) = outcome {
+        let mut event = audit::event("login.locked_out", Some(username), ip);
+        event.detail = Some(format!("locked for {}s", duration.as_secs()));
+        state.audit.record(event).await;
+    }
+}
+
+/// Marks the session as logged in once every factor has been checked.
+pub(crate) async fn start_session(
+    session: &Session,
+    user: User,
+    stay_signed_in: bool,
+    meta: SessionMeta,
+) -> Result<(), StatusCode> {
+    // New session id and CSRF token, so nothing planted before login survives it.
+    session
+        .cycle_id()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    csrf::rotate_token(session).await?;
+    session
+        .insert(USER_SESSION_KEY, user)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    session
+        .insert(SESSION_META_KEY, meta)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if stay_signed_in {
+        session.set_expiry(Some(tower_sessions::Expiry::OnInactivity(
+            time::Duration::days(90),
+        )));
+    }
+
+    Ok(())
+}
+
+/// Single-user fallback for deployments without a users file: checks the
+/// `WIKI_USERNAME`/`WIKI_PASSWORD` pair and grants `rw` on every volume.
+fn legacy_env_login(state: &AppState, payload: &LoginRequest) -> Result<Option<User>, StatusCode> {
     let expected_username = std::env::var("WIKI_USERNAME").map_err(|_| {
-        eprintln!("WIKI_USERNAME environment variable is not set!");
+        eprintln!("No users file found and WIKI_USERNAME environment variable is not set!");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
     let expected_password = std::env::var("WIKI_PASSWORD").map_err(|_| {
-        eprintln!("WIKI_PASSWORD environment variable is not set!");
+        eprintln!("No users file found and WIKI_PASSWORD environment variable is not set!");
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    if payload.username == expected_username && payload.password == expected_password {
-        // For single user mode, we grant 'rw' to all configured volumes
-        let mut permissions = std::collections::HashMap::new();
+    let username_ok: bool = payload
+        .username
+        .as_bytes()
+        .ct_eq(expected_username.as_bytes())
+        .into();
+    let password_ok: bool = payload
+        .password
+        .as_bytes()
+        .ct_eq(expected_password.as_bytes())
+        .into();
+    if !(username_ok && password_ok) {
+        return Ok(None);
+    }
+
+    Ok(Some(User {
+        username: payload.username.clone(),
+        permissions: full_access(state),
+        admin: false,
+        groups: Vec::new(),
+    }))
+}
 
-        for volume_name in state.volumes.keys() {
-            permissions.insert(volume_name.clone(), "rw".to_string());
-        }
+fn dev_bypass_enabled() -> bool {
+    std::env::var("DEV_BYPASS_AUTH").unwrap_or_default() == "true"
+}
+
+/// `rw` on every configured volume.
+fn full_access(state: &AppState) -> HashMap<String, String> {
+    state
+        .volumes
+        .keys()
+        .map(|volume_name| (volume_name.clone(), "rw".to_string()))
+        .collect()
+}
+
+/// The logged-in user for this session, if any.
+///
+/// The account is looked up again on every request, so permission changes
+/// apply to existing sessions and disabled or deleted accounts are logged out.
+/// Sessions from the single-user fallback are taken as stored.
+pub async fn session_user(state: &AppState, session: &Session) -> Result<Option<User>, StatusCode> {
+    if dev_bypass_enabled() {
+        return Ok(Some(User {
+            username: "dev".to_string(),
+            permissions: full_access(state),
+            admin: false,
+            groups: Vec::new(),
+        }));
+    }
+
+    let user: Option<User> = session
+        .get(USER_SESSION_KEY)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    if state.users.is_empty().await {
+        return Ok(Some(user));
+    }
+    Ok(state
+        .users
+        .with_user(&user.username, |record| {
+            (!record.disabled).then(|| record.to_user(&user.username))
+        })
+        .await
+        .flatten())
+}
+
+/// The address of the directly connected client, when the server was started
+/// with connect info (see `main`).
+pub fn peer_addr(req: &Request) -> Option<SocketAddr> {
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0)
+}
 
-        let user = User {
-            username: payload.username,
+/// The user named by a trusted proxy header, if proxy auth is configured and
+/// applies to this request. Identities without an account fall back to the
+/// default policy and are refused when there is none.
+async fn proxy_user(
+    state: &AppState,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Result<Option<User>, StatusCode> {
+    let Some(identity) = state
+        .proxy
+        .as_ref()
+        .and_then(|proxy| proxy.identity(headers, peer))
+    else {
+        return Ok(None);
+    };
+    if let Some(user) = state
+        .users
+        .with_user(&identity, |record| {
+            (!record.disabled).then(|| record.to_user(&identity))
+        })
+        .await
+    {
+        return user.map(Some).ok_or(StatusCode::FORBIDDEN);
+    }
+    match state
+        .proxy
+        .as_ref()
+        .and_then(|proxy| proxy.default_permissions.clone())
+    {
+        Some(permissions) => Ok(Some(User {
+            username: identity,
             permissions,
-        };
+            admin: false,
+            groups: Vec::new(),
+        })),
+        None => Err(StatusCode::FORBIDDEN),
+    }
+}
 
-        session
-            .insert(USER_SESSION_KEY, user)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// The caller of a browser request: a trusted proxy identity, else the session user.
+pub async fn interactive_user(
+    state: &AppState,
+    session: &Session,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Result<Option<(User, AuthSource)>, StatusCode> {
+    if let Some(user) = proxy_user(state, headers, peer).await? {
+        return Ok(Some((user, AuthSource::Proxy)));
+    }
+    Ok(session_user(state, session)
+        .await?
+        .map(|user| (user, AuthSource::Session)))
+}
 
-        if payload.stay_signed_in.unwrap_or(false) {
-            session.set_expiry(Some(tower_sessions::Expiry::OnInactivity(
-                time::Duration::days(90),
-            )));
-        }
+/// How the current request was authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthSource {
+    Session,
+    Token,
+    Proxy,
+}
 
-        Ok(StatusCode::OK)
-    } else {
-        Err(StatusCode::UNAUTHORIZED)
+impl AuthSource {
+    /// A person at a browser, as opposed to a script holding a token.
+    pub fn is_interactive(self) -> bool {
+        matches!(self, AuthSource::Session | AuthSource::Proxy)
     }
 }
 
+/// Resolves the caller from an `Authorization: Bearer` token, a trusted proxy
+/// header or the session, and stores the user in the request extensions so that later layers and
+/// handlers can check permissions.
 pub async fn require_auth(
+    State(state): State<Arc<AppState>>,
     session: Session,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+
+    let (user, source) = match bearer {
+        Some(token) => {
+            let user = state
+                .users
+                .authenticate_token(&token)
+                .await
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+            (user, AuthSource::Token)
+        }
+        None => {
+            let peer = peer_addr(&req);
+            let (user, source) = interactive_user(&state, &session, req.headers(), peer)
+                .await?
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+            if source == AuthSource::Session {
+                let ip = state.login_throttle.config().client_ip(req.headers(), peer);
+                sessions::touch(&session, req.headers(), ip).await;
+            }
+            (user, source)
+        }
+    };
+    let acl = state.users.path_acl(&user).await;
+    req.extensions_mut().insert(user);
+    req.extensions_mut().insert(source);
+    req.extensions_mut().insert(acl);
+    Ok(next.run(req).await)
+}
+
+/// Per-volume permission check for every route with a `{volume}` segment:
+/// safe methods need `r`, everything else needs `rw`. Routes that also have a
+/// `{path}` segment are checked against the path rules.
+pub async fn require_volume_access(
+    Path(params): Path<HashMap<String, String>>,
+    Extension(acl): Extension<PathAcl>,
     req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    if std::env::var("DEV_BYPASS_AUTH").unwrap_or_default() == "true" {
-        return Ok(next.run(req).await);
+    if let Some(volume) = params.get("volume") {
+        let path = params.get("path").map_or("", String::as_str);
+        let allowed = if req.method().is_safe() {
+            acl.can_read(volume, path)
+        } else {
+            acl.can_write(volume, path)
+        };
+        if !allowed {
+            return Err(StatusCode::FORBIDDEN);
+        }
     }
 
-    let user: Option<User> = session
-        .get(USER_SESSION_KEY)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(next.run(req).await)
+}
+
+/// Default and maximum lifetime of a share link.
+const SHARE_DEFAULT_SECS: i64 = 24 * 60 * 60;
+const SHARE_MAX_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Signs and verifies time-limited asset URLs.
+///
+/// The key comes from `SHARE_SECRET`, then `AUTH_SECRET`; without either a
+/// random key is used and links stop working when the server restarts.
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts any key");
+        mac.update(b"webwiki share url");
+        Self {
+            key: mac.finalize().into_bytes().to_vec(),
+        }
+    }
 
-    if user.is_some() {
-        Ok(next.run(req).await)
+    pub fn from_env() -> Self {
+        match std::env::var("SHARE_SECRET").or_else(|_| std::env::var("AUTH_SECRET")) {
+            Ok(secret) if !secret.is_empty() => Self::new(secret.as_bytes()),
+            _ => {
+                let mut secret = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut secret);
+                Self::new(&secret)
+            }
+        }
+    }
+
+    fn mac(&self, volume: &str, path: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(&self.key).expect("HMAC accepts any key");
+        mac.update(format!("{}\n{}\n{}", volume, path, expires).as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, volume: &str, path: &str, expires: i64) -> String {
+        URL_SAFE_NO_PAD.encode(self.mac(volume, path, expires).finalize().into_bytes())
+    }
+
+    pub fn verify(&self, volume: &str, path: &str, expires: i64, sig: &str) -> bool {
+        if expires < time::OffsetDateTime::now_utc().unix_timestamp() {
+            return false;
+        }
+        let Ok(sig) = URL_SAFE_NO_PAD.decode(sig) else {
+            return false;
+        };
+        self.mac(volume, path, expires).verify_slice(&sig).is_ok()
+    }
+}
+
+/// Query parameters carried by a signed asset URL.
+#[derive(Deserialize, Default)]
+pub struct ShareParams {
+    pub expires: Option<i64>,
+    pub sig: Option<String>,
+}
+
+/// Allows an asset request that either carries a valid signature or comes
+/// from a logged-in user with read access to the volume.
+pub async fn authorize_asset(
+    state: &AppState,
+    session: &Session,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    volume: &str,
+    path: &str,
+    share: &ShareParams,
+) -> Result<(), StatusCode> {
+    if let (Some(expires), Some(sig)) = (share.expires, share.sig.as_deref()) {
+        return if state.signer.verify(volume, path, expires, sig) {
+            Ok(())
+        } else {
+            Err(StatusCode::FORBIDDEN)
+        };
+    }
+
+    let Some((user, _)) = interactive_user(state, session, headers, peer).await? else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if state.users.path_acl(&user).await.can_read(volume, path) {
+        Ok(())
     } else {
-        Err(StatusCode::UNAUTHORIZED)
+        Err(StatusCode::FORBIDDEN)
     }
 }
 
-pub async fn require_write_access(
-    Path(volume): Path<String>,
-    session: Session,
-    req: Request,
-    next: Next,
-) -> Result<Response, StatusCode> {
-    if std::env::var("DEV_BYPASS_AUTH").unwrap_or_default() == "true" {
-        return Ok(next.run(req).await);
+#[derive(Deserialize)]
+pub struct ShareQuery {
+    pub expires_in: Option<i64>,
+}
+
+/// Mints a signed `/wiki/{volume}/{path}` URL that works without a session.
+pub async fn create_share_link(
+    State(state): State<Arc<AppState>>,
+    Path((volume, path)): Path<(String, String)>,
+    Query(query): Query<ShareQuery>,
+) -> Result<Json<ShareLink>, StatusCode> {
+    if !state.volumes.contains_key(&volume) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if path.contains("..") {
+        return Err(StatusCode::FORBIDDEN);
     }
 
-    let user: User = session
-        .get(USER_SESSION_KEY)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let lifetime = query
+        .expires_in
+        .unwrap_or(SHARE_DEFAULT_SECS)
+        .clamp(1, SHARE_MAX_SECS);
+    let expires_at = time::OffsetDateTime::now_utc().unix_timestamp() + lifetime;
+    let sig = state.signer.sign(&volume, &path, expires_at);
 
-    if let Some(perm) = user.permissions.get(&volume) {
-        if perm.contains('w') {
-            return Ok(next.run(req).await);
+    Ok(Json(ShareLink {
+        url: format!(
+            "/wiki/{}/{}?expires={}&sig={}",
+            encode_path(&volume),
+            encode_path(&path),
+            expires_at,
+            sig
+        ),
+        expires_at,
+    }))
+}
+
+/// Percent-encodes everything except unreserved characters and `/`.
+fn encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
         }
     }
+    out
+}
 
-    Err(StatusCode::FORBIDDEN)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::http::Method;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_login_rejects_bad_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let req = axum::http::Request::post("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"username":"alice","password":"nope"}"#))
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_failures_are_throttled_and_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path());
+        // Lock out on the fourth failure rather than backing off for a second,
+        // which a busy test run can outlast.
+        let mut config = crate::throttle::ThrottleConfig::default();
+        config.max_user_failures = 4;
+        Arc::get_mut(&mut state).unwrap().login_throttle =
+            crate::throttle::LoginThrottle::new(config);
+        let app = crate::app(state);
+        let attempt = |password: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = axum::http::Request::post("/api/login")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(format!(
+                        r#"{{"username":"alice","password":"{}"}}"#,
+                        password
+                    )))
+                    .unwrap();
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        for _ in 0..4 {
+            assert_eq!(attempt("nope").await.status(), StatusCode::UNAUTHORIZED);
+        }
+        // Even the right password is refused until the lockout ends.
+        let resp = attempt("pw").await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = resp.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((890..=900).contains(&retry_after));
+
+        let log = std::fs::read_to_string(dir.path().join("audit.log")).unwrap();
+        let actions: Vec<String> = log
+            .lines()
+            .map(|line| serde_json::from_str::<common::AuditEvent>(line).unwrap())
+            .map(|event| event.action)
+            .collect();
+        assert_eq!(actions.iter().filter(|a| *a == "login.failed").count(), 4);
+        assert!(actions.contains(&"login.locked_out".to_string()));
+        assert_eq!(actions.last().unwrap(), "login.throttled");
+    }
+
+    #[tokio::test]
+    async fn test_volume_permissions_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+
+        assert_eq!(
+            send(&app, "", Method::GET, "/api/wiki/personal/index.md").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let cookie = login_cookie(&app).await;
+        let cases = [
+            (Method::GET, "/api/wiki/personal/index.md", StatusCode::OK),
+            (Method::PUT, "/api/wiki/personal/index.md", StatusCode::OK),
+            (Method::GET, "/api/wiki/work/index.md", StatusCode::OK),
+            (
+                Method::PUT,
+                "/api/wiki/work/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::DELETE,
+                "/api/wiki/work/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::POST,
+                "/api/rename/work/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::POST,
+                "/api/upload/work/a.png",
+                StatusCode::FORBIDDEN,
+            ),
+            (Method::POST, "/api/git/work/commit", StatusCode::FORBIDDEN),
+            (
+                Method::GET,
+                "/api/wiki/secret/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::GET,
+                "/api/tree?volume=secret",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::GET,
+                "/api/search?q=notes&volume=secret",
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+        for (method, uri, expected) in cases {
+            assert_eq!(
+                send(&app, &cookie, method.clone(), uri).await,
+                expected,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("work/index.md")).unwrap(),
+            "work notes"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tree_and_search_only_cover_readable_volumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+
+        let get_json = |uri: &'static str| {
+            let app = app.clone();
+            let cookie = cookie.clone();
+            async move {
+                let req = axum::http::Request::get(uri)
+                    .header(header::COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap();
+                let resp = app.oneshot(req).await.unwrap();
+                let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+            }
+        };
+
+        let volumes: Vec<String> = get_json("/api/tree")
+            .await
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(volumes, vec!["personal", "work"]);
+
+        let results = get_json("/api/search?q=notes").await;
+        let mut searched: Vec<&str> = results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["volume"].as_str().unwrap())
+            .collect();
+        searched.sort();
+        assert_eq!(searched, vec!["personal", "work"]);
+    }
+
+    #[tokio::test]
+    async fn test_assets_require_session_or_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        std::fs::write(dir.path().join("personal/photo.png"), b"png bytes").unwrap();
+        std::fs::write(dir.path().join("secret/photo.png"), b"png bytes").unwrap();
+        let app = crate::app(state);
+
+        let get = |uri: String, cookie: Option<String>| {
+            let app = app.clone();
+            async move {
+                let mut req = axum::http::Request::get(uri);
+                if let Some(cookie) = cookie {
+                    req = req.header(header::COOKIE, cookie);
+                }
+                app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap()
+            }
+        };
+
+        let anonymous = get("/wiki/personal/photo.png".to_string(), None).await;
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+        let cookie = login_cookie(&app).await;
+        let resp = get("/wiki/personal/photo.png".to_string(), Some(cookie.clone())).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = get("/wiki/secret/photo.png".to_string(), Some(cookie.clone())).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let resp = get(
+            "/api/share/personal/photo.png?expires_in=60".to_string(),
+            Some(cookie.clone()),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let link: ShareLink = serde_json::from_slice(&bytes).unwrap();
+
+        let shared = get(link.url.clone(), None).await;
+        assert_eq!(shared.status(), StatusCode::OK);
+        let tampered = link.url.replace("photo.png", "other.png");
+        assert_eq!(get(tampered, None).await.status(), StatusCode::FORBIDDEN);
+
+        let resp = get("/api/share/secret/photo.png".to_string(), Some(cookie)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_user_store_is_replaced_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("users.json");
+        let mut store = UserStore::default();
+        save_user_store(&path, "secret", &store).unwrap();
+
+        store
+            .users
+            .insert("bob".to_string(), UserRecord::new("pw", HashMap::new()));
+        save_user_store(&path, "secret", &store).unwrap();
+        let loaded = load_user_store(&path, Some("secret")).unwrap();
+        assert!(loaded.users.contains_key("bob"));
+        // No temporary file is left next to it.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_url_signer_rejects_expired_links() {
+        let signer = UrlSigner::new(b"secret");
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let sig = signer.sign("personal", "a.png", now + 60);
+        assert!(signer.verify("personal", "a.png", now + 60, &sig));
+        assert!(!signer.verify("work", "a.png", now + 60, &sig));
+
+        let expired = signer.sign("personal", "a.png", now - 1);
+        assert!(!signer.verify("personal", "a.png", now - 1, &expired));
+    }
 }
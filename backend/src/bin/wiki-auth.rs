@@ -0,0 +1,474 @@
+//! User management for the encrypted users file.
+//!
+//! Usage: `wiki-auth <command> <users.json> [args]`, with `AUTH_SECRET` set to
+//! the same secret the server uses.
+
+use backend::auth::{load_user_store, save_user_store};
+use common::auth::{hash_password, parse_permissions, UserRecord, UserStore};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: wiki-auth <command> <users-file> [args]
+
+Commands:
+  add-user <file> [username] [--force]        Add a user (prompts for details); --force
+                                              resets an existing user's password and
+                                              permissions
+  remove-user <file> <username>               Delete a user
+  set-password <file> <username>              Change a user's password
+  set-permissions <file> <username> <perms>   Replace permissions, e.g. personal:rw,work:r
+  set-admin <file> <username> <yes|no>        Grant or revoke access to the admin API
+  set-groups <file> <username> <groups>       Replace groups used by path rules, e.g. hr,finance
+  disable-user <file> <username>              Block a user from logging in
+  enable-user <file> <username>               Allow a disabled user to log in again
+  list-users <file>                           Show users and their permissions
+  reset-2fa <file> <username>                 Remove a user's two-factor authentication
+  rotate-secret <file>                        Re-encrypt with a new AUTH_SECRET
+
+AUTH_SECRET must hold the current secret. rotate-secret reads the new one from
+NEW_AUTH_SECRET or prompts for it.";
+
+type CliResult = Result<(), String>;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> CliResult {
+    let (command, file) = match args {
+        [command, file, ..] => (command.as_str(), PathBuf::from(file)),
+        [flag] if flag == "-h" || flag == "--help" => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+    let rest = &args[2..];
+
+    match command {
+        "add-user" => {
+            let force = rest.iter().any(|a| a == "--force");
+            let username = rest.iter().find(|a| *a != "--force").cloned();
+            add_user(&file, username, force)
+        }
+        "remove-user" => remove_user(&file, arg(rest, 0, "username")?),
+        "set-password" => set_password(&file, arg(rest, 0, "username")?),
+        "set-permissions" => set_permissions(
+            &file,
+            arg(rest, 0, "username")?,
+            arg(rest, 1, "permissions")?,
+        ),
+        "set-admin" => set_admin(&file, arg(rest, 0, "username")?, arg(rest, 1, "yes|no")?),
+        "set-groups" => set_groups(
+            &file,
+            arg(rest, 0, "username")?,
+            rest.get(1).map(String::as_str).unwrap_or(""),
+        ),
+        "disable-user" => set_disabled(&file, arg(rest, 0, "username")?, true),
+        "enable-user" => set_disabled(&file, arg(rest, 0, "username")?, false),
+        "list-users" => list_users(&file),
+        "reset-2fa" => reset_2fa(&file, arg(rest, 0, "username")?),
+        "rotate-secret" => rotate_secret(&file),
+        _ => Err(format!("unknown command '{}'\n\n{}", command, USAGE)),
+    }
+}
+
+fn arg<'a>(rest: &'a [String], index: usize, name: &str) -> Result<&'a str, String> {
+    rest.get(index)
+        .map(String::as_str)
+        .ok_or_else(|| format!("missing <{}>\n\n{}", name, USAGE))
+}
+
+fn secret() -> Result<String, String> {
+    std::env::var("AUTH_SECRET").map_err(|_| "AUTH_SECRET is not set".to_string())
+}
+
+fn load(file: &Path) -> Result<(UserStore, String), String> {
+    let secret = secret()?;
+    let store = load_user_store(file, Some(&secret)).map_err(|e| e.to_string())?;
+    Ok((store, secret))
+}
+
+fn save(file: &Path, secret: &str, store: &UserStore) -> CliResult {
+    save_user_store(file, secret, store).map_err(|e| e.to_string())
+}
+
+fn prompt(label: &str) -> Result<String, String> {
+    print!("{}: ", label);
+    std::io::stdout().flush().map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    Ok(line.trim().to_string())
+}
+
+fn prompt_new_password(label: &str) -> Result<String, String> {
+    let password = rpassword::prompt_password(format!("{}: ", label)).map_err(|e| e.to_string())?;
+    if password.is_empty() {
+        return Err("password must not be empty".to_string());
+    }
+    let confirm = rpassword::prompt_password("Confirm: ").map_err(|e| e.to_string())?;
+    if password != confirm {
+        return Err("passwords do not match".to_string());
+    }
+    Ok(password)
+}
+
+fn permissions_summary(permissions: &HashMap<String, String>) -> String {
+    let mut entries: Vec<String> = permissions
+        .iter()
+        .map(|(volume, mode)| format!("{}:{}", volume, mode))
+        .collect();
+    entries.sort();
+    if entries.is_empty() {
+        "(none)".to_string()
+    } else {
+        entries.join(",")
+    }
+}
+
+/// Adds a user, or with `force` resets an existing user's password and
+/// permissions while keeping their tokens, two-factor setup, groups and flags.
+fn insert_user(
+    store: &mut UserStore,
+    username: &str,
+    password: &str,
+    permissions: HashMap<String, String>,
+    force: bool,
+) -> CliResult {
+    match store.users.get_mut(username) {
+        Some(_) if !force => Err(exists_error(username)),
+        Some(record) => {
+            record.password_hash = hash_password(password);
+            record.permissions = permissions;
+            Ok(())
+        }
+        None => {
+            store
+                .users
+                .insert(username.to_string(), UserRecord::new(password, permissions));
+            Ok(())
+        }
+    }
+}
+
+fn exists_error(username: &str) -> String {
+    format!(
+        "user '{}' already exists; use set-password or set-permissions, or add-user --force",
+        username
+    )
+}
+
+fn remove(store: &mut UserStore, username: &str) -> CliResult {
+    store
+        .users
+        .remove(username)
+        .map(|_| ())
+        .ok_or_else(|| format!("no such user '{}'", username))
+}
+
+fn change_password(store: &mut UserStore, username: &str, password: &str) -> CliResult {
+    let record = store
+        .users
+        .get_mut(username)
+        .ok_or_else(|| format!("no such user '{}'", username))?;
+    record.password_hash = hash_password(password);
+    Ok(())
+}
+
+fn add_user(file: &Path, username: Option<String>, force: bool) -> CliResult {
+    let (mut store, secret) = load(file)?;
+
+    let username = match username {
+        Some(name) => name,
+        None => prompt("Username")?,
+    };
+    if username.is_empty() {
+        return Err("username must not be empty".to_string());
+    }
+    if store.users.contains_key(&username) {
+        if !force {
+            return Err(exists_error(&username));
+        }
+        println!(
+            "User '{}' exists; their password and permissions will be reset.",
+            username
+        );
+    }
+
+    let password = prompt_new_password("Password")?;
+    let permissions = parse_permissions(&prompt("Permissions (e.g. personal:rw,work:r)")?)
+        .map_err(|e| e.to_string())?;
+
+    insert_user(&mut store, &username, &password, permissions, force)?;
+    save(file, &secret, &store)?;
+    println!("Saved user '{}'.", username);
+    Ok(())
+}
+
+fn remove_user(file: &Path, username: &str) -> CliResult {
+    let (mut store, secret) = load(file)?;
+    remove(&mut store, username)?;
+    save(file, &secret, &store)?;
+    println!("Removed user '{}'.", username);
+    Ok(())
+}
+
+fn set_password(file: &Path, username: &str) -> CliResult {
+    let (mut store, secret) = load(file)?;
+    if !store.users.contains_key(username) {
+        return Err(format!("no such user '{}'", username));
+    }
+    let password = prompt_new_password("New password")?;
+    change_password(&mut store, username, &password)?;
+    save(file, &secret, &store)?;
+    println!("Password updated for '{}'.", username);
+    Ok(())
+}
+
+fn set_permissions(file: &Path, username: &str, spec: &str) -> CliResult {
+    let (mut store, secret) = load(file)?;
+    let record = store
+        .users
+        .get_mut(username)
+        .ok_or_else(|| format!("no such user '{}'", username))?;
+    record.permissions = parse_permissions(spec).map_err(|e| e.to_string())?;
+    let summary = permissions_summary(&record.permissions);
+    save(file, &secret, &store)?;
+    println!("Permissions for '{}': {}", username, summary);
+    Ok(())
+}
+
+fn set_admin(file: &Path, username: &str, flag: &str) -> CliResult {
+    let admin = match flag {
+        "yes" => true,
+        "no" => false,
+        _ => return Err(format!("expected 'yes' or 'no', got '{}'", flag)),
+    };
+    let (mut store, secret) = load(file)?;
+    let record = store
+        .users
+        .get_mut(username)
+        .ok_or_else(|| format!("no such user '{}'", username))?;
+    record.admin = admin;
+    save(file, &secret, &store)?;
+    if admin {
+        println!("'{}' is now an administrator.", username);
+    } else {
+        println!("'{}' is no longer an administrator.", username);
+    }
+    Ok(())
+}
+
+fn set_groups(file: &Path, username: &str, spec: &str) -> CliResult {
+    let groups: Vec<String> = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|g| !g.is_empty())
+        .map(|g| g.trim_start_matches('@').to_string())
+        .collect();
+    let (mut store, secret) = load(file)?;
+    let record = store
+        .users
+        .get_mut(username)
+        .ok_or_else(|| format!("no such user '{}'", username))?;
+    let summary = if groups.is_empty() {
+        "(none)".to_string()
+    } else {
+        groups.join(",")
+    };
+    record.groups = groups;
+    save(file, &secret, &store)?;
+    println!("Groups for '{}': {}", username, summary);
+    Ok(())
+}
+
+fn set_disabled(file: &Path, username: &str, disabled: bool) -> CliResult {
+    let (mut store, secret) = load(file)?;
+    let record = store
+        .users
+        .get_mut(username)
+        .ok_or_else(|| format!("no such user '{}'", username))?;
+    record.disabled = disabled;
+    save(file, &secret, &store)?;
+    if disabled {
+        println!(
+            "Disabled '{}'. Their sessions end on the next request.",
+            username
+        );
+    } else {
+        println!("Enabled '{}'.", username);
+    }
+    Ok(())
+}
+
+fn list_users(file: &Path) -> CliResult {
+    let (store, _) = load(file)?;
+    if store.users.is_empty() {
+        println!("No users in {}.", file.display());
+        return Ok(());
+    }
+    for (username, record) in &store.users {
+        let mut flags = String::new();
+        for (set, flag) in [
+            (record.admin, "admin"),
+            (record.totp_enabled(), "2fa"),
+            (record.disabled, "disabled"),
+        ] {
+            if set {
+                flags.push('\t');
+                flags.push_str(flag);
+            }
+        }
+        if !record.groups.is_empty() {
+            flags.push_str("\tgroups=");
+            flags.push_str(&record.groups.join(","));
+        }
+        println!(
+            "{}\t{}{}",
+            username,
+            permissions_summary(&record.permissions),
+            flags
+        );
+    }
+    Ok(())
+}
+
+fn reset_2fa(file: &Path, username: &str) -> CliResult {
+    let (mut store, secret) = load(file)?;
+    let record = store
+        .users
+        .get_mut(username)
+        .ok_or_else(|| format!("no such user '{}'", username))?;
+    if record.totp.take().is_none() {
+        println!("'{}' has no two-factor authentication set up.", username);
+        return Ok(());
+    }
+    save(file, &secret, &store)?;
+    println!("Two-factor authentication removed for '{}'.", username);
+    Ok(())
+}
+
+fn rotate_secret(file: &Path) -> CliResult {
+    if !file.exists() {
+        return Err(format!("{} does not exist", file.display()));
+    }
+    let (store, _) = load(file)?;
+    let new_secret = match std::env::var("NEW_AUTH_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret,
+        _ => prompt_new_password("New AUTH_SECRET")?,
+    };
+    save(file, &new_secret, &store)?;
+    println!(
+        "Re-encrypted {} with the new secret. Update AUTH_SECRET before restarting the server.",
+        file.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::auth::{verify_password, TotpConfig};
+
+    const SECRET: &str = "test-secret";
+
+    fn perms(spec: &str) -> HashMap<String, String> {
+        parse_permissions(spec).unwrap()
+    }
+
+    /// Applies `change` to the store in `file` and saves it, as the commands do.
+    fn edit(file: &Path, change: impl FnOnce(&mut UserStore) -> CliResult) -> CliResult {
+        let mut store = load_user_store(file, Some(SECRET)).unwrap();
+        change(&mut store)?;
+        save(file, SECRET, &store)
+    }
+
+    fn record(file: &Path, username: &str) -> Option<UserRecord> {
+        load_user_store(file, Some(SECRET))
+            .unwrap()
+            .users
+            .remove(username)
+    }
+
+    #[test]
+    fn test_add_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("users.json");
+        edit(&file, |s| {
+            insert_user(s, "alice", "pw", perms("work:rw"), false)
+        })
+        .unwrap();
+        let alice = record(&file, "alice").unwrap();
+        assert!(verify_password("pw", &alice.password_hash));
+        assert_eq!(alice.permissions, perms("work:rw"));
+
+        // An existing user is not replaced without --force.
+        edit(&file, |s| {
+            let alice = s.users.get_mut("alice").unwrap();
+            alice.admin = true;
+            alice.groups = vec!["hr".to_string()];
+            alice.totp = Some(TotpConfig::new("SECRET".to_string()));
+            Ok(())
+        })
+        .unwrap();
+        let refused = edit(&file, |s| {
+            insert_user(s, "alice", "other", perms("work:r"), false)
+        });
+        assert!(refused.unwrap_err().contains("already exists"));
+        let alice = record(&file, "alice").unwrap();
+        assert!(verify_password("pw", &alice.password_hash));
+
+        // --force resets only the password and permissions.
+        edit(&file, |s| {
+            insert_user(s, "alice", "new", perms("work:r"), true)
+        })
+        .unwrap();
+        let alice = record(&file, "alice").unwrap();
+        assert!(verify_password("new", &alice.password_hash));
+        assert_eq!(alice.permissions, perms("work:r"));
+        assert!(alice.admin);
+        assert_eq!(alice.groups, ["hr"]);
+        assert!(alice.totp.is_some());
+    }
+
+    #[test]
+    fn test_set_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("users.json");
+        edit(&file, |s| {
+            insert_user(s, "alice", "pw", perms("work:rw"), false)
+        })
+        .unwrap();
+        edit(&file, |s| change_password(s, "alice", "new")).unwrap();
+        let alice = record(&file, "alice").unwrap();
+        assert!(verify_password("new", &alice.password_hash));
+        assert!(!verify_password("pw", &alice.password_hash));
+        assert_eq!(alice.permissions, perms("work:rw"));
+        assert!(edit(&file, |s| change_password(s, "bob", "pw")).is_err());
+    }
+
+    #[test]
+    fn test_remove_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("users.json");
+        edit(&file, |s| {
+            insert_user(s, "alice", "pw", perms("work:rw"), false)
+        })
+        .unwrap();
+        edit(&file, |s| {
+            insert_user(s, "bob", "pw", perms("work:r"), false)
+        })
+        .unwrap();
+        edit(&file, |s| remove(s, "alice")).unwrap();
+        assert!(record(&file, "alice").is_none());
+        assert!(record(&file, "bob").is_some());
+        assert!(edit(&file, |s| remove(s, "alice")).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Copies of files and folders, within a volume or into another one.
+
+use crate::acl::{normalize, relative_path, PathAcl};
+use crate::atomic::{self, write_error};
+use crate::audit::Detail;
+use crate::links::{self, Move, Page};
+use crate::tokens::ApiResult;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use common::CopyRequest;
+use std::path::Path as FsPath;
+use std::sync::Arc;
+
+fn is_markdown(path: &FsPath) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "md" || ext == "markdown")
+}
+
+/// Copies what `copied` describes from `source_root` to `target_root`, after
+/// checking that the caller may read every source and write every copy.
+/// Hidden files and symlinks are left out. Call it from blocking code.
+fn copy_tree(
+    acl: &PathAcl,
+    source_root: &FsPath,
+    target_root: &FsPath,
+    copied: &Move,
+    rewrite_links: bool,
+) -> ApiResult<usize> {
+    let source = source_root.join(copied.from);
+    let mut entries = Vec::new();
+    for entry in walkdir::WalkDir::new(&source)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+    {
+        let entry = entry.map_err(|e| {
+            eprintln!("Failed to read {}: {}", source.display(), e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read the source".to_string(),
+            )
+        })?;
+        let from = relative_path(source_root, entry.path());
+        let to = format!("{}{}", copied.to, &from[copied.from.len()..]);
+        if !acl.can_read(copied.from_volume, &from) || !acl.can_write(copied.to_volume, &to) {
+            return Err((StatusCode::FORBIDDEN, "Access denied".to_string()));
+        }
+        entries.push((entry, to));
+    }
+
+    let target = target_root.join(copied.to);
+    let copy_all = || -> std::io::Result<usize> {
+        let mut files = 0;
+        for (entry, to) in &entries {
+            let dest = target_root.join(to);
+            if entry.file_type().is_dir() {
+                std::fs::create_dir_all(&dest)?;
+                continue;
+            }
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            if let Some(parent) = dest.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let page = Page {
+                volume: copied.to_volume,
+                path: to,
+            };
+            let rewritten = if rewrite_links && is_markdown(entry.path()) {
+                std::fs::read_to_string(entry.path())
+                    .ok()
+                    .and_then(|content| links::rewrite_links(&content, page, copied))
+            } else {
+                None
+            };
+            match rewritten {
+                Some((text, _)) => atomic::write_blocking(&dest, text.as_bytes())?,
+                None => atomic::copy_blocking(entry.path(), &dest)?,
+            }
+            files += 1;
+        }
+        Ok(files)
+    };
+    copy_all().map_err(|e| {
+        // Leave nothing half-copied behind.
+        let _ = if target.is_dir() {
+            std::fs::remove_dir_all(&target)
+        } else {
+            std::fs::remove_file(&target)
+        };
+        eprintln!("Failed to copy to {}: {}", target.display(), e);
+        write_error(&e)
+    })
+}
+
+/// Copies a file or folder to `new_path`, in the same volume or the one named
+/// in the request.
+pub async fn copy_page(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path((volume, path)): Path<(String, String)>,
+    Json(payload): Json<CopyRequest>,
+) -> ApiResult<(Extension<Detail>, StatusCode)> {
+    let target_volume = payload.volume.unwrap_or_else(|| volume.clone());
+    let source_root = state.volume_root(&volume)?;
+    let target_root = state.volume_root(&target_volume)?;
+    if path.contains("..") || payload.new_path.contains("..") {
+        return Err((StatusCode::FORBIDDEN, "Invalid path".to_string()));
+    }
+    let from = normalize(&path);
+    let to = normalize(&payload.new_path);
+    if from.is_empty() || to.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Invalid path".to_string()));
+    }
+    // The route is not checked for write access, as only the copy is written.
+    if !acl.can_read(&volume, &from) || !acl.can_write(&target_volume, &to) {
+        return Err((StatusCode::FORBIDDEN, "Access denied".to_string()));
+    }
+    if target_volume == volume && to.starts_with(&format!("{}/", from)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Cannot copy a folder into itself".to_string(),
+        ));
+    }
+    if !tokio::fs::try_exists(source_root.join(&from))
+        .await
+        .unwrap_or(false)
+    {
+        return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+    }
+    if tokio::fs::try_exists(target_root.join(&to))
+        .await
+        .unwrap_or(true)
+    {
+        return Err((StatusCode::CONFLICT, format!("'{}' already exists", to)));
+    }
+
+    let detail = Detail(format!("to {}:{}", target_volume, to));
+    tokio::task::spawn_blocking(move || {
+        let copied = Move {
+            from_volume: &volume,
+            from: &from,
+            to_volume: &target_volume,
+            to: &to,
+        };
+        copy_tree(
+            &acl,
+            &source_root,
+            &target_root,
+            &copied,
+            payload.rewrite_links,
+        )
+    })
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Copy failed".to_string()))??;
+    Ok((Extension(detail), StatusCode::CREATED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+
+    #[tokio::test]
+    async fn test_copies_within_and_across_volumes() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let personal = dir.path().join("personal");
+        std::fs::create_dir_all(personal.join("meetings")).unwrap();
+        std::fs::write(personal.join("meetings/10-10.md"), "[[../index]]").unwrap();
+
+        let copy =
+            |uri: &'static str, body: &'static str| post_json(&app, &cookie, uri, body.to_string());
+        let read = |path: &str| std::fs::read_to_string(dir.path().join(path)).unwrap();
+
+        let (status, _) = copy(
+            "/api/copy/personal/meetings/10-10.md",
+            r#"{"new_path":"meetings/10-17.md"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(read("personal/meetings/10-17.md"), "[[../index]]");
+
+        // alice can only read `work`.
+        let (status, _) = copy(
+            "/api/copy/personal/meetings",
+            r#"{"new_path":"meetings","volume":"work"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(!dir.path().join("work/meetings").exists());
+
+        // Copying out of a volume only needs read access to it.
+        let (status, _) = copy(
+            "/api/copy/work/index.md",
+            r#"{"new_path":"work.md","volume":"personal"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(read("personal/work.md"), "work notes");
+        let (status, _) = copy(
+            "/api/copy/secret/index.md",
+            r#"{"new_path":"secret.md","volume":"personal"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(!dir.path().join("personal/secret.md").exists());
+
+        let (status, _) = copy(
+            "/api/copy/personal/meetings",
+            r#"{"new_path":"archive/meetings","rewrite_links":true}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            read("personal/archive/meetings/10-10.md"),
+            "[[../../index]]"
+        );
+        assert_eq!(
+            read("personal/archive/meetings/10-17.md"),
+            "[[../../index]]"
+        );
+
+        for (uri, body, expected) in [
+            (
+                "/api/copy/personal/meetings",
+                r#"{"new_path":"meetings/old"}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/api/copy/personal/meetings/10-10.md",
+                r#"{"new_path":"index.md"}"#,
+                StatusCode::CONFLICT,
+            ),
+            (
+                "/api/copy/personal/missing.md",
+                r#"{"new_path":"new.md"}"#,
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            assert_eq!(copy(uri, body).await.0, expected, "{} {}", uri, body);
+        }
+    }
+}
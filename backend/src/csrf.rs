@@ -0,0 +1,238 @@
+//! CSRF protection for browser-authenticated requests.
+//!
+//! Each session holds a random token, mirrored to the frontend in the
+//! `XSRF-TOKEN` cookie (readable by scripts, unlike the session cookie).
+//! Mutating requests must echo it in the `X-CSRF-Token` header, which a
+//! cross-site form or script cannot do. The `Origin` header is checked as well.
+
+use crate::auth::AuthSource;
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+use tower_sessions::cookie::{Cookie, SameSite};
+use tower_sessions::Session;
+
+pub const CSRF_SESSION_KEY: &str = "csrf_token";
+pub const CSRF_COOKIE: &str = "XSRF-TOKEN";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Whether cookies get the `Secure` attribute (`COOKIE_SECURE=true`). Enable
+/// it whenever the wiki is served over HTTPS, including behind a TLS proxy.
+pub fn cookie_secure() -> bool {
+    std::env::var("COOKIE_SECURE").unwrap_or_default() == "true"
+}
+
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Stores a fresh token in the session. Called at login so a token planted
+/// before authentication cannot be reused.
+pub async fn rotate_token(session: &Session) -> Result<(), StatusCode> {
+    session
+        .insert(CSRF_SESSION_KEY, new_token())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .find(|cookie| cookie.name() == CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+}
+
+/// Accepts requests without an `Origin` (non-browser clients, older browsers)
+/// and requests whose origin matches the `Host` header or `ALLOWED_ORIGINS`.
+fn origin_allowed(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN).and_then(|o| o.to_str().ok()) else {
+        return true;
+    };
+    let allowed = std::env::var("ALLOWED_ORIGINS").unwrap_or_default();
+    if allowed
+        .split(',')
+        .map(str::trim)
+        .filter(|allowed| !allowed.is_empty())
+        .any(|allowed| allowed.trim_end_matches('/') == origin)
+    {
+        return true;
+    }
+
+    let origin_host = origin
+        .split_once("://")
+        .map_or(origin, |(_, host)| host)
+        .trim_end_matches('/');
+    headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .is_some_and(|host| host.eq_ignore_ascii_case(origin_host))
+}
+
+/// Verifies the token on mutating requests from browser sessions and keeps
+/// the `XSRF-TOKEN` cookie in sync with the session. Bearer token requests
+/// are exempt because browsers never attach those automatically.
+pub async fn require_csrf(session: Session, req: Request, next: Next) -> Response {
+    let interactive = req
+        .extensions()
+        .get::<AuthSource>()
+        .is_some_and(|source| source.is_interactive());
+    if !interactive {
+        return next.run(req).await;
+    }
+
+    let expected: Option<String> = match session.get(CSRF_SESSION_KEY).await {
+        Ok(token) => token,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if !req.method().is_safe() {
+        if !origin_allowed(req.headers()) {
+            return (StatusCode::FORBIDDEN, "Cross-origin request rejected").into_response();
+        }
+        let provided = req
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let valid = match (&expected, provided) {
+            (Some(expected), Some(provided)) => {
+                expected.as_bytes().ct_eq(provided.as_bytes()).into()
+            }
+            _ => false,
+        };
+        if !valid {
+            return (StatusCode::FORBIDDEN, "Missing or invalid CSRF token").into_response();
+        }
+    }
+
+    let cookie = cookie_token(req.headers());
+    let token = match expected {
+        Some(token) => token,
+        // Sessions from before CSRF protection, or proxy users, get one lazily.
+        None => {
+            let token = new_token();
+            if session.insert(CSRF_SESSION_KEY, &token).await.is_err() {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            token
+        }
+    };
+
+    let mut response = next.run(req).await;
+    if cookie.as_deref() != Some(token.as_str()) {
+        let cookie = Cookie::build((CSRF_COOKIE, token))
+            .path("/")
+            .same_site(SameSite::Strict)
+            .secure(cookie_secure())
+            .build();
+        if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_origin_allowed() {
+        let headers = |origin: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, "wiki.example.com".parse().unwrap());
+            if let Some(origin) = origin {
+                headers.insert(header::ORIGIN, origin.parse().unwrap());
+            }
+            headers
+        };
+        assert!(origin_allowed(&headers(None)));
+        assert!(origin_allowed(&headers(Some("https://wiki.example.com"))));
+        assert!(!origin_allowed(&headers(Some("https://evil.example.com"))));
+        assert!(!origin_allowed(&headers(Some("null"))));
+    }
+
+    #[tokio::test]
+    async fn test_mutations_need_csrf_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let session = cookie.split("; ").next().unwrap();
+
+        let put = |cookie: String, token: Option<String>, origin: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let mut req = axum::http::Request::put("/api/wiki/personal/index.md")
+                    .header(header::COOKIE, cookie)
+                    .header(header::HOST, "wiki.example.com")
+                    .header(header::CONTENT_TYPE, "application/json");
+                if let Some(token) = token {
+                    req = req.header(CSRF_HEADER, token);
+                }
+                if let Some(origin) = origin {
+                    req = req.header(header::ORIGIN, origin);
+                }
+                let req = req
+                    .body(Body::from(r#"{"path":"index.md","content":"changed"}"#))
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+        let token = csrf_token(&cookie).to_string();
+
+        assert_eq!(put(cookie.clone(), None, None).await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            put(cookie.clone(), Some("forged".to_string()), None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            put(
+                cookie.clone(),
+                Some(token.clone()),
+                Some("https://evil.example.com")
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("personal/index.md")).unwrap(),
+            "personal notes"
+        );
+        assert_eq!(
+            put(
+                session.to_string(),
+                Some(token),
+                Some("https://wiki.example.com")
+            )
+            .await,
+            StatusCode::OK
+        );
+
+        // A second session's token does not work for the first.
+        let other = login_cookie(&app).await;
+        assert_eq!(
+            put(
+                session.to_string(),
+                Some(csrf_token(&other).to_string()),
+                None
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
@@ -1,12 +1,15 @@
+use crate::acl::PathAcl;
+use crate::audit::Detail;
 use crate::AppState;
 use axum::{
     extract::{Path, State},
     http::StatusCode,
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
 use common::{
     CommitRequest, FileStatus, GitStatusResponse, HistoryEntry, HistoryResponse, RestoreRequest,
+    User,
 };
 use git2::{Repository, Status, StatusOptions};
 use std::path::PathBuf;
@@ -41,6 +44,7 @@ pub fn git_routes() -> Router<Arc<AppState>> {
 
 async fn get_status(
     State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
     Path(volume): Path<String>,
 ) -> Result<Json<GitStatusResponse>, StatusCode> {
     let git_state = state.git_states.get(&volume).ok_or(StatusCode::NOT_FOUND)?;
@@ -60,29 +64,12 @@ async fn get_status(
         let mut file_statuses = Vec::new();
         for entry in statuses.iter() {
             let path = entry.path().unwrap_or("").to_string();
-            let status = entry.status();
-
-            let status_str = if status.contains(Status::INDEX_NEW)
-                || status.contains(Status::WT_NEW)
-            {
-                "New"
-            } else if status.contains(Status::INDEX_MODIFIED)
-                || status.contains(Status::WT_MODIFIED)
-            {
-                "Modified"
-            } else if status.contains(Status::INDEX_DELETED) || status.contains(Status::WT_DELETED)
-            {
-                "Deleted"
-            } else if status.contains(Status::INDEX_RENAMED) || status.contains(Status::WT_RENAMED)
-            {
-                "Renamed"
-            } else {
-                "Unknown"
-            };
-
+            if !acl.can_read(&volume, &path) {
+                continue;
+            }
             file_statuses.push(FileStatus {
                 path,
-                status: status_str.to_string(),
+                status: status_label(entry.status()).to_string(),
             });
         }
 
@@ -110,6 +97,21 @@ async fn get_status(
     result
 }
 
+/// How a change is shown to users.
+pub fn status_label(status: Status) -> &'static str {
+    if status.contains(Status::INDEX_NEW) || status.contains(Status::WT_NEW) {
+        "New"
+    } else if status.contains(Status::INDEX_MODIFIED) || status.contains(Status::WT_MODIFIED) {
+        "Modified"
+    } else if status.contains(Status::INDEX_DELETED) || status.contains(Status::WT_DELETED) {
+        "Deleted"
+    } else if status.contains(Status::INDEX_RENAMED) || status.contains(Status::WT_RENAMED) {
+        "Renamed"
+    } else {
+        "Unknown"
+    }
+}
+
 fn calculate_commits_ahead_behind(repo: &Repository) -> Result<(usize, usize), git2::Error> {
     let head = repo.head()?;
     let head_oid = head
@@ -140,6 +142,7 @@ fn calculate_commits_ahead_behind(repo: &Repository) -> Result<(usize, usize), g
 
 async fn fetch_changes(
     State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
     Path(volume): Path<String>,
 ) -> Result<Json<GitStatusResponse>, String> {
     let git_state = state
@@ -203,27 +206,12 @@ async fn fetch_changes(
         let mut file_statuses = Vec::new();
         for entry in statuses.iter() {
             let path = entry.path().unwrap_or("").to_string();
-            let status = entry.status();
-            let status_str = if status.contains(Status::INDEX_NEW)
-                || status.contains(Status::WT_NEW)
-            {
-                "New"
-            } else if status.contains(Status::INDEX_MODIFIED)
-                || status.contains(Status::WT_MODIFIED)
-            {
-                "Modified"
-            } else if status.contains(Status::INDEX_DELETED) || status.contains(Status::WT_DELETED)
-            {
-                "Deleted"
-            } else if status.contains(Status::INDEX_RENAMED) || status.contains(Status::WT_RENAMED)
-            {
-                "Renamed"
-            } else {
-                "Unknown"
-            };
+            if !acl.can_read(&volume, &path) {
+                continue;
+            }
             file_statuses.push(FileStatus {
                 path,
-                status: status_str.to_string(),
+                status: status_label(entry.status()).to_string(),
             });
         }
 
@@ -388,15 +376,33 @@ async fn pull_changes(
     result
 }
 
+/// Commit and restore only touch paths the caller may write.
+fn check_writable(
+    acl: &PathAcl,
+    volume: &str,
+    files: &[String],
+) -> Result<(), (StatusCode, String)> {
+    match files.iter().find(|file| !acl.can_write(volume, file)) {
+        Some(file) => Err((
+            StatusCode::FORBIDDEN,
+            format!("No write access to '{}'", file),
+        )),
+        None => Ok(()),
+    }
+}
+
 async fn commit_changes(
     State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Extension(user): Extension<User>,
     Path(volume): Path<String>,
     Json(payload): Json<CommitRequest>,
-) -> Result<StatusCode, (StatusCode, String)> {
+) -> Result<(Extension<Detail>, StatusCode), (StatusCode, String)> {
     let git_state = state
         .git_states
         .get(&volume)
         .ok_or((StatusCode::NOT_FOUND, "Volume not found".to_string()))?;
+    check_writable(&acl, &volume, &payload.files)?;
     let _lock = git_state.write_lock.lock().await;
     let repo_path = git_state.repo_path.clone();
 
@@ -414,8 +420,9 @@ async fn commit_changes(
             )
         })?;
 
-        for file in payload.files {
-            let path = std::path::Path::new(&file);
+        let file_count = payload.files.len();
+        for file in &payload.files {
+            let path = std::path::Path::new(file);
             index.add_path(path).map_err(|e| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -444,13 +451,20 @@ async fn commit_changes(
             )
         })?;
 
-        let signature =
-            git2::Signature::now(&payload.author_name, &payload.author_email).map_err(|e| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Failed to create signature: {}", e),
-                )
-            })?;
+        let signature_error = |e: git2::Error| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to create signature: {}", e),
+            )
+        };
+        // The author is whatever the client chose to show; the committer is
+        // always the account that made the request, with nothing taken from
+        // the request body. Usernames stand in for the email, which git
+        // requires but accounts do not have.
+        let author = git2::Signature::now(&payload.author_name, &payload.author_email)
+            .map_err(signature_error)?;
+        let committer =
+            git2::Signature::now(&user.username, &user.username).map_err(signature_error)?;
 
         let parent_commit = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
 
@@ -460,22 +474,27 @@ async fn commit_changes(
             vec![]
         };
 
-        repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            &payload.message,
-            &tree,
-            &parents,
-        )
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to commit: {}", e),
+        let commit_id = repo
+            .commit(
+                Some("HEAD"),
+                &author,
+                &committer,
+                &payload.message,
+                &tree,
+                &parents,
             )
-        })?;
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to commit: {}", e),
+                )
+            })?;
 
-        Ok(StatusCode::OK)
+        let detail = format!(
+            "commit {} by {} <{}>, {} file(s)",
+            commit_id, payload.author_name, payload.author_email, file_count
+        );
+        Ok((Extension(Detail(detail)), StatusCode::OK))
     })
     .await
     .map_err(|e| {
@@ -488,8 +507,49 @@ async fn commit_changes(
     result
 }
 
+/// The changed files equal to or inside `paths`, or all changed files when
+/// `paths` is empty. Paths with pathspec magic are refused because they cannot
+/// be checked against path rules.
+fn changed_files_under(
+    repo: &Repository,
+    paths: &[String],
+) -> Result<Vec<String>, (StatusCode, String)> {
+    if let Some(path) = paths
+        .iter()
+        .find(|p| p.contains(['*', '?', '[', '\\']) || p.starts_with([':', '!']))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported characters in '{}'", path),
+        ));
+    }
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true).recurse_untracked_dirs(true);
+    let statuses = repo.statuses(Some(&mut opts)).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to get status: {}", e),
+        )
+    })?;
+    Ok(statuses
+        .iter()
+        .filter_map(|entry| entry.path().map(str::to_string))
+        .filter(|changed| {
+            paths.is_empty()
+                || paths.iter().any(|path| {
+                    let path = path.trim_end_matches('/');
+                    changed == path
+                        || changed
+                            .strip_prefix(path)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                })
+        })
+        .collect())
+}
+
 async fn restore_changes(
     State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
     Path(volume): Path<String>,
     Json(payload): Json<RestoreRequest>,
 ) -> Result<StatusCode, (StatusCode, String)> {
@@ -497,6 +557,7 @@ async fn restore_changes(
         .git_states
         .get(&volume)
         .ok_or((StatusCode::NOT_FOUND, "Volume not found".to_string()))?;
+    check_writable(&acl, &volume, &payload.files)?;
     let _lock = git_state.write_lock.lock().await;
     let repo_path = git_state.repo_path.clone();
 
@@ -511,7 +572,22 @@ async fn restore_changes(
         let mut checkout_builder = git2::build::CheckoutBuilder::new();
         checkout_builder.force(); // Overwrite working directory changes
 
-        for file in &payload.files {
+        // Checkout paths are pathspecs, so a folder or a wildcard can reach
+        // files the path rules protect. Under rules, restore only the changed
+        // files they cover, and only if every one of them is writable.
+        let files = if acl.restricts(&volume) {
+            let files = changed_files_under(&repo, &payload.files)?;
+            if files.is_empty() {
+                // Without paths the checkout would restore everything.
+                return Ok(StatusCode::OK);
+            }
+            files
+        } else {
+            payload.files
+        };
+        check_writable(&acl, &volume, &files)?;
+
+        for file in &files {
             checkout_builder.path(file);
         }
 
@@ -728,3 +804,87 @@ async fn get_history(
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use common::auth::{PathRule, RuleEffect};
+
+    #[tokio::test]
+    async fn test_fetch_lists_only_readable_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let personal = dir.path().join("personal");
+        let origin = dir.path().join("origin.git");
+        git2::Repository::init_bare(&origin).unwrap();
+        let repo = git2::Repository::init(&personal).unwrap();
+        repo.remote("origin", origin.to_str().unwrap()).unwrap();
+        for file in ["hr/salaries.md", "notes/a.md"] {
+            let path = personal.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "changed").unwrap();
+        }
+        state
+            .users
+            .update(|store| {
+                store.path_rules = vec![PathRule {
+                    volume: "personal".to_string(),
+                    pattern: "hr".to_string(),
+                    subjects: vec!["*".to_string()],
+                    effect: RuleEffect::Deny,
+                    access: "rw".to_string(),
+                }]
+            })
+            .await
+            .unwrap();
+        let app = crate::app(state);
+        let cookie = login_cookie(&app).await;
+
+        let (status, body) =
+            post_json(&app, &cookie, "/api/git/personal/fetch", String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        let paths: Vec<&str> = body["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| file["path"].as_str().unwrap())
+            .collect();
+        assert!(paths.iter().any(|path| path.starts_with("notes")));
+        assert!(
+            paths.iter().all(|path| !path.starts_with("hr")),
+            "{:?}",
+            paths
+        );
+    }
+
+    #[tokio::test]
+    async fn test_committer_is_the_logged_in_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let personal = dir.path().join("personal");
+        let repo = git2::Repository::init(&personal).unwrap();
+        std::fs::write(personal.join("a.md"), "hello").unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+
+        let request = serde_json::json!({
+            "message": "Add a",
+            "files": ["a.md"],
+            "author_name": "Mallory",
+            "author_email": "mallory@example.com",
+        });
+        let (status, _) = post_json(
+            &app,
+            &cookie,
+            "/api/git/personal/commit",
+            request.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(commit.author().name(), Some("Mallory"));
+        assert_eq!(commit.author().email(), Some("mallory@example.com"));
+        assert_eq!(commit.committer().name(), Some("alice"));
+        assert_eq!(commit.committer().email(), Some("alice"));
+    }
+}
@@ -1,21 +1,43 @@
+pub mod acl;
+pub mod admin;
+pub mod atomic;
+pub mod audit;
 pub mod auth;
+pub mod copy;
+pub mod csrf;
 pub mod git;
+pub mod links;
+pub mod proxy_auth;
+pub mod serve;
+pub mod session_store;
+pub mod sessions;
+pub mod templates;
+#[cfg(test)]
+mod test_support;
+pub mod throttle;
+pub mod thumbnails;
+pub mod tokens;
+pub mod trash;
+pub mod tree;
+pub mod two_factor;
+pub mod upload;
 
 use axum::extract::Query;
 use axum::{
-    extract::{Path, State},
-    http::{header, StatusCode},
+    extract::{ConnectInfo, DefaultBodyLimit, Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     middleware,
     response::IntoResponse,
     routing::{delete, get, post, put},
-    Json, Router,
+    Extension, Json, Router,
 };
-use common::{FileNode, RenameRequest, WikiPage};
+use common::{content_etag, FileNode, PageConflict, RenameReport, RenameRequest, User, WikiPage};
 use git::{git_routes, GitState};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::{path::PathBuf, sync::Arc};
 use tower_http::services::{ServeDir, ServeFile};
-use tower_sessions::{MemoryStore, SessionManagerLayer};
+use tower_sessions::{Session, SessionManagerLayer};
 
 pub mod search;
 use search::search_wiki;
@@ -34,13 +56,34 @@ pub struct TreeParams {
 pub struct AppState {
     pub volumes: HashMap<String, PathBuf>,
     pub git_states: HashMap<String, Arc<GitState>>,
+    pub users: auth::UserDirectory,
+    pub signer: auth::UrlSigner,
+    pub sessions: session_store::FileSessionStore,
+    /// Set when `AUTH_PROXY_HEADER` enables reverse-proxy SSO.
+    pub proxy: Option<proxy_auth::ProxyAuth>,
+    pub login_throttle: throttle::LoginThrottle,
+    pub audit: audit::AuditLog,
+    pub uploads: upload::UploadConfig,
+    pub thumbnails: thumbnails::ThumbnailCache,
 }
 
-pub fn app(state: Arc<AppState>) -> Router {
-    let session_store = MemoryStore::default();
+impl AppState {
+    /// The folder of a volume, or `404 Not Found` if there is no such volume.
+    pub fn volume_root(&self, volume: &str) -> tokens::ApiResult<PathBuf> {
+        self.volumes
+            .get(volume)
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, "Volume not found".to_string()))
+    }
+}
 
-    let session_layer = SessionManagerLayer::new(session_store)
-        .with_secure(false) // Set to true in production with HTTPS (Cloudflare handles this)
+pub fn app(state: Arc<AppState>) -> Router {
+    let session_layer = SessionManagerLayer::new(state.sessions.clone())
+        .with_secure(csrf::cookie_secure())
+        .with_http_only(true)
+        // Strict keeps the session cookie off every cross-site request; the SPA
+        // only calls the API from its own pages.
+        .with_same_site(tower_sessions::cookie::SameSite::Strict)
         .with_expiry(tower_sessions::Expiry::OnSessionEnd);
 
     // API Router
@@ -50,19 +93,75 @@ pub fn app(state: Arc<AppState>) -> Router {
         .route("/wiki/{volume}/{*path}", put(write_page))
         .route("/wiki/{volume}/{*path}", delete(delete_page))
         .route("/rename/{volume}/{*path}", post(rename_page))
-        .route("/upload/{volume}/{*path}", post(upload_file))
+        .route("/templates/{volume}", get(templates::list_templates))
+        .route(
+            "/templates/{volume}/{*path}",
+            post(templates::create_from_template),
+        )
+        // Uploads are limited per file and per request by `UploadConfig`.
+        .route(
+            "/upload/{volume}/{*path}",
+            post(upload::upload_file).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/me", get(sessions::me))
+        .route(
+            "/sessions",
+            get(sessions::list_sessions).delete(sessions::revoke_other_sessions),
+        )
+        .route("/sessions/{id}", delete(sessions::revoke_session))
+        .route(
+            "/trash/{volume}",
+            get(trash::list_trash).delete(trash::empty_trash),
+        )
+        .route("/trash/{volume}/{id}", delete(trash::purge_item))
+        .route("/trash/{volume}/{id}/restore", post(trash::restore_item))
         .route("/tree", get(get_tree))
+        .route("/list/{volume}", get(tree::list_root))
+        .route("/list/{volume}/{*path}", get(tree::list_directory))
         .route("/search", get(search_handler))
-        .nest(
-            "/git/{volume}",
-            git_routes()
-                .with_state(state.clone())
-                .layer(middleware::from_fn(auth::require_write_access)),
+        .route("/share/{volume}/{*path}", get(auth::create_share_link))
+        .route(
+            "/tokens",
+            get(tokens::list_tokens).post(tokens::create_token),
         )
-        .layer(middleware::from_fn(auth::require_auth));
+        .route("/tokens/{id}", delete(tokens::revoke_token))
+        .route("/2fa", get(two_factor::status))
+        .route("/2fa/enroll", post(two_factor::enroll))
+        .route("/2fa/confirm", post(two_factor::confirm))
+        .route(
+            "/2fa/recovery-codes",
+            post(two_factor::regenerate_recovery_codes),
+        )
+        .route("/2fa/disable", post(two_factor::disable))
+        .route(
+            "/admin/users",
+            get(admin::list_users).post(admin::create_user),
+        )
+        .route("/admin/users/{username}", put(admin::update_user))
+        .route("/admin/users/{username}/logout", post(admin::force_logout))
+        .route(
+            "/admin/path-rules",
+            get(admin::list_path_rules).put(admin::set_path_rules),
+        )
+        .route("/admin/audit", get(admin::audit_log))
+        .nest("/git/{volume}", git_routes().with_state(state.clone()))
+        .route_layer(middleware::from_fn(auth::require_volume_access))
+        // Needs only read access to the source; `copy_page` checks the rest.
+        .route("/copy/{volume}/{*path}", post(copy::copy_page))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            audit::record_mutations,
+        ))
+        .layer(middleware::from_fn(csrf::require_csrf))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ));
 
     let api_router = Router::new()
         .route("/login", post(auth::login))
+        .route("/login/totp", post(two_factor::login_totp))
+        .route("/auth/mode", get(auth::auth_mode))
         .merge(protected_router);
 
     Router::new()
@@ -76,7 +175,12 @@ pub fn app(state: Arc<AppState>) -> Router {
 
 async fn serve_wiki_asset(
     State(state): State<Arc<AppState>>,
+    session: Session,
+    headers: HeaderMap,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
     Path((volume, path)): Path<(String, String)>,
+    Query(share): Query<auth::ShareParams>,
+    Query(variant): Query<thumbnails::VariantParams>,
 ) -> impl IntoResponse {
     let wiki_path = match state.volumes.get(&volume) {
         Some(p) => p,
@@ -93,24 +197,57 @@ async fn serve_wiki_asset(
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
-    if let Ok(meta) = tokio::fs::metadata(&file_path).await {
-        if meta.is_file() {
-            let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
+    let text_extensions = [
+        "", "md", "markdown", "json", "toml", "yaml", "yml", "opml", "dot", "mermaid", "mmd",
+        "drawio", "dio",
+    ];
+    let ext = file_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
 
-            let text_extensions = [
-                "", "md", "markdown", "json", "toml", "yaml", "yml", "opml", "dot", "mermaid",
-                "mmd", "drawio", "dio",
-            ];
-            let ext = file_path
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or("")
-                .to_lowercase();
+    // Raw assets need a session with read access or a signed share URL. This is
+    // checked before touching the filesystem so anonymous requests cannot probe
+    // which files exist.
+    if !text_extensions.contains(&ext.as_str()) {
+        let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr);
+        if let Err(status) =
+            auth::authorize_asset(&state, &session, &headers, peer, &volume, &path, &share).await
+        {
+            return status.into_response();
+        }
 
-            if !text_extensions.contains(&ext.as_str()) {
-                if let Ok(bytes) = tokio::fs::read(&file_path).await {
-                    return ([(header::CONTENT_TYPE, mime.to_string())], bytes).into_response();
+        if let Ok(meta) = tokio::fs::metadata(&file_path).await {
+            if meta.is_file() {
+                if variant.is_requested() && thumbnails::is_resizable(&ext) {
+                    return match state
+                        .thumbnails
+                        .variant(&volume, &path, &file_path, &variant)
+                        .await
+                    {
+                        Ok((mime, variant)) => {
+                            let mut response = match variant {
+                                thumbnails::Variant::Cached(cached) => {
+                                    serve::file_response(&cached, mime, &headers).await
+                                }
+                                thumbnails::Variant::Uncached(bytes) => {
+                                    ([(header::CONTENT_TYPE, mime)], bytes).into_response()
+                                }
+                            };
+                            // Cached by the browser, but checked against the
+                            // `ETag` in case the image changed.
+                            response.headers_mut().insert(
+                                header::CACHE_CONTROL,
+                                HeaderValue::from_static("private, no-cache"),
+                            );
+                            response
+                        }
+                        Err(e) => e.into_response(),
+                    };
                 }
+                let mime = mime_guess::from_path(&file_path).first_or_octet_stream();
+                return serve::file_response(&file_path, mime.as_ref(), &headers).await;
             }
         }
     }
@@ -124,11 +261,17 @@ async fn serve_wiki_asset(
 
 async fn search_handler(
     State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(acl): Extension<acl::PathAcl>,
     Query(params): Query<SearchParams>,
 ) -> impl IntoResponse {
     let mut results = Vec::new();
 
     if let Some(volume_name) = params.volume {
+        if !user.can_read(&volume_name) {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+
         // Search in specific volume
         if let Some(path) = state.volumes.get(&volume_name) {
             {
@@ -140,6 +283,7 @@ async fn search_handler(
                 .await
                 .unwrap_or_default();
 
+                vol_results.retain(|res| acl.can_read(&volume_name, &res.path));
                 for res in &mut vol_results {
                     res.volume = Some(volume_name.clone());
                 }
@@ -148,7 +292,11 @@ async fn search_handler(
         }
     } else {
         // Search in all allowed volumes
-        for (volume_name, path) in &state.volumes {
+        for (volume_name, path) in state
+            .volumes
+            .iter()
+            .filter(|(volume_name, _)| user.can_read(volume_name))
+        {
             {
                 let mut vol_results = tokio::task::spawn_blocking({
                     let path = path.clone();
@@ -158,6 +306,7 @@ async fn search_handler(
                 .await
                 .unwrap_or_default();
 
+                vol_results.retain(|res| acl.can_read(volume_name, &res.path));
                 for res in &mut vol_results {
                     res.volume = Some(volume_name.clone());
                 }
@@ -171,7 +320,9 @@ async fn search_handler(
 
 async fn read_page(
     State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<acl::PathAcl>,
     Path((volume, path)): Path<(String, String)>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     let wiki_path = match state.volumes.get(&volume) {
         Some(p) => p,
@@ -198,6 +349,12 @@ async fn read_page(
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
+    // The requested path was checked by `require_volume_access`; the `.md`
+    // fallback may have resolved to a different file.
+    if !acl.can_read(&volume, &acl::relative_path(wiki_path, &file_path)) {
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
+
     let final_meta = match tokio::fs::metadata(&file_path).await {
         Ok(meta) => meta,
         Err(_) => return (StatusCode::NOT_FOUND, "Page not found").into_response(),
@@ -224,38 +381,62 @@ async fn read_page(
 
     // Determine if we should attempt to serve as WikiPage (text content)
     // 1. Explicit text extension
-    // 2. Small file AND not a known binary type (Image/PDF)
-    let is_image_or_pdf =
-        mime.type_().as_str() == "image" || mime.essence_str() == "application/pdf";
+    // 2. Small file AND not a known binary type (Image/Audio/Video/PDF)
+    let is_media = matches!(mime.type_().as_str(), "image" | "audio" | "video")
+        || mime.essence_str() == "application/pdf";
 
-    let should_try_text = is_explicit_text || (is_small && !is_image_or_pdf);
+    let should_try_text = is_explicit_text || (is_small && !is_media);
 
     if should_try_text {
         match tokio::fs::read(&file_path).await {
             Ok(bytes) => {
                 // Try to convert to UTF-8 string
+                let etag = content_etag(&bytes);
+                if serve::etag_matches(&headers, &etag) {
+                    return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+                }
                 match String::from_utf8(bytes.clone()) {
-                    Ok(content) => Json(WikiPage { path, content }).into_response(),
+                    Ok(content) => {
+                        ([(header::ETAG, etag)], Json(WikiPage { path, content })).into_response()
+                    }
                     Err(_) => {
                         // Not valid UTF-8, fallback to raw bytes
-                        ([(header::CONTENT_TYPE, mime.to_string())], bytes).into_response()
+                        (
+                            [
+                                (header::CONTENT_TYPE, mime.to_string()),
+                                (header::ETAG, etag),
+                            ],
+                            bytes,
+                        )
+                            .into_response()
                     }
                 }
             }
             Err(_) => (StatusCode::NOT_FOUND, "Page not found").into_response(),
         }
     } else {
-        // Binary / Image / PDF / Large Unknown
-        match tokio::fs::read(&file_path).await {
-            Ok(bytes) => ([(header::CONTENT_TYPE, mime.to_string())], bytes).into_response(),
-            Err(_) => (StatusCode::NOT_FOUND, "File not found").into_response(),
-        }
+        // Binary / Media / Large Unknown, streamed
+        serve::file_response(&file_path, mime.as_ref(), &headers).await
     }
 }
 
+/// Whether an `If-Match` header accepts the current version of a file, given
+/// its ETag or `None` if it does not exist. An `If-None-Match` header refuses
+/// exactly the versions this accepts.
+fn if_match_allows(if_match: &header::HeaderValue, current: Option<&str>) -> bool {
+    let Ok(if_match) = if_match.to_str() else {
+        return false;
+    };
+    if_match.split(',').map(str::trim).any(|tag| match tag {
+        "*" => current.is_some(),
+        tag => current == Some(tag),
+    })
+}
+
 async fn write_page(
     State(state): State<Arc<AppState>>,
     Path((volume, path)): Path<(String, String)>,
+    headers: HeaderMap,
     Json(payload): Json<WikiPage>,
 ) -> impl IntoResponse {
     let wiki_path = match state.volumes.get(&volume) {
@@ -274,28 +455,81 @@ async fn write_page(
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
-    // Ensure parent directory exists
+    // Held from the `If-Match` check until the write, so two saves of the same
+    // version cannot both succeed, and a pull cannot slip in between.
+    let git_state = state.git_states.get(&volume).cloned();
+    let _lock = match &git_state {
+        Some(git_state) => Some(git_state.write_lock.lock().await),
+        None => None,
+    };
+
+    // `If-None-Match: *` creates a page only if nobody else has.
+    let if_match = headers.get(header::IF_MATCH);
+    let if_none_match = headers.get(header::IF_NONE_MATCH);
+    if if_match.is_some() || if_none_match.is_some() {
+        let current = tokio::fs::read(&file_path).await.ok();
+        let etag = current.as_deref().map(content_etag);
+        let allowed = if_match.is_none_or(|tags| if_match_allows(tags, etag.as_deref()))
+            && if_none_match.is_none_or(|tags| !if_match_allows(tags, etag.as_deref()));
+        if !allowed {
+            let conflict = PageConflict {
+                path,
+                content: current.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()),
+                etag,
+            };
+            return (StatusCode::CONFLICT, Json(conflict)).into_response();
+        }
+    }
+
     if let Some(parent) = file_path.parent() {
-        if tokio::fs::create_dir_all(parent).await.is_err() {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to create directory",
-            )
-                .into_response();
+        if let Err(e) = tokio::fs::create_dir_all(parent).await {
+            return atomic::write_error(&e).into_response();
         }
     }
 
-    match tokio::fs::write(&file_path, payload.content).await {
-        Ok(_) => (StatusCode::OK, "Saved").into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    let etag = content_etag(payload.content.as_bytes());
+    match atomic::write(file_path, payload.content).await {
+        Ok(_) => (StatusCode::OK, [(header::ETAG, etag)], "Saved").into_response(),
+        Err(e) => atomic::write_error(&e).into_response(),
     }
 }
 
 async fn rename_page(
     State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<acl::PathAcl>,
     Path((volume, path)): Path<(String, String)>,
     Json(payload): Json<RenameRequest>,
 ) -> impl IntoResponse {
+    let detail = audit::Detail(format!("to {}", acl::normalize(&payload.new_path)));
+    let dry_run = payload.dry_run;
+    let mut response = move_page(&state, acl, volume, path, payload).await;
+    if dry_run {
+        response.extensions_mut().insert(audit::Unrecorded);
+    } else {
+        response.extensions_mut().insert(detail);
+    }
+    response
+}
+
+/// Takes the git lock of every volume, in name order so that two callers
+/// cannot deadlock.
+async fn lock_all_volumes(state: &AppState) -> Vec<tokio::sync::OwnedMutexGuard<()>> {
+    let mut names: Vec<&String> = state.git_states.keys().collect();
+    names.sort();
+    let mut locks = Vec::with_capacity(names.len());
+    for name in names {
+        locks.push(state.git_states[name].write_lock.clone().lock_owned().await);
+    }
+    locks
+}
+
+async fn move_page(
+    state: &AppState,
+    acl: acl::PathAcl,
+    volume: String,
+    path: String,
+    payload: RenameRequest,
+) -> axum::response::Response {
     let wiki_path = match state.volumes.get(&volume) {
         Some(p) => p,
         None => return (StatusCode::NOT_FOUND, "Volume not found").into_response(),
@@ -313,6 +547,79 @@ async fn rename_page(
         return (StatusCode::FORBIDDEN, "Access denied").into_response();
     }
 
+    let old = acl::normalize(&path);
+    let new = acl::normalize(&payload.new_path);
+    if old.is_empty() || new.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    }
+    if new.starts_with(&format!("{}/", old)) {
+        return (StatusCode::BAD_REQUEST, "Cannot move a folder into itself").into_response();
+    }
+    if !tokio::fs::try_exists(&old_file_path).await.unwrap_or(false) {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    }
+    if tokio::fs::try_exists(&new_file_path).await.unwrap_or(true) {
+        return (StatusCode::CONFLICT, format!("'{}' already exists", new)).into_response();
+    }
+
+    // Everything moved must be writable both where it is and where it goes.
+    let allowed = tokio::task::spawn_blocking({
+        let wiki_path = wiki_path.clone();
+        let (acl, volume, old, new) = (acl.clone(), volume.clone(), old.clone(), new.clone());
+        move || {
+            acl.can_write_tree(&volume, &wiki_path, &old)
+                && walkdir::WalkDir::new(wiki_path.join(&old))
+                    .into_iter()
+                    .filter_map(|entry| entry.ok())
+                    .all(|entry| {
+                        let moved = acl::relative_path(&wiki_path, entry.path());
+                        let target = match moved.strip_prefix(&old) {
+                            Some(rest) => format!("{}{}", new, rest),
+                            None => new.clone(),
+                        };
+                        acl.can_write(&volume, &target)
+                    })
+                && acl.can_write(&volume, &new)
+        }
+    })
+    .await
+    .unwrap_or(false);
+    if !allowed {
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
+
+    // Held through the move and the link updates, so no save, commit or pull
+    // interleaves with them. The updates may reach any volume.
+    let _locks = if payload.dry_run {
+        Vec::new()
+    } else {
+        lock_all_volumes(state).await
+    };
+
+    // Work out the link updates first so a dry run reports exactly what a
+    // real rename would do. Reads every page, so keep it off the executor.
+    let planned = tokio::task::spawn_blocking({
+        let volumes = state.volumes.clone();
+        let (volume, old, new) = (volume.clone(), old.clone(), new.clone());
+        move || {
+            let moved = links::Move::within(&volume, &old, &new);
+            links::plan_updates(&volumes, &moved, &acl)
+        }
+    })
+    .await;
+    let Ok((planned, mut failed)) = planned else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to check links").into_response();
+    };
+    if payload.dry_run {
+        let updated = planned.into_iter().map(|plan| plan.update).collect();
+        return Json(RenameReport {
+            dry_run: true,
+            updated,
+            failed,
+        })
+        .into_response();
+    }
+
     // Ensure parent directory exists for new path
     if let Some(parent) = new_file_path.parent() {
         if tokio::fs::create_dir_all(parent).await.is_err() {
@@ -324,54 +631,33 @@ async fn rename_page(
         }
     }
 
-    match tokio::fs::rename(&old_file_path, &new_file_path).await {
-        Ok(_) => {
-            // Auto-update links across all markdown files in the volume using spawn_blocking
-            // to avoid blocking the async executor with synchronous I/O.
-            let new_path = payload.new_path.clone();
-            let wiki_path_clone = wiki_path.clone();
-            let _ = tokio::task::spawn_blocking(move || {
-                if let Ok(re) = regex::Regex::new(&format!(
-                    r"\[\[((?:[^:|\]]+:)?)({})(?:\|([^\]]+))?\]\]",
-                    regex::escape(&path)
-                )) {
-                    for entry in walkdir::WalkDir::new(wiki_path_clone)
-                        .into_iter()
-                        .filter_map(|e| e.ok())
-                    {
-                        if entry.file_type().is_file()
-                            && entry.path().extension().is_some_and(|ext| ext == "md")
-                        {
-                            if let Ok(content) = std::fs::read_to_string(entry.path()) {
-                                let result = re.replace_all(&content, |caps: &regex::Captures| {
-                                    let vol = caps.get(1).map_or("", |m| m.as_str());
-                                    let desc = caps.get(3).map_or("", |m| m.as_str());
-
-                                    if desc.is_empty() {
-                                        format!("[[{}{}]]", vol, new_path)
-                                    } else {
-                                        format!("[[{}{}|{}]]", vol, new_path, desc)
-                                    }
-                                });
-
-                                if result != content {
-                                    let _ = std::fs::write(entry.path(), result.as_bytes());
-                                }
-                            }
-                        }
-                    }
-                }
-            })
-            .await;
-
-            (StatusCode::OK, "Renamed").into_response()
-        }
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    if let Err(e) = tokio::fs::rename(&old_file_path, &new_file_path).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    let (updated, apply_failed) =
+        tokio::task::spawn_blocking(move || links::apply_updates(planned))
+            .await
+            .unwrap_or_default();
+    failed.extend(apply_failed);
+    for failure in &failed {
+        eprintln!(
+            "Failed to update links in {}:{}: {}",
+            failure.volume, failure.path, failure.error
+        );
     }
+    Json(RenameReport {
+        dry_run: false,
+        updated,
+        failed,
+    })
+    .into_response()
 }
 
+/// Moves a file or folder into the volume's trash.
 async fn delete_page(
     State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(acl): Extension<acl::PathAcl>,
     Path((volume, path)): Path<(String, String)>,
 ) -> impl IntoResponse {
     let wiki_path = match state.volumes.get(&volume) {
@@ -396,23 +682,39 @@ async fn delete_page(
     };
 
     if meta.is_dir() {
-        match tokio::fs::remove_dir_all(&file_path).await {
-            Ok(_) => (StatusCode::OK, "Deleted").into_response(),
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-        }
-    } else {
-        match tokio::fs::remove_file(&file_path).await {
-            Ok(_) => (StatusCode::OK, "Deleted").into_response(),
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        // Deleting a folder must not take protected files inside it along.
+        let allowed = tokio::task::spawn_blocking({
+            let (wiki_path, path) = (wiki_path.clone(), path.clone());
+            move || acl.can_write_tree(&volume, &wiki_path, &path)
+        })
+        .await
+        .unwrap_or(false);
+        if !allowed {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
         }
     }
+
+    match trash::move_to_trash(wiki_path, &path, meta.is_dir(), &user.username).await {
+        Ok(item) => (
+            Extension(audit::Detail(format!("to trash {}", item.id))),
+            (StatusCode::OK, "Moved to trash"),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
 async fn get_tree(
     State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(acl): Extension<acl::PathAcl>,
     Query(params): Query<TreeParams>,
 ) -> impl IntoResponse {
     if let Some(volume) = params.volume {
+        if !user.can_read(&volume) {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+
         // Return tree for specific volume
         let wiki_path = match state.volumes.get(&volume) {
             Some(p) => p,
@@ -421,20 +723,23 @@ async fn get_tree(
 
         let wiki_path_clone = wiki_path.clone();
         let tree = tokio::task::spawn_blocking(move || {
-            build_file_tree(&wiki_path_clone, &wiki_path_clone)
+            build_file_tree(&wiki_path_clone, &wiki_path_clone, &|path| {
+                acl.can_read(&volume, path)
+            })
         })
         .await
         .unwrap_or_default();
         Json(tree).into_response()
     } else {
-        // Return list of volumes as directories
+        // Return list of readable volumes as directories
         let mut nodes = Vec::new();
-        for volume_name in state.volumes.keys() {
+        for volume_name in state.volumes.keys().filter(|v| user.can_read(v)) {
             nodes.push(FileNode {
                 name: volume_name.clone(),
                 path: volume_name.clone(), // Path is just the volume name
                 is_dir: true,
                 children: None, // Frontend can fetch children when expanded
+                ..Default::default()
             });
         }
         // Sort volumes alphabetically
@@ -443,7 +748,13 @@ async fn get_tree(
     }
 }
 
-fn build_file_tree(root: &PathBuf, current: &PathBuf) -> Vec<FileNode> {
+/// Lists `current` recursively, leaving out hidden entries and those
+/// `visible` rejects (given the path relative to `root`).
+fn build_file_tree(
+    root: &PathBuf,
+    current: &PathBuf,
+    visible: &dyn Fn(&str) -> bool,
+) -> Vec<FileNode> {
     let mut nodes = Vec::new();
 
     if let Ok(entries) = std::fs::read_dir(current) {
@@ -465,6 +776,9 @@ fn build_file_tree(root: &PathBuf, current: &PathBuf) -> Vec<FileNode> {
                 .unwrap_or(&path)
                 .to_string_lossy()
                 .to_string();
+            if !visible(&relative_path) {
+                continue;
+            }
             let name = path
                 .file_name()
                 .unwrap_or_default()
@@ -473,7 +787,7 @@ fn build_file_tree(root: &PathBuf, current: &PathBuf) -> Vec<FileNode> {
             let is_dir = path.is_dir();
 
             let children = if is_dir {
-                Some(build_file_tree(root, &path))
+                Some(build_file_tree(root, &path, visible))
             } else {
                 None
             };
@@ -483,6 +797,7 @@ fn build_file_tree(root: &PathBuf, current: &PathBuf) -> Vec<FileNode> {
                 path: relative_path,
                 is_dir,
                 children,
+                ..Default::default()
             });
         }
     }
@@ -502,40 +817,141 @@ async fn logout_handler(session: tower_sessions::Session) -> impl IntoResponse {
     StatusCode::OK
 }
 
-async fn upload_file(
-    State(state): State<Arc<AppState>>,
-    Path((volume, path)): Path<(String, String)>,
-    body: axum::body::Bytes,
-) -> impl IntoResponse {
-    let wiki_path = match state.volumes.get(&volume) {
-        Some(p) => p,
-        None => return (StatusCode::NOT_FOUND, "Volume not found").into_response(),
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_page_saves_check_if_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let uri = "/api/wiki/personal/index.md";
+        let save = |if_match: Option<&str>, content: &str| {
+            let mut req = axum::http::Request::put(uri)
+                .header(header::COOKIE, &cookie)
+                .header(csrf::CSRF_HEADER, csrf_token(&cookie))
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(if_match) = if_match {
+                req = req.header(header::IF_MATCH, if_match);
+            }
+            let body = serde_json::json!({ "path": "index.md", "content": content });
+            app.clone()
+                .oneshot(req.body(Body::from(body.to_string())).unwrap())
+        };
 
-    if path.contains("..") {
-        return (StatusCode::FORBIDDEN, "Invalid path").into_response();
+        let req = axum::http::Request::get(uri)
+            .header(header::COOKIE, &cookie)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let original = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(original, common::content_etag(b"personal notes"));
+
+        // The first save of that version wins and returns the new version.
+        let resp = save(Some(&original), "first").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()[header::ETAG],
+            common::content_etag(b"first").as_str()
+        );
+
+        // A second save based on the old version is refused with the current page.
+        let resp = save(Some(&original), "second").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let conflict: common::PageConflict = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(conflict.content.as_deref(), Some("first"));
+        assert_eq!(conflict.etag, Some(common::content_etag(b"first")));
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("personal/index.md")).unwrap(),
+            "first"
+        );
+
+        let both = format!("{}, {}", original, conflict.etag.unwrap());
+        assert_eq!(
+            save(Some(&both), "second").await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            save(Some("*"), "third").await.unwrap().status(),
+            StatusCode::OK
+        );
+        // Without If-Match the save is unconditional, as before.
+        assert_eq!(save(None, "fourth").await.unwrap().status(), StatusCode::OK);
+
+        std::fs::remove_file(dir.path().join("personal/index.md")).unwrap();
+        let resp = save(Some("*"), "fifth").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
     }
 
-    let file_path = wiki_path.join(&path);
+    #[tokio::test]
+    async fn test_new_pages_are_not_created_twice() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let save = |path: &str, if_match: Option<&str>, content: &str| {
+            let mut req = axum::http::Request::put(format!("/api/wiki/personal/{}", path))
+                .header(header::COOKIE, &cookie)
+                .header(csrf::CSRF_HEADER, csrf_token(&cookie))
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::IF_NONE_MATCH, "*");
+            if let Some(if_match) = if_match {
+                req = req.header(header::IF_MATCH, if_match);
+            }
+            let body = serde_json::json!({ "path": path, "content": content });
+            app.clone()
+                .oneshot(req.body(Body::from(body.to_string())).unwrap())
+        };
 
-    // Safety check
-    if !file_path.starts_with(wiki_path) {
-        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        let resp = save("new/page.md", None, "mine").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = save("new/page.md", None, "theirs").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let page = dir.path().join("personal/new/page.md");
+        assert_eq!(std::fs::read_to_string(&page).unwrap(), "mine");
+
+        // A refused save leaves no folders behind.
+        let stale = common::content_etag(b"old");
+        let resp = save("other/page.md", Some(&stale), "x").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert!(!dir.path().join("personal/other").exists());
     }
 
-    // Ensure parent directory exists
-    if let Some(parent) = file_path.parent() {
-        if tokio::fs::create_dir_all(parent).await.is_err() {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to create directory",
+    #[tokio::test]
+    async fn test_renames_wait_for_git_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let app = crate::app(state.clone());
+        let cookie = login_cookie(&app).await;
+        std::fs::write(dir.path().join("personal/a.md"), "a").unwrap();
+
+        // A commit in another volume may be writing pages the rename links to.
+        let lock = state.git_states["work"]
+            .write_lock
+            .clone()
+            .lock_owned()
+            .await;
+        let rename = tokio::spawn(async move {
+            post_json(
+                &app,
+                &cookie,
+                "/api/rename/personal/a.md",
+                r#"{"new_path":"b.md"}"#.to_string(),
             )
-                .into_response();
-        }
-    }
-
-    match tokio::fs::write(&file_path, body).await {
-        Ok(_) => (StatusCode::OK, "Saved").into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!rename.is_finished());
+        assert!(dir.path().join("personal/a.md").exists());
+
+        drop(lock);
+        assert_eq!(rename.await.unwrap().0, StatusCode::OK);
+        assert!(dir.path().join("personal/b.md").exists());
     }
 }
@@ -0,0 +1,673 @@
+//! Keeps links between pages pointing at the right place when files move or
+//! are copied.
+//!
+//! Links are resolved the way the frontend's `WikiLinkParser` resolves them:
+//! `[[wikilinks]]`, Markdown links and images, and reference definitions,
+//! each of which may be relative to the page, absolute (`/path`), prefixed
+//! with another volume (`work:path`), missing the `.md` extension, or carry
+//! an `#anchor`. A rewritten link keeps its form where it can. Text in code
+//! spans and code blocks is not a link and is never rewritten.
+
+use crate::acl::{relative_path, PathAcl};
+use crate::atomic;
+use common::{LinkEdit, LinkUpdate, LinkUpdateFailure};
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+static WIKILINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\[(.*?)\]\]").unwrap());
+/// The destination of an inline link or image, `[text](dest "title")`.
+static INLINE_DEST: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]\((\s*)(<[^>\n]*>|[^\s)]+)").unwrap());
+/// The destination of a reference definition, `[id]: dest`.
+static REFERENCE_DEST: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^( {0,3}\[[^\]\n]+\]:[ \t]*)(<[^>\n]*>|\S+)").unwrap());
+
+/// A page, by volume and path relative to the volume root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page<'a> {
+    pub volume: &'a str,
+    pub path: &'a str,
+}
+
+/// A file or folder that moved, or was copied, to a new place, possibly in
+/// another volume. Paths are normalised.
+#[derive(Debug, Clone, Copy)]
+pub struct Move<'a> {
+    pub from_volume: &'a str,
+    pub from: &'a str,
+    pub to_volume: &'a str,
+    pub to: &'a str,
+}
+
+impl<'a> Move<'a> {
+    /// A move within one volume.
+    pub const fn within(volume: &'a str, from: &'a str, to: &'a str) -> Self {
+        Self {
+            from_volume: volume,
+            from,
+            to_volume: volume,
+            to,
+        }
+    }
+
+    /// Where a link target is after the move, or `None` if it did not move.
+    /// A link that left out `.md` still leaves it out.
+    fn apply(&self, volume: &str, path: &str) -> Option<(String, String)> {
+        if volume != self.from_volume {
+            return None;
+        }
+        let path = if path == self.from {
+            self.to.to_string()
+        } else if let Some(rest) = inside(path, self.from) {
+            format!("{}{}", self.to, rest)
+        } else if self.from.strip_suffix(".md") == Some(path) {
+            self.to.strip_suffix(".md").unwrap_or(self.to).to_string()
+        } else {
+            return None;
+        };
+        Some((self.to_volume.to_string(), path))
+    }
+
+    /// Where `path` in `volume` will be after a move within that volume.
+    fn destination(&self, volume: &str, path: &str) -> String {
+        if volume == self.from_volume && volume == self.to_volume {
+            if path == self.from {
+                return self.to.to_string();
+            }
+            if let Some(rest) = inside(path, self.from) {
+                return format!("{}{}", self.to, rest);
+            }
+        }
+        path.to_string()
+    }
+
+    /// Where a page that is now at `page` was before the move.
+    fn origin(&self, page: Page) -> (String, String) {
+        if page.volume == self.to_volume {
+            if page.path == self.to {
+                return (self.from_volume.to_string(), self.from.to_string());
+            }
+            if let Some(rest) = inside(page.path, self.to) {
+                return (
+                    self.from_volume.to_string(),
+                    format!("{}{}", self.from, rest),
+                );
+            }
+        }
+        (page.volume.to_string(), page.path.to_string())
+    }
+}
+
+/// The part of `path` after the folder `dir`, starting with `/`.
+fn inside<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    path.strip_prefix(dir).filter(|rest| rest.starts_with('/'))
+}
+
+fn dir_of(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// Appends `link` to the folder `base`, following `.` and `..` but never
+/// above the volume root.
+fn join(base: &str, link: &str) -> String {
+    let mut parts: Vec<&str> = base.split('/').filter(|p| !p.is_empty()).collect();
+    for part in link.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// `target` written relative to the folder `from`.
+fn relative_to(from: &str, target: &str) -> String {
+    let from: Vec<&str> = from.split('/').filter(|p| !p.is_empty()).collect();
+    let to: Vec<&str> = target.split('/').filter(|p| !p.is_empty()).collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts = vec![".."; from.len() - common];
+    parts.extend(&to[common..]);
+    if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    }
+}
+
+/// Splits `volume:path`; any colon separates a volume, as in the frontend.
+fn split_volume(link: &str) -> (Option<&str>, &str) {
+    match link.split_once(':') {
+        Some((volume, path)) => (Some(volume), path),
+        None => (None, link),
+    }
+}
+
+/// The volume and path a link on `page` points at. Paths in the page's own
+/// volume are relative to the page unless they start with `/`; paths in
+/// other volumes are relative to that volume's root.
+fn resolve(page: Page, link: &str) -> (String, String) {
+    let (prefix, path) = split_volume(link);
+    let volume = prefix.unwrap_or(page.volume);
+    let base = if !path.starts_with('/') && volume == page.volume {
+        dir_of(page.path)
+    } else {
+        ""
+    };
+    (volume.to_string(), join(base, path))
+}
+
+/// The link as it should now be written on `page`, which was at `origin`
+/// before the move, or `None` if it still points where it did.
+fn rewrite_target(link: &str, origin: Page, page: Page, moved: &Move) -> Option<String> {
+    let (target, fragment) = link.split_at(link.find('#').unwrap_or(link.len()));
+    if target.is_empty()
+        || ["http://", "https://", "mailto:"]
+            .iter()
+            .any(|s| target.starts_with(s))
+    {
+        return None;
+    }
+    let (volume, old_path) = resolve(origin, target);
+    let wanted = moved
+        .apply(&volume, &old_path)
+        .unwrap_or_else(|| (volume.clone(), old_path));
+    if resolve(page, target) == wanted {
+        return None;
+    }
+
+    let (wanted_volume, wanted_path) = wanted;
+    let (prefix, path) = split_volume(target);
+    let was_relative = !path.starts_with('/') && volume == origin.volume;
+    let path = if wanted_volume != page.volume {
+        match path.starts_with('/') {
+            true => format!("/{}", wanted_path),
+            false => wanted_path,
+        }
+    } else if was_relative {
+        relative_to(dir_of(page.path), &wanted_path)
+    } else {
+        format!("/{}", wanted_path)
+    };
+    // Links into another volume need its name, including links that only
+    // became cross-volume because the page itself changed volume.
+    Some(if prefix.is_some() || wanted_volume != page.volume {
+        format!("{}:{}{}", wanted_volume, path, fragment)
+    } else {
+        format!("{}{}", path, fragment)
+    })
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).unwrap_or_else(|_| text.to_string())
+}
+
+/// Like `rewrite_target` for a Markdown destination, which may be wrapped in
+/// `<>` or percent-encoded.
+fn rewrite_dest(dest: &str, origin: Page, page: Page, moved: &Move) -> Option<String> {
+    if let Some(inner) = dest.strip_prefix('<').and_then(|d| d.strip_suffix('>')) {
+        return rewrite_target(inner, origin, page, moved).map(|new| format!("<{}>", new));
+    }
+    rewrite_target(&percent_decode(dest), origin, page, moved).map(|new| new.replace(' ', "%20"))
+}
+
+/// The byte ranges of code spans and code blocks in `text`, in order.
+fn code_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    let mut ranges = Vec::new();
+    let mut block_start = None;
+    for (event, range) in Parser::new_ext(text, options).into_offset_iter() {
+        match event {
+            Event::Code(_) => ranges.push(range),
+            Event::Start(Tag::CodeBlock(_)) => block_start = Some(range.start),
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some(start) = block_start.take() {
+                    ranges.push(start..range.end);
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// Replaces the matches of `regex` in `text` that do not start in code.
+fn replace_outside_code(
+    regex: &Regex,
+    text: &str,
+    mut replace: impl FnMut(&Captures) -> String,
+) -> String {
+    let code = code_ranges(text);
+    regex
+        .replace_all(text, |caps: &Captures| {
+            let start = caps.get(0).map_or(0, |m| m.start());
+            if code.iter().any(|range| range.contains(&start)) {
+                caps[0].to_string()
+            } else {
+                replace(caps)
+            }
+        })
+        .into_owned()
+}
+
+/// Rewrites the links in the text of `page` (where it is after the move,
+/// which may be inside what moved) for `moved`. Returns the new text and the
+/// links changed, or `None` if none did.
+pub fn rewrite_links(content: &str, page: Page, moved: &Move) -> Option<(String, Vec<LinkEdit>)> {
+    let (origin_volume, origin_path) = moved.origin(page);
+    let origin = Page {
+        volume: &origin_volume,
+        path: &origin_path,
+    };
+    let mut edits = Vec::new();
+    let mut edit = |before: &str, after: String| {
+        edits.push(LinkEdit {
+            before: before.to_string(),
+            after: after.clone(),
+        });
+        after
+    };
+
+    let text = replace_outside_code(&WIKILINK, content, |caps| {
+        let inner = &caps[1];
+        let (target, label) = inner.split_at(inner.find('|').unwrap_or(inner.len()));
+        match rewrite_target(target.trim(), origin, page, moved) {
+            Some(new) => edit(&caps[0], format!("[[{}{}]]", new, label)),
+            None => caps[0].to_string(),
+        }
+    });
+    let text = replace_outside_code(&INLINE_DEST, &text, |caps| {
+        match rewrite_dest(&caps[2], origin, page, moved) {
+            Some(new) => format!("]({}{}", &caps[1], edit(&caps[2], new)),
+            None => caps[0].to_string(),
+        }
+    });
+    let text = replace_outside_code(&REFERENCE_DEST, &text, |caps| {
+        match rewrite_dest(&caps[2], origin, page, moved) {
+            Some(new) => format!("{}{}", &caps[1], edit(&caps[2], new)),
+            None => caps[0].to_string(),
+        }
+    });
+    (!edits.is_empty()).then_some((text, edits))
+}
+
+/// A page whose links a move will change, worked out before the move.
+pub struct PlannedUpdate {
+    /// Where the page will be on disk after the move.
+    file: PathBuf,
+    original: String,
+    text: String,
+    pub update: LinkUpdate,
+}
+
+/// Works out the link updates `moved` needs in every Markdown page of every
+/// volume, before it happens on disk. Pages `acl` lets the user read but not
+/// write are reported as failed; pages they cannot read are left out, as
+/// even their names are hidden. Reads every page, so call it from blocking
+/// code.
+pub fn plan_updates(
+    volumes: &HashMap<String, PathBuf>,
+    moved: &Move,
+    acl: &PathAcl,
+) -> (Vec<PlannedUpdate>, Vec<LinkUpdateFailure>) {
+    let mut planned = Vec::new();
+    let mut failed = Vec::new();
+    for (volume, root) in volumes {
+        let pages = walkdir::WalkDir::new(root)
+            .into_iter()
+            // Hidden folders such as `.git` and `.trash` are not part of the wiki.
+            .filter_entry(|entry| {
+                entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+            })
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                entry.file_type().is_file()
+                    && entry
+                        .path()
+                        .extension()
+                        .is_some_and(|ext| ext == "md" || ext == "markdown")
+            });
+        for entry in pages {
+            let path = moved.destination(volume, &relative_path(root, entry.path()));
+            if !acl.can_read(volume, &path) {
+                continue;
+            }
+            let original = match std::fs::read_to_string(entry.path()) {
+                Ok(content) => content,
+                Err(e) => {
+                    failed.push(LinkUpdateFailure {
+                        volume: volume.clone(),
+                        path,
+                        error: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+            let page = Page {
+                volume,
+                path: &path,
+            };
+            let Some((text, edits)) = rewrite_links(&original, page, moved) else {
+                continue;
+            };
+            if !acl.can_write(volume, &path) {
+                failed.push(LinkUpdateFailure {
+                    volume: volume.clone(),
+                    path,
+                    error: "you cannot edit this page".to_string(),
+                });
+                continue;
+            }
+            planned.push(PlannedUpdate {
+                file: root.join(&path),
+                original,
+                text,
+                update: LinkUpdate {
+                    volume: volume.clone(),
+                    path,
+                    edits,
+                },
+            });
+        }
+    }
+    (planned, failed)
+}
+
+/// Writes planned updates once the move has happened, leaving alone pages
+/// that changed in the meantime. Call it from blocking code.
+pub fn apply_updates(planned: Vec<PlannedUpdate>) -> (Vec<LinkUpdate>, Vec<LinkUpdateFailure>) {
+    let mut updated = Vec::new();
+    let mut failed = Vec::new();
+    for plan in planned {
+        let result = match std::fs::read_to_string(&plan.file) {
+            Ok(current) if current != plan.original => {
+                Err("the page changed during the rename".to_string())
+            }
+            Ok(_) => {
+                atomic::write_blocking(&plan.file, plan.text.as_bytes()).map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(()) => updated.push(plan.update),
+            Err(error) => failed.push(LinkUpdateFailure {
+                volume: plan.update.volume,
+                path: plan.update.path,
+                error,
+            }),
+        }
+    }
+    (updated, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::http::StatusCode;
+
+    fn rewrite(content: &str, volume: &str, path: &str, moved: Move) -> String {
+        rewrite_links(content, Page { volume, path }, &moved)
+            .map_or_else(|| content.to_string(), |(text, _)| text)
+    }
+
+    const PAGE_MOVE: Move = Move::within("default", "notes/todo.md", "archive/2024/todo.md");
+
+    #[test]
+    fn test_rewrites_every_link_form() {
+        let content = "\
+[[todo]] [[notes/todo|Todo]] [[/notes/todo.md#Today]] [[default:todo]]
+[Todo](todo.md) ![shot](todo.md \"title\") [x](<todo.md>) [y](../other.md)
+[[https://example.com]] [anchor](#todo) [[todo-list]]
+
+[ref]: todo.md#later
+";
+        assert_eq!(
+            rewrite(content, "default", "notes/index.md", PAGE_MOVE),
+            "\
+[[../archive/2024/todo]] [[notes/todo|Todo]] [[/archive/2024/todo.md#Today]] [[default:../archive/2024/todo]]
+[Todo](../archive/2024/todo.md) ![shot](../archive/2024/todo.md \"title\") [x](<../archive/2024/todo.md>) [y](../other.md)
+[[https://example.com]] [anchor](#todo) [[todo-list]]
+
+[ref]: ../archive/2024/todo.md#later
+"
+        );
+        // `notes/todo` from the root is a different link to the same page.
+        assert_eq!(
+            rewrite("[[notes/todo|Todo]]", "default", "index.md", PAGE_MOVE),
+            "[[archive/2024/todo|Todo]]"
+        );
+    }
+
+    #[test]
+    fn test_code_is_left_alone() {
+        let content = "\
+See `[[todo]]` and `[x](todo.md)`, but [[todo]].
+
+```
+[[todo]] [T](todo.md)
+[ref]: todo.md
+```
+
+    [[todo]]
+";
+        assert_eq!(
+            rewrite(content, "default", "notes/index.md", PAGE_MOVE),
+            content.replacen("but [[todo]]", "but [[../archive/2024/todo]]", 1)
+        );
+    }
+
+    #[test]
+    fn test_rewrites_cross_volume_links() {
+        let moved = Move::within("work", "notes/todo.md", "archive/2024/todo.md");
+        assert_eq!(
+            rewrite(
+                "[[work:notes/todo]] [[notes/todo]]",
+                "default",
+                "a/b.md",
+                moved
+            ),
+            "[[work:archive/2024/todo]] [[notes/todo]]"
+        );
+        assert_eq!(
+            rewrite("[T](work:/notes/todo.md)", "default", "b.md", moved),
+            "[T](work:/archive/2024/todo.md)"
+        );
+    }
+
+    #[test]
+    fn test_folder_moves() {
+        let moved = Move::within("default", "projects/web", "done/web");
+        // Links into the folder from outside.
+        assert_eq!(
+            rewrite(
+                "[[projects/web/plan]] [[projects/web]] [Spec](projects/web/docs/My%20Spec.md)",
+                "default",
+                "index.md",
+                moved
+            ),
+            "[[done/web/plan]] [[done/web]] [Spec](done/web/docs/My%20Spec.md)"
+        );
+        // Links inside the folder keep working between its own pages, and
+        // relative links out of it are corrected.
+        assert_eq!(
+            rewrite(
+                "[[plan]] [[docs/spec]] [[../api/index]] [[/index]]",
+                "default",
+                "done/web/readme.md",
+                moved
+            ),
+            "[[plan]] [[docs/spec]] [[../../projects/api/index]] [[/index]]"
+        );
+        // A folder that merely shares a prefix is left alone.
+        assert_eq!(
+            rewrite("[[projects/website]]", "default", "index.md", moved),
+            "[[projects/website]]"
+        );
+    }
+
+    #[test]
+    fn test_copies_to_another_volume() {
+        let copied = Move {
+            from_volume: "work",
+            from: "meetings/2024-10-10",
+            to_volume: "personal",
+            to: "notes/meeting",
+        };
+        // Links out of the copy still point at the originals, links within it
+        // at the copies.
+        assert_eq!(
+            rewrite(
+                "[[agenda]] [[../team]] [[/index]] [[personal:todo]] [Logo](../logo.png)",
+                "personal",
+                "notes/meeting/minutes.md",
+                copied
+            ),
+            "[[agenda]] [[work:meetings/team]] [[work:/index]] [[personal:/todo]] [Logo](work:meetings/logo.png)"
+        );
+    }
+
+    #[test]
+    fn test_reports_each_edit() {
+        let (_, edits) = rewrite_links(
+            "[[notes/todo|Todo]] and [Todo](notes/todo.md#top)",
+            Page {
+                volume: "default",
+                path: "index.md",
+            },
+            &PAGE_MOVE,
+        )
+        .unwrap();
+        let edits: Vec<(&str, &str)> = edits
+            .iter()
+            .map(|edit| (edit.before.as_str(), edit.after.as_str()))
+            .collect();
+        assert_eq!(
+            edits,
+            [
+                ("[[notes/todo|Todo]]", "[[archive/2024/todo|Todo]]"),
+                ("notes/todo.md#top", "archive/2024/todo.md#top"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_helpers() {
+        assert_eq!(join("a/b", "../../../c/./d"), "c/d");
+        assert_eq!(relative_to("a/b", "a/c/d"), "../c/d");
+        assert_eq!(relative_to("a", "a"), ".");
+        assert_eq!(percent_decode("My%20Page%zz"), "My Page%zz");
+    }
+
+    #[tokio::test]
+    async fn test_folder_renames_rewrite_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let personal = dir.path().join("personal");
+        std::fs::create_dir_all(personal.join("projects/web")).unwrap();
+        std::fs::write(
+            personal.join("projects/web/plan.md"),
+            "[[spec]] [Home](../../index.md)",
+        )
+        .unwrap();
+        std::fs::write(
+            personal.join("index.md"),
+            "[[projects/web/plan|Plan]] ![d](projects/web/d.png)",
+        )
+        .unwrap();
+        // alice can only read `work`, so its links are left alone and
+        // reported, and cannot see `secret` at all.
+        let work_index = "[[personal:projects/web/plan]]";
+        std::fs::write(dir.path().join("work/index.md"), work_index).unwrap();
+        std::fs::write(dir.path().join("secret/index.md"), work_index).unwrap();
+
+        let rename = |body: &str| {
+            post_json(
+                &app,
+                &cookie,
+                "/api/rename/personal/projects/web",
+                body.to_string(),
+            )
+        };
+        let read = |path: &str| std::fs::read_to_string(dir.path().join(path)).unwrap();
+
+        // A dry run lists the edits and changes nothing.
+        let (status, report) = rename(r#"{"new_path":"archive/web","dry_run":true}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["dry_run"], true);
+        assert_eq!(report["updated"][0]["path"], "index.md");
+        assert_eq!(
+            report["updated"][0]["edits"][1],
+            serde_json::json!({"before": "projects/web/d.png", "after": "archive/web/d.png"})
+        );
+        assert!(personal.join("projects/web/plan.md").exists());
+        assert!(read("personal/index.md").starts_with("[[projects/web/plan|Plan]]"));
+
+        for (new_path, expected) in [
+            ("projects/web/old", StatusCode::BAD_REQUEST),
+            ("index.md", StatusCode::CONFLICT),
+        ] {
+            let (status, _) = rename(&format!(r#"{{"new_path":"{}"}}"#, new_path)).await;
+            assert_eq!(status, expected, "{}", new_path);
+        }
+        let (status, report) = rename(r#"{"new_path":"archive/web"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["dry_run"], false);
+        assert_eq!(report["updated"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            report["failed"],
+            serde_json::json!([{
+                "volume": "work",
+                "path": "index.md",
+                "error": "you cannot edit this page"
+            }])
+        );
+
+        assert_eq!(
+            read("personal/index.md"),
+            "[[archive/web/plan|Plan]] ![d](archive/web/d.png)"
+        );
+        assert_eq!(
+            read("personal/archive/web/plan.md"),
+            "[[spec]] [Home](../../index.md)"
+        );
+        assert_eq!(read("work/index.md"), work_index);
+
+        // The dry run is not in the audit log.
+        let renames = read("audit.log")
+            .lines()
+            .filter(|line| line.contains("page.rename"))
+            .count();
+        assert_eq!(renames, 3);
+    }
+}
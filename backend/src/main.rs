@@ -1,8 +1,17 @@
+use backend::audit::AuditLog;
+use backend::auth::{UrlSigner, UserDirectory};
 use backend::git::GitState;
+use backend::proxy_auth::ProxyAuth;
+use backend::session_store::FileSessionStore;
+use backend::throttle::{LoginThrottle, ThrottleConfig};
+use backend::thumbnails::ThumbnailCache;
+use backend::trash;
+use backend::upload::UploadConfig;
 use backend::AppState;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
+use tower_sessions::session_store::ExpiredDeletion;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -24,9 +33,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         git_states.insert(name.clone(), Arc::new(GitState::new(path.clone())));
     }
 
+    // Users (encrypted users.json, see the wiki-auth tool)
+    let users = UserDirectory::from_env().map_err(|e| format!("Failed to load users: {}", e))?;
+
+    // Sessions (persisted to SESSION_DIR so logins survive restarts)
+    let sessions = FileSessionStore::from_env()
+        .map_err(|e| format!("Failed to open session directory: {}", e))?;
+    let cleanup_store = sessions.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = cleanup_store.delete_expired().await {
+                eprintln!("Failed to delete expired sessions: {}", e);
+            }
+        }
+    });
+
+    // Trash retention (TRASH_RETENTION_DAYS)
+    let retention = trash::retention_from_env()?;
+    if let Some(retention) = retention {
+        let roots: Vec<PathBuf> = volumes.values().cloned().collect();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                for root in &roots {
+                    if let Err(e) = trash::purge_expired(root, retention).await {
+                        eprintln!("Failed to purge trash in {}: {}", root.display(), e);
+                    }
+                }
+            }
+        });
+    }
+
+    // Reverse-proxy SSO (optional)
+    let proxy = ProxyAuth::from_env().map_err(|e| format!("Invalid proxy auth settings: {}", e))?;
+
+    // Login brute-force protection
+    let throttle_config = ThrottleConfig::from_env()
+        .map_err(|e| format!("Invalid login throttle settings: {}", e))?;
+
+    // Upload limits (UPLOAD_MAX_MB, UPLOAD_MAX_MB_PER_VOLUME, UPLOAD_MAX_REQUEST_MB,
+    // UPLOAD_ALLOWED_TYPES)
+    let uploads =
+        UploadConfig::from_env().map_err(|e| format!("Invalid upload settings: {}", e))?;
+
     let state = Arc::new(AppState {
         volumes,
         git_states,
+        users,
+        signer: UrlSigner::from_env(),
+        sessions,
+        proxy,
+        login_throttle: LoginThrottle::new(throttle_config),
+        audit: AuditLog::from_env(),
+        uploads,
+        thumbnails: ThumbnailCache::from_env(),
     });
 
     let app = backend::app(state);
@@ -35,7 +98,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = std::net::SocketAddr::from(([0, 0, 0, 0], 3000));
     println!("listening on {}", addr);
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    // Connect info lets proxy auth check which address a request came from.
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -0,0 +1,292 @@
+//! Identity supplied by a trusted reverse proxy (SSO gateways such as
+//! oauth2-proxy or an identity-aware tunnel) through a request header.
+
+use axum::http::{HeaderMap, HeaderName};
+use common::auth::parse_permissions;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+
+/// An address or CIDR range that proxy requests may come from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TrustedSource {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl TrustedSource {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (addr, prefix) = match spec.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (spec, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address or CIDR range", spec))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("'{}' has an invalid prefix length", spec))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    /// Parses a comma-separated list of addresses and CIDR ranges.
+    pub(crate) fn parse_list(spec: &str) -> Result<Vec<Self>, String> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Trusts an identity header, but only on connections from configured proxy addresses.
+pub struct ProxyAuth {
+    header: HeaderName,
+    trusted: Vec<TrustedSource>,
+    /// Permissions for proxy users without an entry in the users file.
+    /// Without a default policy such users are refused.
+    pub default_permissions: Option<HashMap<String, String>>,
+    /// Whether `/api/login` still accepts passwords.
+    pub password_login: bool,
+}
+
+impl ProxyAuth {
+    pub fn new(
+        header: &str,
+        trusted: &str,
+        default_permissions: Option<HashMap<String, String>>,
+        password_login: bool,
+    ) -> Result<Self, String> {
+        let header = HeaderName::try_from(header)
+            .map_err(|_| format!("'{}' is not a valid header name", header))?;
+        let trusted = TrustedSource::parse_list(trusted)?;
+        if trusted.is_empty() {
+            return Err("AUTH_PROXY_TRUSTED must list at least one proxy address".to_string());
+        }
+        Ok(Self {
+            header,
+            trusted,
+            default_permissions,
+            password_login,
+        })
+    }
+
+    /// Enabled by `AUTH_PROXY_HEADER`; also reads `AUTH_PROXY_TRUSTED`,
+    /// `AUTH_PROXY_DEFAULT_PERMISSIONS` and `AUTH_PROXY_ONLY`.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(header) = std::env::var("AUTH_PROXY_HEADER") else {
+            return Ok(None);
+        };
+        let trusted = std::env::var("AUTH_PROXY_TRUSTED").unwrap_or_default();
+        let default_permissions = match std::env::var("AUTH_PROXY_DEFAULT_PERMISSIONS") {
+            Ok(spec) => Some(parse_permissions(&spec).map_err(|e| e.to_string())?),
+            Err(_) => None,
+        };
+        let password_login = std::env::var("AUTH_PROXY_ONLY").unwrap_or_default() != "true";
+        Self::new(&header, &trusted, default_permissions, password_login).map(Some)
+    }
+
+    /// The identity asserted by the proxy, if the request came from a trusted
+    /// address and carries a non-empty header.
+    pub fn identity(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<String> {
+        let peer = peer?;
+        if !self.trusted.iter().any(|t| t.contains(peer.ip())) {
+            return None;
+        }
+        let identity = headers.get(&self.header)?.to_str().ok()?.trim();
+        (!identity.is_empty()).then(|| identity.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csrf;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::http::{header, Method, StatusCode};
+    use common::AuthMode;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_trusted_sources() {
+        let proxy = ProxyAuth::new(
+            "X-Auth-Email",
+            "127.0.0.1, 10.0.0.0/8, fd00::/8",
+            None,
+            true,
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-auth-email", "alice@example.com".parse().unwrap());
+
+        let from = |addr: &str| Some(SocketAddr::new(addr.parse().unwrap(), 4000));
+        for trusted in ["127.0.0.1", "10.20.30.40", "::ffff:10.0.0.1", "fd12::1"] {
+            assert_eq!(
+                proxy.identity(&headers, from(trusted)).as_deref(),
+                Some("alice@example.com"),
+                "{}",
+                trusted
+            );
+        }
+        for untrusted in ["127.0.0.2", "11.0.0.1", "fe80::1"] {
+            assert_eq!(
+                proxy.identity(&headers, from(untrusted)),
+                None,
+                "{}",
+                untrusted
+            );
+        }
+        assert_eq!(proxy.identity(&headers, None), None);
+        assert_eq!(proxy.identity(&HeaderMap::new(), from("127.0.0.1")), None);
+
+        assert!(ProxyAuth::new("X-Auth-Email", "", None, true).is_err());
+        assert!(ProxyAuth::new("X-Auth-Email", "10.0.0.0/33", None, true).is_err());
+        assert!(ProxyAuth::new("X-Auth-Email", "proxy.local", None, true).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_trusted_proxy_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = Arc::try_unwrap(test_state(dir.path())).ok().unwrap();
+        state.proxy = Some(
+            crate::proxy_auth::ProxyAuth::new(
+                "X-Auth-Email",
+                "10.0.0.0/8",
+                Some(parse_permissions("personal:r").unwrap()),
+                false,
+            )
+            .unwrap(),
+        );
+        let app = crate::app(Arc::new(state));
+
+        // A fake proxy at 10.1.2.3 vouching for the user in the header.
+        let request = |peer: &str, identity: &str, method: Method, uri: &str, cookie: &str| {
+            let mut req = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("X-Auth-Email", identity)
+                .header(header::COOKIE, cookie)
+                .header(csrf::CSRF_HEADER, csrf_token(cookie))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"path":"index.md","content":"changed","username":"alice","password":"pw"}"#,
+                ))
+                .unwrap();
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 443)));
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap() }
+        };
+        // Like a browser, pick up the session and CSRF cookies with a read first.
+        let via =
+            |peer: &'static str, identity: &'static str, method: Method, uri: &'static str| {
+                let request = &request;
+                async move {
+                    let resp = request(peer, identity, Method::GET, "/api/tree", "").await;
+                    let cookie = ["id", csrf::CSRF_COOKIE]
+                        .iter()
+                        .filter_map(|name| set_cookie(&resp, name))
+                        .collect::<Vec<_>>()
+                        .join("; ");
+                    request(peer, identity, method, uri, &cookie).await
+                }
+            };
+
+        let cases = [
+            (
+                "10.1.2.3",
+                "alice",
+                Method::PUT,
+                "/api/wiki/personal/index.md",
+                StatusCode::OK,
+            ),
+            (
+                "10.1.2.3",
+                "alice",
+                Method::GET,
+                "/api/wiki/work/index.md",
+                StatusCode::OK,
+            ),
+            // Unknown identities get the default policy.
+            (
+                "10.1.2.3",
+                "bob@example.com",
+                Method::GET,
+                "/api/wiki/personal/index.md",
+                StatusCode::OK,
+            ),
+            (
+                "10.1.2.3",
+                "bob@example.com",
+                Method::PUT,
+                "/api/wiki/personal/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "10.1.2.3",
+                "bob@example.com",
+                Method::GET,
+                "/api/wiki/work/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            // The header is ignored from anywhere else.
+            (
+                "192.168.1.5",
+                "alice",
+                Method::GET,
+                "/api/wiki/personal/index.md",
+                StatusCode::UNAUTHORIZED,
+            ),
+            // AUTH_PROXY_ONLY disables the password form.
+            (
+                "192.168.1.5",
+                "alice",
+                Method::POST,
+                "/api/login",
+                StatusCode::FORBIDDEN,
+            ),
+        ];
+        for (peer, identity, method, uri, expected) in cases {
+            let resp = via(peer, identity, method.clone(), uri).await;
+            assert_eq!(
+                resp.status(),
+                expected,
+                "{} {} {} {}",
+                peer,
+                identity,
+                method,
+                uri
+            );
+        }
+
+        let resp = via("10.1.2.3", "alice", Method::GET, "/api/auth/mode").await;
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mode: AuthMode = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(mode.proxy_user.as_deref(), Some("alice"));
+        assert!(!mode.password_login);
+    }
+}
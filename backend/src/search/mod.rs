@@ -27,7 +27,15 @@ pub fn search_wiki(root: &PathBuf, query: &str) -> Vec<SearchResult> {
     let mut results = Vec::new();
     let query_lower = query.to_lowercase();
 
-    for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
+    // Hidden folders such as `.git` and `.trash` are not part of the wiki.
+    let visible = |entry: &walkdir::DirEntry| {
+        entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+    };
+    for entry in WalkDir::new(root)
+        .into_iter()
+        .filter_entry(visible)
+        .filter_map(|e| e.ok())
+    {
         let path = entry.path();
         if entry.file_type().is_dir() {
             continue;
@@ -0,0 +1,350 @@
+//! Files streamed from disk with validators and byte ranges, so large videos
+//! and PDFs are never read into memory, can be seeked in the browser, and are
+//! not downloaded again while unchanged.
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// A byte range to send, or why the `Range` header cannot be satisfied.
+#[derive(Debug, PartialEq)]
+enum Range {
+    /// First and last byte, inclusive.
+    Bytes(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for a file of `len` bytes. Returns `None` for
+/// anything but a single byte range, in which case the whole file is sent.
+fn parse_range(value: &str, len: u64) -> Option<Range> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return None,
+        // The last `n` bytes.
+        ("", suffix) => {
+            let n: u64 = suffix.parse().ok()?;
+            if n == 0 || len == 0 {
+                Range::Unsatisfiable
+            } else {
+                Range::Bytes(len.saturating_sub(n), len - 1)
+            }
+        }
+        (start, end) => {
+            let start: u64 = start.parse().ok()?;
+            let end = match end {
+                "" => u64::MAX,
+                end => end.parse().ok()?,
+            };
+            if end < start {
+                return None;
+            }
+            if start >= len {
+                Range::Unsatisfiable
+            } else {
+                Range::Bytes(start, end.min(len - 1))
+            }
+        }
+    };
+    Some(range)
+}
+
+/// A strong `ETag` from a file's size and modification time, which change
+/// whenever the wiki writes a file.
+fn file_etag(len: u64, modified: Option<SystemTime>) -> String {
+    let nanos = modified
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{:x}-{:x}\"", len, nanos)
+}
+
+/// Whether `If-None-Match` lists `etag` (or is `*`). Weak and strong tags of
+/// the same value match, as the header uses weak comparison.
+pub fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    value
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Truncates to whole seconds, the precision of HTTP dates.
+fn http_seconds(time: SystemTime) -> SystemTime {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// Whether the client's copy is current, by `If-None-Match` or, failing
+/// that, `If-Modified-Since`.
+fn not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return etag_matches(headers, etag);
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => http_seconds(modified) <= since,
+        _ => false,
+    }
+}
+
+/// Whether a `Range` request may be answered with part of the file: only if
+/// `If-Range`, when present, names the current version exactly.
+fn if_range_allows(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(value) = headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) else {
+        return true;
+    };
+    let value = value.trim();
+    if value.starts_with('"') {
+        return value == etag;
+    }
+    match (httpdate::parse_http_date(value), modified) {
+        (Ok(date), Some(modified)) => http_seconds(modified) == date,
+        _ => false,
+    }
+}
+
+/// Responds with the file at `path` as `content_type`, streaming it from
+/// disk. Honours `Range` (one range per request), `If-Range`,
+/// `If-None-Match` and `If-Modified-Since`.
+pub async fn file_response(path: &Path, content_type: &str, headers: &HeaderMap) -> Response {
+    let not_found = || (StatusCode::NOT_FOUND, "File not found").into_response();
+    let Ok(mut file) = tokio::fs::File::open(path).await else {
+        return not_found();
+    };
+    let meta = match file.metadata().await {
+        Ok(meta) if meta.is_file() => meta,
+        _ => return not_found(),
+    };
+    let len = meta.len();
+    let modified = meta.modified().ok();
+    let etag = file_etag(len, modified);
+
+    let mut response_headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    if let Some(value) =
+        modified.and_then(|t| HeaderValue::from_str(&httpdate::fmt_http_date(t)).ok())
+    {
+        response_headers.insert(header::LAST_MODIFIED, value);
+    }
+    if not_modified(headers, &etag, modified) {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+    if let Ok(value) = HeaderValue::from_str(content_type) {
+        response_headers.insert(header::CONTENT_TYPE, value);
+    }
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| if_range_allows(headers, &etag, modified))
+        .and_then(|v| parse_range(v, len));
+    let (status, start, count) = match range {
+        None => (StatusCode::OK, 0, len),
+        Some(Range::Bytes(start, end)) => {
+            let content_range = format!("bytes {}-{}/{}", start, end, len);
+            if let Ok(value) = HeaderValue::from_str(&content_range) {
+                response_headers.insert(header::CONTENT_RANGE, value);
+            }
+            (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+        }
+        Some(Range::Unsatisfiable) => {
+            let content_range = format!("bytes */{}", len);
+            if let Ok(value) = HeaderValue::from_str(&content_range) {
+                response_headers.insert(header::CONTENT_RANGE, value);
+            }
+            return (StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response();
+        }
+    };
+
+    if start > 0 && file.seek(SeekFrom::Start(start)).await.is_err() {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file").into_response();
+    }
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(count));
+    let body = Body::from_stream(ReaderStream::new(file.take(count)));
+    (status, response_headers, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Range::Bytes(0, 99)));
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            Some(Range::Bytes(900, 999))
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            Some(Range::Bytes(900, 999))
+        );
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(Range::Bytes(0, 999)));
+        assert_eq!(
+            parse_range("bytes=500-5000", 1000),
+            Some(Range::Bytes(500, 999))
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Range::Unsatisfiable));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(Range::Unsatisfiable));
+        // Anything else is ignored and the whole file is sent.
+        for ignored in [
+            "bytes=0-1,5-6",
+            "bytes=5-1",
+            "items=0-1",
+            "bytes=x-",
+            "bytes=-",
+        ] {
+            assert_eq!(parse_range(ignored, 1000), None, "{}", ignored);
+        }
+    }
+
+    #[test]
+    fn test_conditional_requests() {
+        let modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let etag = file_etag(10, Some(modified));
+        let request = |name: header::HeaderName, value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+
+        assert!(not_modified(
+            &request(header::IF_NONE_MATCH, &etag),
+            &etag,
+            Some(modified)
+        ));
+        let weak = format!("\"other\", W/{}", etag);
+        assert!(not_modified(
+            &request(header::IF_NONE_MATCH, &weak),
+            &etag,
+            Some(modified)
+        ));
+        assert!(!not_modified(
+            &request(header::IF_NONE_MATCH, "\"other\""),
+            &etag,
+            Some(modified)
+        ));
+
+        let date = httpdate::fmt_http_date(modified);
+        assert!(not_modified(
+            &request(header::IF_MODIFIED_SINCE, &date),
+            &etag,
+            Some(modified)
+        ));
+        let earlier = httpdate::fmt_http_date(modified - Duration::from_secs(1));
+        assert!(!not_modified(
+            &request(header::IF_MODIFIED_SINCE, &earlier),
+            &etag,
+            Some(modified)
+        ));
+
+        assert!(if_range_allows(
+            &request(header::IF_RANGE, &etag),
+            &etag,
+            Some(modified)
+        ));
+        assert!(if_range_allows(
+            &request(header::IF_RANGE, &date),
+            &etag,
+            Some(modified)
+        ));
+        assert!(!if_range_allows(
+            &request(header::IF_RANGE, "\"old\""),
+            &etag,
+            Some(modified)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_files_are_served_in_ranges_and_revalidated() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let video: Vec<u8> = (0..=255).cycle().take(3 * 1024 * 1024).collect();
+        std::fs::write(dir.path().join("personal/clip.mp4"), &video).unwrap();
+        let app = crate::app(state);
+        let cookie = login_cookie(&app).await;
+
+        let get = |uri: &str, extra: &[(header::HeaderName, &str)]| {
+            let mut req = axum::http::Request::get(uri).header(header::COOKIE, &cookie);
+            for (name, value) in extra {
+                req = req.header(name, *value);
+            }
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+
+        for uri in ["/wiki/personal/clip.mp4", "/api/wiki/personal/clip.mp4"] {
+            let resp = get(uri, &[]).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(resp.headers()[header::CONTENT_TYPE], "video/mp4");
+            assert_eq!(resp.headers()[header::ACCEPT_RANGES], "bytes");
+            let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+            assert!(resp.headers().contains_key(header::LAST_MODIFIED));
+
+            let resp = get(uri, &[(header::RANGE, "bytes=1000-1999")])
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(
+                resp.headers()[header::CONTENT_RANGE],
+                format!("bytes 1000-1999/{}", video.len())
+            );
+            let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(&bytes[..], &video[1000..2000]);
+
+            let resp = get(uri, &[(header::IF_NONE_MATCH, &etag)]).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+            // A stale If-Range gets the whole file.
+            let resp = get(
+                uri,
+                &[
+                    (header::RANGE, "bytes=0-9"),
+                    (header::IF_RANGE, "\"stale\""),
+                ],
+            )
+            .await
+            .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp = get(uri, &[(header::RANGE, "bytes=99999999-")])
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        }
+
+        // Pages can be revalidated by their content ETag.
+        let resp = get("/api/wiki/personal/index.md", &[]).await.unwrap();
+        let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+        let resp = get(
+            "/api/wiki/personal/index.md",
+            &[(header::IF_NONE_MATCH, &etag)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::auth::USER_SESSION_KEY;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, ExpiredDeletion, SessionStore};
+
+/// Session store that keeps one JSON file per session in a directory.
+///
+/// Records are written to a temporary file and renamed into place, so several
+/// server processes on the same host can share the directory safely.
+#[derive(Debug, Clone)]
+pub struct FileSessionStore {
+    dir: PathBuf,
+}
+
+fn backend_error(e: impl std::fmt::Display) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+impl FileSessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(Self { dir })
+    }
+
+    /// Uses `SESSION_DIR`, defaulting to `sessions` in the working directory.
+    pub fn from_env() -> std::io::Result<Self> {
+        Self::new(std::env::var("SESSION_DIR").unwrap_or_else(|_| "sessions".to_string()))
+    }
+
+    fn record_path(&self, id: &Id) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    async fn read_record(path: &Path) -> session_store::Result<Option<Record>> {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| session_store::Error::Decode(e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(backend_error(e)),
+        }
+    }
+
+    /// All unexpired session records, in no particular order.
+    pub async fn records(&self) -> session_store::Result<Vec<Record>> {
+        let now = OffsetDateTime::now_utc();
+        let mut records = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .map_err(backend_error)?;
+        while let Some(entry) = entries.next_entry().await.map_err(backend_error)? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            // Skip records that are unreadable or were deleted concurrently.
+            if let Ok(Some(record)) = Self::read_record(&path).await {
+                if record.expiry_date > now {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Deletes every session logged in as `username` and returns how many
+    /// there were.
+    pub async fn delete_user_sessions(&self, username: &str) -> session_store::Result<usize> {
+        let mut deleted = 0;
+        for record in self.records().await? {
+            if record_username(&record) == Some(username) {
+                self.delete(&record.id).await?;
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+/// The user a session record is logged in as, if any.
+pub fn record_username(record: &Record) -> Option<&str> {
+    record.data.get(USER_SESSION_KEY)?.get("username")?.as_str()
+}
+
+#[async_trait]
+impl SessionStore for FileSessionStore {
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        let bytes =
+            serde_json::to_vec(record).map_err(|e| session_store::Error::Encode(e.to_string()))?;
+        let path = self.record_path(&record.id);
+        let tmp_path = self
+            .dir
+            .join(format!(".{}.{}.tmp", record.id, rand::random::<u64>()));
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let write = async {
+            use tokio::io::AsyncWriteExt;
+            let mut file = options.open(&tmp_path).await?;
+            file.write_all(&bytes).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&tmp_path, &path).await
+        };
+        if let Err(e) = write.await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(backend_error(e));
+        }
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let path = self.record_path(session_id);
+        match Self::read_record(&path).await? {
+            Some(record) if record.expiry_date > OffsetDateTime::now_utc() => Ok(Some(record)),
+            Some(_) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        match tokio::fs::remove_file(self.record_path(session_id)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(backend_error(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for FileSessionStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .map_err(backend_error)?;
+        while let Some(entry) = entries.next_entry().await.map_err(backend_error)? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match Self::read_record(&path).await {
+                Ok(Some(record)) if record.expiry_date > now => {}
+                // Expired or corrupt records are removed.
+                Ok(Some(_)) | Err(_) => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+                Ok(None) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn record(expiry_date: OffsetDateTime) -> Record {
+        let mut data = HashMap::new();
+        data.insert(
+            "user".to_string(),
+            serde_json::json!({ "username": "alice" }),
+        );
+        Record {
+            id: Id::default(),
+            data,
+            expiry_date,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_survive_a_new_store_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSessionStore::new(dir.path()).unwrap();
+        let mut rec = record(OffsetDateTime::now_utc() + time::Duration::hours(1));
+        store.create(&mut rec).await.unwrap();
+
+        let reopened = FileSessionStore::new(dir.path()).unwrap();
+        let loaded = reopened.load(&rec.id).await.unwrap().unwrap();
+        assert_eq!(loaded.data, rec.data);
+
+        reopened.delete(&rec.id).await.unwrap();
+        assert!(store.load(&rec.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_records_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSessionStore::new(dir.path()).unwrap();
+        let mut live = record(OffsetDateTime::now_utc() + time::Duration::hours(1));
+        let mut expired = record(OffsetDateTime::now_utc() - time::Duration::seconds(1));
+        store.create(&mut live).await.unwrap();
+        store.create(&mut expired).await.unwrap();
+
+        assert_eq!(store.records().await.unwrap().len(), 1);
+        store.delete_expired().await.unwrap();
+        assert!(!store.record_path(&expired.id).exists());
+        assert!(store.record_path(&live.id).exists());
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSessionStore::new(dir.path()).unwrap();
+        let expiry = OffsetDateTime::now_utc() + time::Duration::hours(1);
+        let mut first = record(expiry);
+        let mut second = record(expiry);
+        let mut other = record(expiry);
+        other
+            .data
+            .insert("user".to_string(), serde_json::json!({ "username": "bob" }));
+        for rec in [&mut first, &mut second, &mut other] {
+            store.create(rec).await.unwrap();
+        }
+
+        assert_eq!(store.delete_user_sessions("alice").await.unwrap(), 2);
+        let remaining = store.records().await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(record_username(&remaining[0]), Some("bob"));
+    }
+}
@@ -0,0 +1,271 @@
+//! The caller's own account and browser sessions, under `/api/me` and
+//! `/api/sessions`.
+
+use crate::session_store::record_username;
+use crate::tokens::ApiResult;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    Extension, Json,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::{SessionInfo, User};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+use std::sync::Arc;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::{Session, SessionStore};
+
+pub(crate) const SESSION_META_KEY: &str = "session_meta";
+
+/// How stale `last_seen` may get before a request rewrites the session.
+const TOUCH_INTERVAL_SECS: i64 = 60;
+const MAX_USER_AGENT_LEN: usize = 200;
+
+/// Where and when a session was used, kept alongside the logged-in user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionMeta {
+    created_at: i64,
+    last_seen: i64,
+    #[serde(default)]
+    ip: Option<String>,
+    #[serde(default)]
+    user_agent: Option<String>,
+}
+
+impl SessionMeta {
+    pub fn new(headers: &HeaderMap, ip: Option<IpAddr>) -> Self {
+        let now = now();
+        Self {
+            created_at: now,
+            last_seen: now,
+            ip: ip.map(|ip| ip.to_string()),
+            user_agent: user_agent(headers),
+        }
+    }
+}
+
+fn now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    let agent = headers.get(header::USER_AGENT)?.to_str().ok()?;
+    Some(agent.chars().take(MAX_USER_AGENT_LEN).collect())
+}
+
+/// The id sessions are listed and revoked by. The session id itself is the
+/// cookie secret, so only a hash of it ever leaves the server.
+fn public_id(id: &Id) -> String {
+    URL_SAFE_NO_PAD.encode(&Sha256::digest(id.to_string().as_bytes())[..12])
+}
+
+/// Records that the session was just used. Writes at most once a minute so
+/// ordinary requests do not rewrite the session file.
+pub async fn touch(session: &Session, headers: &HeaderMap, ip: Option<IpAddr>) {
+    let meta: Option<SessionMeta> = session.get(SESSION_META_KEY).await.ok().flatten();
+    let now = now();
+    let meta = match meta {
+        Some(meta) if now - meta.last_seen < TOUCH_INTERVAL_SECS => return,
+        Some(meta) => SessionMeta {
+            last_seen: now,
+            ip: ip.map(|ip| ip.to_string()).or(meta.ip),
+            user_agent: user_agent(headers).or(meta.user_agent),
+            ..meta
+        },
+        // Sessions started before metadata was kept.
+        None => SessionMeta::new(headers, ip),
+    };
+    if let Err(e) = session.insert(SESSION_META_KEY, meta).await {
+        eprintln!("Failed to update session metadata: {}", e);
+    }
+}
+
+fn session_info(record: &Record, current: Option<&Id>) -> SessionInfo {
+    let meta: Option<SessionMeta> = record
+        .data
+        .get(SESSION_META_KEY)
+        .and_then(|value| serde_json::from_value(value.clone()).ok());
+    SessionInfo {
+        id: public_id(&record.id),
+        created_at: meta.as_ref().map(|m| m.created_at),
+        last_seen: meta.as_ref().map(|m| m.last_seen),
+        ip: meta.as_ref().and_then(|m| m.ip.clone()),
+        user_agent: meta.and_then(|m| m.user_agent),
+        expires_at: record.expiry_date.unix_timestamp(),
+        current: current == Some(&record.id),
+    }
+}
+
+async fn user_records(state: &AppState, username: &str) -> ApiResult<Vec<Record>> {
+    let records = state.sessions.records().await.map_err(|e| {
+        eprintln!("Failed to read sessions: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to read sessions".to_string(),
+        )
+    })?;
+    Ok(records
+        .into_iter()
+        .filter(|record| record_username(record) == Some(username))
+        .collect())
+}
+
+async fn end_session(state: &AppState, session: &Session, record: &Record) -> ApiResult<()> {
+    let result = if session.id() == Some(record.id) {
+        // Flushing also stops the session layer from saving it again.
+        session.flush().await.map_err(|e| e.to_string())
+    } else {
+        state
+            .sessions
+            .delete(&record.id)
+            .await
+            .map_err(|e| e.to_string())
+    };
+    result.map_err(|e| {
+        eprintln!("Failed to end session: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to end session".to_string(),
+        )
+    })
+}
+
+/// Who the caller is and what they may access.
+pub async fn me(Extension(user): Extension<User>) -> Json<User> {
+    Json(user)
+}
+
+/// The caller's browser sessions, most recently used first.
+pub async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    session: Session,
+) -> ApiResult<Json<Vec<SessionInfo>>> {
+    let current = session.id();
+    let mut sessions: Vec<SessionInfo> = user_records(&state, &user.username)
+        .await?
+        .iter()
+        .map(|record| session_info(record, current.as_ref()))
+        .collect();
+    sessions.sort_by_key(|info| std::cmp::Reverse(info.last_seen));
+    Ok(Json(sessions))
+}
+
+/// Ends one of the caller's sessions, which may be the current one.
+pub async fn revoke_session(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    session: Session,
+    Path(id): Path<String>,
+) -> ApiResult<StatusCode> {
+    let record = user_records(&state, &user.username)
+        .await?
+        .into_iter()
+        .find(|record| public_id(&record.id) == id)
+        .ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+    end_session(&state, &session, &record).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Ends every session of the caller except the current one.
+pub async fn revoke_other_sessions(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    session: Session,
+) -> ApiResult<StatusCode> {
+    let current = session.id();
+    for record in user_records(&state, &user.username).await? {
+        if Some(record.id) != current {
+            end_session(&state, &session, &record).await?;
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::http::Method;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_me_and_session_management() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        let app = crate::app(state.clone());
+
+        let laptop = login_cookie(&app).await;
+        let req = axum::http::Request::post("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::USER_AGENT, "PhoneBrowser/1.0")
+            .body(Body::from(r#"{"username":"alice","password":"pw"}"#))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let phone = with_csrf_cookie(&app, set_cookie(&resp, "id").unwrap().to_string()).await;
+        let other = login_cookie(&app).await;
+
+        let (status, me) = send_json(&app, &laptop, Method::GET, "/api/me", String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(me["username"], "alice");
+        assert_eq!(me["permissions"]["work"], "r");
+
+        let (status, list) =
+            send_json(&app, &laptop, Method::GET, "/api/sessions", String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        let list = list.as_array().unwrap().clone();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.iter().filter(|s| s["current"] == true).count(), 1);
+        let phone_id = list
+            .iter()
+            .find(|s| s["user_agent"] == "PhoneBrowser/1.0")
+            .unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(list.iter().all(|s| s["created_at"].is_i64()));
+        // Session ids are cookie secrets and never listed.
+        assert!(!laptop.contains(list[0]["id"].as_str().unwrap()));
+
+        assert_eq!(
+            send(&app, &laptop, Method::DELETE, "/api/sessions/unknown").await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            send(
+                &app,
+                &laptop,
+                Method::DELETE,
+                &format!("/api/sessions/{}", phone_id)
+            )
+            .await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&app, &phone, Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&app, &other, Method::GET, "/api/tree").await,
+            StatusCode::OK
+        );
+
+        assert_eq!(
+            send(&app, &laptop, Method::DELETE, "/api/sessions").await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&app, &other, Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            send(&app, &laptop, Method::GET, "/api/me").await,
+            StatusCode::OK
+        );
+    }
+}
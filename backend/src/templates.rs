@@ -0,0 +1,254 @@
+//! Page templates: Markdown files in a volume's `.templates` folder that new
+//! pages can start from. Placeholders such as `{{date}}` are filled in when a
+//! page is created.
+
+use crate::acl::{normalize, PathAcl};
+use crate::atomic;
+use crate::audit::Detail;
+use crate::tokens::ApiResult;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use common::{CreateFromTemplate, TemplateInfo, User};
+use regex::{Captures, Regex};
+use std::sync::{Arc, LazyLock};
+use time::{OffsetDateTime, UtcOffset};
+use tokio::io::AsyncWriteExt;
+
+pub const TEMPLATES_DIR: &str = ".templates";
+/// The furthest any time zone is from UTC.
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*([a-z]+)\s*\}\}").unwrap());
+
+/// What the placeholders of a template stand for.
+pub struct Vars<'a> {
+    pub volume: &'a str,
+    pub path: &'a str,
+    pub user: &'a str,
+    pub now: OffsetDateTime,
+}
+
+/// Fills in `{{date}}`, `{{time}}`, `{{datetime}}`, `{{title}}`, `{{user}}`,
+/// `{{path}}` and `{{volume}}`. Other placeholders are left as they are.
+pub fn expand(template: &str, vars: &Vars) -> String {
+    let now = vars.now;
+    let date = format!(
+        "{:04}-{:02}-{:02}",
+        now.year(),
+        u8::from(now.month()),
+        now.day()
+    );
+    let time = format!("{:02}:{:02}", now.hour(), now.minute());
+    let name = vars.path.rsplit('/').next().unwrap_or(vars.path);
+    let title = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    PLACEHOLDER
+        .replace_all(template, |caps: &Captures| match &caps[1] {
+            "date" => date.clone(),
+            "time" => time.clone(),
+            "datetime" => format!("{} {}", date, time),
+            "title" => title.to_string(),
+            "user" => vars.user.to_string(),
+            "path" => vars.path.to_string(),
+            "volume" => vars.volume.to_string(),
+            _ => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+fn template_path(name: &str) -> Option<String> {
+    let valid = !name.is_empty() && !name.contains(['/', '\\']) && !name.starts_with('.');
+    valid.then(|| format!("{}/{}.md", TEMPLATES_DIR, name))
+}
+
+/// The templates of a volume the caller can read, by name.
+pub async fn list_templates(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path(volume): Path<String>,
+) -> ApiResult<Json<Vec<TemplateInfo>>> {
+    let root = state.volume_root(&volume)?;
+    let mut templates = Vec::new();
+    let mut entries = match tokio::fs::read_dir(root.join(TEMPLATES_DIR)).await {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Json(templates)),
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let Some(name) = file_name.strip_suffix(".md") else {
+            continue;
+        };
+        if template_path(name).is_some_and(|path| acl.can_read(&volume, &path)) {
+            templates.push(TemplateInfo {
+                name: name.to_string(),
+            });
+        }
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(templates))
+}
+
+/// Creates a page from a template of the same volume.
+pub async fn create_from_template(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(acl): Extension<PathAcl>,
+    Path((volume, path)): Path<(String, String)>,
+    Json(payload): Json<CreateFromTemplate>,
+) -> ApiResult<(Extension<Detail>, StatusCode)> {
+    let root = state.volume_root(&volume)?;
+    let path = normalize(&path);
+    if path.is_empty() || path.split('/').any(|part| part == "..") {
+        return Err((StatusCode::FORBIDDEN, "Invalid path".to_string()));
+    }
+    let not_found = || (StatusCode::NOT_FOUND, "Template not found".to_string());
+    let template_path = template_path(&payload.template).ok_or_else(not_found)?;
+    if !acl.can_read(&volume, &template_path) {
+        return Err(not_found());
+    }
+    let template = tokio::fs::read_to_string(root.join(&template_path))
+        .await
+        .map_err(|_| not_found())?;
+
+    if !(-MAX_UTC_OFFSET_MINUTES..=MAX_UTC_OFFSET_MINUTES).contains(&payload.utc_offset_minutes) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "utc_offset_minutes must be within 14 hours of UTC".to_string(),
+        ));
+    }
+    let offset =
+        UtcOffset::from_whole_seconds(payload.utc_offset_minutes * 60).unwrap_or(UtcOffset::UTC);
+    let content = expand(
+        &template,
+        &Vars {
+            volume: &volume,
+            path: &path,
+            user: &user.username,
+            now: OffsetDateTime::now_utc().to_offset(offset),
+        },
+    );
+    let file_path = root.join(&path);
+    if let Some(parent) = file_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| atomic::write_error(&e))?;
+    }
+    // Created only if nothing is there yet, even if another request is
+    // creating the same page.
+    let mut file = match tokio::fs::File::create_new(&file_path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err((StatusCode::CONFLICT, format!("'{}' already exists", path)));
+        }
+        Err(e) => return Err(atomic::write_error(&e)),
+    };
+    let written = async {
+        file.write_all(content.as_bytes()).await?;
+        file.sync_all().await
+    }
+    .await;
+    if let Err(e) = written {
+        drop(file);
+        let _ = tokio::fs::remove_file(&file_path).await;
+        return Err(atomic::write_error(&e));
+    }
+    let detail = Detail(format!("from template {}", payload.template));
+    Ok((Extension(detail), StatusCode::CREATED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::http::Method;
+
+    #[test]
+    fn test_expand() {
+        let vars = Vars {
+            volume: "work",
+            path: "meetings/2024-10-17 standup.md",
+            user: "alice",
+            now: time::Date::from_calendar_date(2024, time::Month::October, 17)
+                .unwrap()
+                .with_hms(9, 5, 0)
+                .unwrap()
+                .assume_offset(UtcOffset::from_hms(2, 0, 0).unwrap()),
+        };
+        assert_eq!(
+            expand(
+                "# {{title}}\n{{ date }} {{time}} by {{user}} ({{volume}}:{{path}}) {{other}}",
+                &vars
+            ),
+            "# 2024-10-17 standup\n2024-10-17 09:05 by alice (work:meetings/2024-10-17 standup.md) {{other}}"
+        );
+    }
+
+    #[test]
+    fn test_template_names_stay_in_the_folder() {
+        assert_eq!(
+            template_path("meeting").as_deref(),
+            Some(".templates/meeting.md")
+        );
+        assert_eq!(template_path("../secret"), None);
+        assert_eq!(template_path(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_pages_from_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let templates = dir.path().join("personal/.templates");
+        std::fs::create_dir_all(&templates).unwrap();
+        std::fs::write(templates.join("meeting.md"), "# {{title}}\nBy {{user}}").unwrap();
+        std::fs::write(templates.join("notes.txt"), "not a template").unwrap();
+
+        let (status, list) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/templates/personal",
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list, serde_json::json!([{"name": "meeting"}]));
+
+        let create = |template: &str| {
+            post_json(
+                &app,
+                &cookie,
+                "/api/templates/personal/notes/standup.md",
+                serde_json::json!({ "template": template }).to_string(),
+            )
+        };
+        assert_eq!(create("meeting").await.0, StatusCode::CREATED);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("personal/notes/standup.md")).unwrap(),
+            "# standup\nBy alice"
+        );
+        assert_eq!(create("meeting").await.0, StatusCode::CONFLICT);
+        assert_eq!(create("../index").await.0, StatusCode::NOT_FOUND);
+
+        for (offset, expected) in [
+            (i32::MAX, StatusCode::BAD_REQUEST),
+            (i32::MIN, StatusCode::BAD_REQUEST),
+            (15 * 60, StatusCode::BAD_REQUEST),
+            (-14 * 60, StatusCode::CREATED),
+        ] {
+            let (status, _) = post_json(
+                &app,
+                &cookie,
+                &format!("/api/templates/personal/offset{}.md", offset),
+                serde_json::json!({ "template": "meeting", "utc_offset_minutes": offset })
+                    .to_string(),
+            )
+            .await;
+            assert_eq!(status, expected, "{}", offset);
+        }
+    }
+}
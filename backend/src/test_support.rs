@@ -0,0 +1,154 @@
+//! Shared fixtures for router tests: an app state over temporary volumes and
+//! helpers to log in and send requests with the session and CSRF cookies.
+
+use crate::auth::{UrlSigner, UserDirectory};
+use crate::git::GitState;
+use crate::{csrf, AppState};
+use axum::body::Body;
+use axum::http::{header, Method, StatusCode};
+use common::auth::{parse_permissions, UserRecord, UserStore};
+use std::collections::HashMap;
+use std::path::Path as FsPath;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+/// Three volumes, each with an `index.md`, and alice with `personal:rw,work:r`.
+pub fn test_state(root: &FsPath) -> Arc<AppState> {
+    let mut volumes = HashMap::new();
+    let mut git_states = HashMap::new();
+    for name in ["personal", "work", "secret"] {
+        let path = root.join(name);
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("index.md"), format!("{} notes", name)).unwrap();
+        git_states.insert(name.to_string(), Arc::new(GitState::new(path.clone())));
+        volumes.insert(name.to_string(), path);
+    }
+
+    let mut store = UserStore::default();
+    store.users.insert(
+        "alice".to_string(),
+        UserRecord::new("pw", parse_permissions("personal:rw,work:r").unwrap()),
+    );
+
+    Arc::new(AppState {
+        volumes,
+        git_states,
+        users: UserDirectory::new(
+            root.join("users.json"),
+            Some("test-secret".to_string()),
+            store,
+        ),
+        signer: UrlSigner::new(b"test-secret"),
+        sessions: crate::session_store::FileSessionStore::new(root.join("sessions")).unwrap(),
+        proxy: None,
+        login_throttle: crate::throttle::LoginThrottle::new(Default::default()),
+        audit: crate::audit::AuditLog::new(root.join("audit.log")),
+        uploads: Default::default(),
+        thumbnails: crate::thumbnails::ThumbnailCache::new(root.join("thumbnails")),
+    })
+}
+
+pub fn set_cookie<'a>(resp: &'a axum::response::Response, name: &str) -> Option<&'a str> {
+    resp.headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|value| value.to_str().unwrap().split(';').next().unwrap())
+        .find(|cookie| cookie.starts_with(&format!("{}=", name)))
+}
+
+/// Logs in as alice and returns a `Cookie` header value carrying both the
+/// session and the CSRF cookie.
+pub async fn login_cookie(app: &axum::Router) -> String {
+    login_as(app, "alice", "pw").await
+}
+
+pub async fn login_as(app: &axum::Router, username: &str, password: &str) -> String {
+    let req = axum::http::Request::post("/api/login")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "username": username, "password": password }).to_string(),
+        ))
+        .unwrap();
+    let resp = app.clone().oneshot(req).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let session = set_cookie(&resp, "id").unwrap().to_string();
+    with_csrf_cookie(app, session).await
+}
+
+/// Adds the CSRF cookie the server hands out on the first API request.
+pub async fn with_csrf_cookie(app: &axum::Router, session: String) -> String {
+    let req = axum::http::Request::get("/api/tree")
+        .header(header::COOKIE, &session)
+        .body(Body::empty())
+        .unwrap();
+    let resp = app.clone().oneshot(req).await.unwrap();
+    let csrf = set_cookie(&resp, csrf::CSRF_COOKIE).unwrap();
+    format!("{}; {}", session, csrf)
+}
+
+pub fn csrf_token(cookie: &str) -> &str {
+    cookie
+        .split("; ")
+        .find_map(|c| c.strip_prefix("XSRF-TOKEN="))
+        .unwrap_or_default()
+}
+
+pub async fn send(app: &axum::Router, cookie: &str, method: Method, uri: &str) -> StatusCode {
+    let req = axum::http::Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::COOKIE, cookie)
+        .header(csrf::CSRF_HEADER, csrf_token(cookie))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"path":"index.md","content":"changed"}"#))
+        .unwrap();
+    app.clone().oneshot(req).await.unwrap().status()
+}
+
+pub async fn bearer(app: &axum::Router, token: &str, method: Method, uri: &str) -> StatusCode {
+    let req = axum::http::Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            r#"{"path":"index.md","content":"changed","name":"minted"}"#,
+        ))
+        .unwrap();
+    app.clone().oneshot(req).await.unwrap().status()
+}
+
+pub async fn post_json(
+    app: &axum::Router,
+    cookie: &str,
+    uri: &str,
+    body: String,
+) -> (StatusCode, serde_json::Value) {
+    send_json(app, cookie, Method::POST, uri, body).await
+}
+
+pub async fn send_json(
+    app: &axum::Router,
+    cookie: &str,
+    method: Method,
+    uri: &str,
+    body: String,
+) -> (StatusCode, serde_json::Value) {
+    let req = axum::http::Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::COOKIE, cookie)
+        .header(csrf::CSRF_HEADER, csrf_token(cookie))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let resp = app.clone().oneshot(req).await.unwrap();
+    let status = resp.status();
+    let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null),
+    )
+}
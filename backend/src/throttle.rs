@@ -0,0 +1,309 @@
+//! Brute-force protection for the login endpoints.
+//!
+//! Failures are counted per username and per client address. After a few free
+//! attempts each further failure doubles the wait before the next attempt, and
+//! reaching the limit locks the key out for a fixed period.
+
+use crate::proxy_auth::TrustedSource;
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Entries are kept this long after their last failure.
+const FORGET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+/// Pruning only runs once the table grows past this many entries.
+const PRUNE_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct ThrottleConfig {
+    /// Failures allowed before any delay applies.
+    pub free_attempts: u32,
+    /// Failures per username before a lockout.
+    pub max_user_failures: u32,
+    /// Failures per client address before a lockout. Higher than the per-user
+    /// limit because several people can share an address.
+    pub max_ip_failures: u32,
+    pub lockout: Duration,
+    pub max_backoff: Duration,
+    /// Proxies whose `X-Forwarded-For` header names the real client.
+    trusted_proxies: Vec<TrustedSource>,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            max_user_failures: 10,
+            max_ip_failures: 50,
+            lockout: Duration::from_secs(15 * 60),
+            max_backoff: Duration::from_secs(5 * 60),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl ThrottleConfig {
+    /// Reads `LOGIN_MAX_FAILURES`, `LOGIN_MAX_FAILURES_PER_IP`,
+    /// `LOGIN_LOCKOUT_SECS` and `TRUSTED_PROXIES`, keeping defaults for unset values.
+    pub fn from_env() -> Result<Self, String> {
+        fn number(name: &str) -> Result<Option<u64>, String> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("{} must be a number", name)),
+                Err(_) => Ok(None),
+            }
+        }
+
+        let mut config = Self::default();
+        if let Some(n) = number("LOGIN_MAX_FAILURES")? {
+            config.max_user_failures = n as u32;
+        }
+        if let Some(n) = number("LOGIN_MAX_FAILURES_PER_IP")? {
+            config.max_ip_failures = n as u32;
+        }
+        if let Some(secs) = number("LOGIN_LOCKOUT_SECS")? {
+            config.lockout = Duration::from_secs(secs);
+        }
+        if let Ok(spec) = std::env::var("TRUSTED_PROXIES") {
+            config.trusted_proxies = TrustedSource::parse_list(&spec)?;
+        }
+        Ok(config)
+    }
+
+    /// The client address: the peer, or the last `X-Forwarded-For` hop when
+    /// the peer is a trusted proxy.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+        let peer = peer?.ip().to_canonical();
+        if !self.trusted_proxies.iter().any(|t| t.contains(peer)) {
+            return Some(peer);
+        }
+        headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+            .map(|ip| ip.to_canonical())
+            .or(Some(peer))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    User(String),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone)]
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// Whether a failure just locked the username or address out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureOutcome {
+    Counted,
+    LockedOut(Duration),
+}
+
+pub struct LoginThrottle {
+    config: ThrottleConfig,
+    entries: Mutex<HashMap<Key, Failures>>,
+}
+
+impl LoginThrottle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &ThrottleConfig {
+        &self.config
+    }
+
+    fn keys(username: &str, ip: Option<IpAddr>) -> Vec<Key> {
+        let mut keys = vec![Key::User(username.to_lowercase())];
+        keys.extend(ip.map(Key::Ip));
+        keys
+    }
+
+    /// `Err(retry_after)` while either the username or the address must wait.
+    pub fn check(&self, username: &str, ip: Option<IpAddr>) -> Result<(), Duration> {
+        self.check_at(username, ip, Instant::now())
+    }
+
+    fn check_at(&self, username: &str, ip: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let entries = self.entries.lock().unwrap();
+        let wait = Self::keys(username, ip)
+            .iter()
+            .filter_map(|key| entries.get(key)?.blocked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+            .max();
+        match wait {
+            Some(wait) => Err(wait),
+            None => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, username: &str, ip: Option<IpAddr>) -> FailureOutcome {
+        self.record_failure_at(username, ip, Instant::now())
+    }
+
+    fn record_failure_at(
+        &self,
+        username: &str,
+        ip: Option<IpAddr>,
+        now: Instant,
+    ) -> FailureOutcome {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() > PRUNE_THRESHOLD {
+            entries.retain(|_, f| now.duration_since(f.last_failure) < FORGET_AFTER);
+        }
+
+        let mut outcome = FailureOutcome::Counted;
+        for key in Self::keys(username, ip) {
+            let limit = match key {
+                Key::User(_) => self.config.max_user_failures,
+                Key::Ip(_) => self.config.max_ip_failures,
+            };
+            let failures = entries.entry(key).or_insert(Failures {
+                count: 0,
+                last_failure: now,
+                blocked_until: None,
+            });
+            // A lockout that has run its course, or a quiet spell as long as
+            // one, starts the count again.
+            let idle = now.duration_since(failures.last_failure) >= self.config.lockout;
+            let blocked = failures.blocked_until.is_some_and(|until| until > now);
+            if !blocked && (failures.count >= limit || idle) {
+                failures.count = 0;
+            }
+            failures.count += 1;
+            failures.last_failure = now;
+
+            if failures.count >= limit {
+                failures.blocked_until = Some(now + self.config.lockout);
+                outcome = FailureOutcome::LockedOut(self.config.lockout);
+            } else if failures.count > self.config.free_attempts {
+                let doublings = (failures.count - self.config.free_attempts - 1).min(16);
+                let backoff = Duration::from_secs(1 << doublings).min(self.config.max_backoff);
+                failures.blocked_until = Some(now + backoff);
+            }
+        }
+        outcome
+    }
+
+    /// Clears the username's failures. The address keeps its count so that one
+    /// known password cannot be used to reset guessing against other accounts.
+    pub fn record_success(&self, username: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&Key::User(username.to_lowercase()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(ThrottleConfig {
+            max_user_failures: 6,
+            max_ip_failures: 8,
+            ..ThrottleConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_backoff_then_lockout() {
+        let throttle = throttle();
+        let ip = Some("203.0.113.9".parse().unwrap());
+        let t0 = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(
+                throttle.record_failure_at("alice", ip, t0),
+                FailureOutcome::Counted
+            );
+        }
+        assert!(throttle.check_at("alice", ip, t0).is_ok());
+
+        // Fourth failure: 1s, fifth: 2s.
+        throttle.record_failure_at("alice", ip, t0);
+        assert_eq!(
+            throttle.check_at("alice", ip, t0),
+            Err(Duration::from_secs(1))
+        );
+        throttle.record_failure_at("Alice", ip, t0);
+        assert_eq!(
+            throttle.check_at("alice", None, t0),
+            Err(Duration::from_secs(2))
+        );
+        assert!(throttle
+            .check_at("alice", ip, t0 + Duration::from_secs(2))
+            .is_ok());
+
+        let t1 = t0 + Duration::from_secs(3);
+        assert_eq!(
+            throttle.record_failure_at("alice", ip, t1),
+            FailureOutcome::LockedOut(Duration::from_secs(15 * 60))
+        );
+        assert!(throttle
+            .check_at("alice", None, t1 + Duration::from_secs(60))
+            .is_err());
+        assert!(throttle
+            .check_at("alice", None, t1 + Duration::from_secs(15 * 60))
+            .is_ok());
+
+        // Other users from the same address are throttled by the address count.
+        assert!(throttle.check_at("bob", None, t1).is_ok());
+        assert!(throttle.check_at("bob", ip, t1).is_err());
+
+        throttle.record_success("alice");
+        assert!(throttle.check_at("alice", None, t1).is_ok());
+    }
+
+    #[test]
+    fn test_ip_lockout_applies_to_every_username() {
+        let throttle = throttle();
+        let ip = Some("203.0.113.9".parse().unwrap());
+        let t0 = Instant::now();
+        let mut outcome = FailureOutcome::Counted;
+        for i in 0..8 {
+            outcome = throttle.record_failure_at(&format!("user{}", i), ip, t0);
+        }
+        assert!(matches!(outcome, FailureOutcome::LockedOut(_)));
+        assert!(throttle.check_at("someone-else", ip, t0).is_err());
+        assert!(throttle.check_at("someone-else", None, t0).is_ok());
+    }
+
+    #[test]
+    fn test_client_ip_uses_forwarded_for_from_trusted_proxies() {
+        let config = ThrottleConfig {
+            trusted_proxies: TrustedSource::parse_list("127.0.0.1").unwrap(),
+            ..ThrottleConfig::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "10.9.9.9, 198.51.100.7".parse().unwrap());
+        let peer = |ip: &str| Some(SocketAddr::new(ip.parse().unwrap(), 1234));
+
+        assert_eq!(
+            config.client_ip(&headers, peer("127.0.0.1")),
+            Some("198.51.100.7".parse().unwrap())
+        );
+        assert_eq!(
+            config.client_ip(&headers, peer("192.0.2.1")),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(config.client_ip(&headers, None), None);
+    }
+}
@@ -0,0 +1,499 @@
+//! Resized copies of wiki images, made on demand for `?w=`, `?h=` and
+//! `?format=` and cached on disk outside the volumes, so they never show up
+//! in git. Variants are turned upright according to the photo's EXIF
+//! orientation and carry no metadata.
+
+use crate::atomic;
+use crate::tokens::ApiResult;
+use axum::http::StatusCode;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageReader, Limits};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tokio::sync::Semaphore;
+
+/// The sizes variants are made in. Requested sizes are rounded up to one of
+/// these, so a handful of files per image is all the cache ever holds.
+pub const SIZES: &[u32] = &[160, 320, 480, 640, 960, 1280, 1920, 2560];
+/// Larger images are not decoded at all.
+const MAX_DIMENSION: u32 = 16384;
+const JPEG_QUALITY: u8 = 82;
+const WEBP_QUALITY: f32 = 80.0;
+
+/// The query parameters asking for a variant.
+#[derive(Deserialize, Default)]
+pub struct VariantParams {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    /// `webp`, `jpeg` or `png`; the format of the original if unset.
+    pub format: Option<String>,
+}
+
+impl VariantParams {
+    pub fn is_requested(&self) -> bool {
+        self.w.is_some() || self.h.is_some() || self.format.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Jpeg,
+    Png,
+    WebP,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "webp" => Some(Self::WebP),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::WebP => "image/webp",
+        }
+    }
+}
+
+/// Whether files with this extension can be resized. GIFs are left alone so
+/// they keep their animation.
+pub fn is_resizable(extension: &str) -> bool {
+    Format::from_name(extension).is_some()
+}
+
+/// A variant, ready to be served.
+pub enum Variant {
+    /// A file in the cache.
+    Cached(PathBuf),
+    /// A variant that could not be cached.
+    Uncached(Vec<u8>),
+}
+
+/// Where variants are kept, and how many may be made at once.
+pub struct ThumbnailCache {
+    dir: PathBuf,
+    renders: Semaphore,
+}
+
+impl ThumbnailCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let workers = std::thread::available_parallelism().map_or(2, |n| n.get());
+        Self {
+            dir: dir.into(),
+            renders: Semaphore::new(workers),
+        }
+    }
+
+    /// Uses `THUMBNAIL_CACHE_DIR`, defaulting to `thumbnails` in the working
+    /// directory.
+    pub fn from_env() -> Self {
+        let dir = std::env::var("THUMBNAIL_CACHE_DIR").unwrap_or_else(|_| "thumbnails".to_string());
+        Self::new(dir)
+    }
+
+    /// The variant of the image `file` (at `path` in `volume`) asked for by
+    /// `params`, with its content type. Made and cached on first use.
+    pub async fn variant(
+        &self,
+        volume: &str,
+        path: &str,
+        file: &Path,
+        params: &VariantParams,
+    ) -> ApiResult<(&'static str, Variant)> {
+        let source_ext = file
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let source_format = Format::from_name(source_ext).ok_or((
+            StatusCode::BAD_REQUEST,
+            "This file cannot be resized".to_string(),
+        ))?;
+        let format = match &params.format {
+            Some(name) => Format::from_name(name).ok_or((
+                StatusCode::BAD_REQUEST,
+                format!("Unsupported image format '{}'", name),
+            ))?,
+            None => source_format,
+        };
+        if params.w == Some(0) || params.h == Some(0) {
+            return Err((StatusCode::BAD_REQUEST, "Invalid image size".to_string()));
+        }
+        let (w, h) = (params.w.map(snap), params.h.map(snap));
+
+        let meta = tokio::fs::metadata(file)
+            .await
+            .map_err(|_| (StatusCode::NOT_FOUND, "File not found".to_string()))?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        // A new version of the image gets new variants; see `prune`.
+        let version = format!("{:x}-{:x}", modified, meta.len());
+        let dir = self.dir.join(image_key(volume, path));
+        let name = format!(
+            "{}-w{}-h{}.{}",
+            version,
+            w.unwrap_or(0),
+            h.unwrap_or(0),
+            format.extension()
+        );
+        let cached = dir.join(&name);
+        if tokio::fs::try_exists(&cached).await.unwrap_or(false) {
+            return Ok((format.mime(), Variant::Cached(cached)));
+        }
+
+        let _permit = self
+            .renders
+            .acquire()
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let source = file.to_path_buf();
+        let bytes = tokio::task::spawn_blocking(move || render(&source, w, h, format))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| render_error(path, e))?;
+
+        // The variant is served even if it cannot be cached.
+        let stored = async {
+            tokio::fs::create_dir_all(&dir).await?;
+            atomic::write(&cached, bytes.clone()).await?;
+            prune(&dir, &version).await
+        };
+        match stored.await {
+            Ok(()) => Ok((format.mime(), Variant::Cached(cached))),
+            Err(e) => {
+                eprintln!("Failed to cache a variant of {}:{}: {}", volume, path, e);
+                Ok((format.mime(), Variant::Uncached(bytes)))
+            }
+        }
+    }
+}
+
+/// The smallest of `SIZES` at least as large as `size`.
+fn snap(size: u32) -> u32 {
+    SIZES
+        .iter()
+        .copied()
+        .find(|&s| s >= size)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// The cache folder for an image.
+fn image_key(volume: &str, path: &str) -> String {
+    let digest = Sha256::digest(format!("{}\0{}", volume, path));
+    digest[..16].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Removes variants of earlier versions of an image.
+async fn prune(dir: &Path, version: &str) -> std::io::Result<()> {
+    let current = format!("{}-", version);
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with(&current) && !name.starts_with('.') {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+    Ok(())
+}
+
+fn render_error(path: &str, e: ImageError) -> (StatusCode, String) {
+    match e {
+        ImageError::IoError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        e => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Cannot resize '{}': {}", path, e),
+        ),
+    }
+}
+
+/// Decodes the image, turns it upright, shrinks it to fit within `w` x `h`
+/// (never enlarging it) and encodes it without any metadata.
+fn render(
+    file: &Path,
+    w: Option<u32>,
+    h: Option<u32>,
+    format: Format,
+) -> Result<Vec<u8>, ImageError> {
+    let mut reader = ImageReader::open(file)?.with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+
+    let width = w.unwrap_or(u32::MAX).min(img.width());
+    let height = h.unwrap_or(u32::MAX).min(img.height());
+    if width < img.width() || height < img.height() {
+        img = img.resize(width, height, FilterType::CatmullRom);
+    }
+
+    let mut out = Cursor::new(Vec::new());
+    match format {
+        Format::Jpeg => img
+            .into_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY))?,
+        Format::Png => img.write_with_encoder(PngEncoder::new(&mut out))?,
+        // Lossy, as lossless WebP makes photos larger than the JPEG they
+        // came from.
+        Format::WebP if img.color().has_alpha() => {
+            let rgba = img.into_rgba8();
+            let (width, height) = rgba.dimensions();
+            let encoded = webp::Encoder::from_rgba(&rgba, width, height).encode(WEBP_QUALITY);
+            out.get_mut().extend_from_slice(&encoded);
+        }
+        Format::WebP => {
+            let rgb = img.into_rgb8();
+            let (width, height) = rgb.dimensions();
+            let encoded = webp::Encoder::from_rgb(&rgb, width, height).encode(WEBP_QUALITY);
+            out.get_mut().extend_from_slice(&encoded);
+        }
+    }
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::http::header;
+    use image::{GenericImageView, RgbImage};
+    use tower::ServiceExt;
+
+    fn params(w: Option<u32>, h: Option<u32>, format: Option<&str>) -> VariantParams {
+        VariantParams {
+            w,
+            h,
+            format: format.map(str::to_string),
+        }
+    }
+
+    fn dimensions(variant: &Variant) -> (u32, u32) {
+        match variant {
+            Variant::Cached(path) => image::open(path).unwrap().dimensions(),
+            Variant::Uncached(bytes) => image::load_from_memory(bytes).unwrap().dimensions(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_variants_are_resized_and_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("photo.png");
+        RgbImage::new(400, 200).save(&file).unwrap();
+        let cache = ThumbnailCache::new(dir.path().join("cache"));
+
+        // 100 is rounded up to 160; the aspect ratio is kept.
+        let (mime, variant) = cache
+            .variant(
+                "personal",
+                "photo.png",
+                &file,
+                &params(Some(100), None, None),
+            )
+            .await
+            .unwrap();
+        assert_eq!(mime, "image/png");
+        assert_eq!(dimensions(&variant), (160, 80));
+        // Images are never enlarged.
+        let (mime, variant) = cache
+            .variant(
+                "personal",
+                "photo.png",
+                &file,
+                &params(None, Some(2000), Some("webp")),
+            )
+            .await
+            .unwrap();
+        assert_eq!(mime, "image/webp");
+        assert_eq!(dimensions(&variant), (400, 200));
+
+        let cached = || {
+            std::fs::read_dir(
+                dir.path()
+                    .join("cache")
+                    .join(image_key("personal", "photo.png")),
+            )
+            .unwrap()
+            .count()
+        };
+        assert_eq!(cached(), 2);
+
+        // A changed image replaces the old variants.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        RgbImage::new(300, 300).save(&file).unwrap();
+        let (_, variant) = cache
+            .variant(
+                "personal",
+                "photo.png",
+                &file,
+                &params(Some(160), None, None),
+            )
+            .await
+            .unwrap();
+        assert_eq!(dimensions(&variant), (160, 160));
+        assert_eq!(cached(), 1);
+
+        let bad = cache
+            .variant(
+                "personal",
+                "photo.png",
+                &file,
+                &params(Some(100), None, Some("bmp")),
+            )
+            .await;
+        assert_eq!(bad.err().map(|e| e.0), Some(StatusCode::BAD_REQUEST));
+    }
+
+    /// A JPEG with an APP1 segment saying it has to be rotated 90 degrees
+    /// clockwise to be shown upright.
+    fn rotated_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        RgbImage::new(width, height)
+            .write_with_encoder(JpegEncoder::new(&mut jpeg))
+            .unwrap();
+        let mut tiff = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        tiff.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0]);
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend(tiff);
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xff, 0xe1]);
+        out.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        out.extend(app1);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    #[tokio::test]
+    async fn test_variants_are_upright_and_without_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("phone.jpg");
+        std::fs::write(&file, rotated_jpeg(400, 200)).unwrap();
+        let cache = ThumbnailCache::new(dir.path().join("cache"));
+
+        let (mime, variant) = cache
+            .variant(
+                "personal",
+                "phone.jpg",
+                &file,
+                &params(Some(160), None, None),
+            )
+            .await
+            .unwrap();
+        assert_eq!(mime, "image/jpeg");
+        assert_eq!(dimensions(&variant), (160, 320));
+        let Variant::Cached(cached) = variant else {
+            panic!("the variant was not cached");
+        };
+        let bytes = std::fs::read(cached).unwrap();
+        assert!(!bytes.windows(4).any(|window| window == b"Exif"));
+
+        // Photos are converted to lossy WebP.
+        let (mime, variant) = cache
+            .variant(
+                "personal",
+                "phone.jpg",
+                &file,
+                &params(Some(160), None, Some("webp")),
+            )
+            .await
+            .unwrap();
+        assert_eq!(mime, "image/webp");
+        assert_eq!(dimensions(&variant), (160, 320));
+    }
+
+    #[test]
+    fn test_sizes_are_snapped() {
+        assert_eq!(snap(1), 160);
+        assert_eq!(snap(480), 480);
+        assert_eq!(snap(481), 640);
+        assert_eq!(snap(10_000), 2560);
+    }
+
+    #[tokio::test]
+    async fn test_image_variants_are_authorized_like_originals() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = test_state(dir.path());
+        for volume in ["personal", "secret"] {
+            image::RgbImage::new(640, 480)
+                .save(dir.path().join(volume).join("photo.png"))
+                .unwrap();
+        }
+        let app = crate::app(state);
+
+        let get = |uri: &str, cookie: Option<&str>| {
+            let mut req = axum::http::Request::get(uri);
+            if let Some(cookie) = cookie {
+                req = req.header(header::COOKIE, cookie);
+            }
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+
+        let uri = "/wiki/personal/photo.png?w=320&format=webp";
+        assert_eq!(
+            get(uri, None).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let cookie = login_cookie(&app).await;
+        let resp = get(uri, Some(&cookie)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/webp");
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let variant = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((variant.width(), variant.height()), (320, 240));
+        // Variants are revalidated rather than downloaded again.
+        let resp = get(uri, Some(&cookie)).await.unwrap();
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "private, no-cache");
+        let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+        let resp = app
+            .clone()
+            .oneshot(
+                axum::http::Request::get(uri)
+                    .header(header::COOKIE, &cookie)
+                    .header(header::IF_NONE_MATCH, etag)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        let resp = get("/wiki/secret/photo.png?w=320", Some(&cookie))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Variants are cached outside the volume.
+        assert!(dir.path().join("thumbnails").exists());
+        let personal: Vec<_> = std::fs::read_dir(dir.path().join("personal"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(personal.len(), 2);
+    }
+}
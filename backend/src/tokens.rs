@@ -0,0 +1,239 @@
+use crate::auth::AuthSource;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use common::auth::ApiTokenRecord;
+use common::{ApiToken, CreateTokenRequest, CreatedToken, User};
+use std::sync::Arc;
+
+pub(crate) type ApiResult<T> = Result<T, (StatusCode, String)>;
+
+/// Longest lifetime a token may be created with.
+const MAX_TOKEN_DAYS: i64 = 365 * 5;
+
+pub(crate) fn internal_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    eprintln!("Failed to update users file: {}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to update users file".to_string(),
+    )
+}
+
+pub(crate) fn no_account() -> (StatusCode, String) {
+    (
+        StatusCode::BAD_REQUEST,
+        "This requires an account in the users file".to_string(),
+    )
+}
+
+pub async fn list_tokens(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Json<Vec<ApiToken>> {
+    let tokens = state
+        .users
+        .with_user(&user.username, |record| {
+            record
+                .api_tokens
+                .iter()
+                .map(ApiTokenRecord::to_api_token)
+                .collect()
+        })
+        .await
+        .unwrap_or_default();
+    Json(tokens)
+}
+
+pub async fn create_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Json(payload): Json<CreateTokenRequest>,
+) -> ApiResult<Json<CreatedToken>> {
+    // A leaked token must not be able to mint longer-lived or wider ones.
+    if !source.is_interactive() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Tokens can only be created by a signed-in user".to_string(),
+        ));
+    }
+
+    if state
+        .users
+        .with_user(&user.username, |_| ())
+        .await
+        .is_none()
+    {
+        return Err(no_account());
+    }
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Token name is required".to_string(),
+        ));
+    }
+    if let Some(scope) = &payload.scope {
+        if let Some((volume, _)) = scope
+            .iter()
+            .find(|(_, mode)| mode.as_str() != "r" && mode.as_str() != "rw")
+        {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Scope for '{}' must be 'r' or 'rw'", volume),
+            ));
+        }
+    }
+
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let expires_at = match payload.expires_in_days {
+        Some(days) if !(1..=MAX_TOKEN_DAYS).contains(&days) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("expires_in_days must be between 1 and {}", MAX_TOKEN_DAYS),
+            ));
+        }
+        Some(days) => Some(now + days * 24 * 60 * 60),
+        None => None,
+    };
+
+    let (token, record) = ApiTokenRecord::generate(name, now, expires_at, payload.scope);
+    let info = record.to_api_token();
+    let username = user.username.clone();
+    let added = state
+        .users
+        .update(move |store| match store.users.get_mut(&username) {
+            Some(account) => {
+                account.api_tokens.retain(|t| !t.is_expired(now));
+                account.api_tokens.push(record);
+                true
+            }
+            None => false,
+        })
+        .await
+        .map_err(internal_error)?;
+    if !added {
+        return Err(no_account());
+    }
+
+    Ok(Json(CreatedToken { token, info }))
+}
+
+pub async fn revoke_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+) -> ApiResult<StatusCode> {
+    let known = state
+        .users
+        .with_user(&user.username, |record| {
+            record.api_tokens.iter().any(|t| t.id == id)
+        })
+        .await;
+    if known != Some(true) {
+        return Err((StatusCode::NOT_FOUND, "Token not found".to_string()));
+    }
+
+    let username = user.username.clone();
+    let removed = state
+        .users
+        .update(move |store| {
+            let Some(account) = store.users.get_mut(&username) else {
+                return false;
+            };
+            let before = account.api_tokens.len();
+            account.api_tokens.retain(|t| t.id != id);
+            account.api_tokens.len() != before
+        })
+        .await
+        .map_err(internal_error)?;
+
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, "Token not found".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::load_user_store;
+    use crate::csrf;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::http::{header, Method};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_api_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+
+        let req = axum::http::Request::post("/api/tokens")
+            .header(header::COOKIE, &cookie)
+            .header(csrf::CSRF_HEADER, csrf_token(&cookie))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"name":"cron","expires_in_days":30,"scope":{"personal":"r","work":"rw"}}"#,
+            ))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let created: common::CreatedToken = serde_json::from_slice(&bytes).unwrap();
+
+        // Stored hashed, and persisted to the users file.
+        let saved = load_user_store(&dir.path().join("users.json"), Some("test-secret")).unwrap();
+        let record = &saved.users["alice"].api_tokens[0];
+        assert_eq!(record.id, created.info.id);
+        assert!(!created.token.contains(&record.secret_hash));
+
+        let token = created.token.as_str();
+        let cases = [
+            (Method::GET, "/api/wiki/personal/index.md", StatusCode::OK),
+            (
+                Method::PUT,
+                "/api/wiki/personal/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            // Scope cannot widen the owner's read-only access.
+            (
+                Method::PUT,
+                "/api/wiki/work/index.md",
+                StatusCode::FORBIDDEN,
+            ),
+            (Method::POST, "/api/tokens", StatusCode::FORBIDDEN),
+        ];
+        for (method, uri, expected) in cases {
+            assert_eq!(
+                bearer(&app, token, method.clone(), uri).await,
+                expected,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+        assert_eq!(
+            bearer(&app, "wk_0000_bogus", Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let uri = format!("/api/tokens/{}", created.info.id);
+        assert_eq!(
+            send(&app, &cookie, Method::DELETE, &uri).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            bearer(&app, token, Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,425 @@
+//! Deleted files and folders, kept in a `.trash` folder at the root of each
+//! volume until they are restored, purged, or outlive the retention period.
+//!
+//! Each item is a folder `.trash/<id>/` holding the deleted file or folder as
+//! `data` and a `meta.json` describing where it came from. The trash is
+//! ignored by git and hidden from the normal wiki routes (see `acl`).
+
+use crate::acl::{normalize, relative_path, PathAcl};
+use crate::audit::Detail;
+use crate::tokens::ApiResult;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use common::TrashItem;
+use rand::Rng;
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const TRASH_DIR: &str = ".trash";
+const META_FILE: &str = "meta.json";
+const DATA_NAME: &str = "data";
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// Whether a volume-relative path points into the trash.
+pub fn is_trash_path(path: &str) -> bool {
+    normalize(path).split('/').next() == Some(TRASH_DIR)
+}
+
+/// Reads `TRASH_RETENTION_DAYS` (default 30). `0` keeps items until they are
+/// purged by hand.
+pub fn retention_from_env() -> Result<Option<Duration>, String> {
+    let days = match std::env::var("TRASH_RETENTION_DAYS") {
+        Ok(value) => value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("TRASH_RETENTION_DAYS must be a number, got '{}'", value))?,
+        Err(_) => DEFAULT_RETENTION_DAYS,
+    };
+    Ok((days > 0).then(|| Duration::from_secs(days * 24 * 60 * 60)))
+}
+
+/// The folder of one item, or `None` for ids that could not have been issued.
+fn item_dir(root: &FsPath, id: &str) -> Option<PathBuf> {
+    let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then(|| root.join(TRASH_DIR).join(id))
+}
+
+fn now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Moves `path` (relative to the volume root `root`) into the trash.
+pub async fn move_to_trash(
+    root: &FsPath,
+    path: &str,
+    is_dir: bool,
+    deleted_by: &str,
+) -> io::Result<TrashItem> {
+    let trash = root.join(TRASH_DIR);
+    tokio::fs::create_dir_all(&trash).await?;
+    let gitignore = trash.join(".gitignore");
+    if !tokio::fs::try_exists(&gitignore).await? {
+        tokio::fs::write(&gitignore, "*\n").await?;
+    }
+
+    let deleted_at = now();
+    let id = format!("{}-{:08x}", deleted_at, rand::thread_rng().gen::<u32>());
+    let dir = trash.join(&id);
+    tokio::fs::create_dir(&dir).await?;
+    let item = TrashItem {
+        id,
+        path: normalize(path),
+        is_dir,
+        deleted_by: deleted_by.to_string(),
+        deleted_at,
+    };
+    let meta = serde_json::to_vec_pretty(&item).map_err(io::Error::other)?;
+    tokio::fs::write(dir.join(META_FILE), meta).await?;
+    if let Err(e) = tokio::fs::rename(root.join(path), dir.join(DATA_NAME)).await {
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        return Err(e);
+    }
+    Ok(item)
+}
+
+async fn read_item(dir: &FsPath) -> Option<TrashItem> {
+    let meta = tokio::fs::read(dir.join(META_FILE)).await.ok()?;
+    serde_json::from_slice(&meta).ok()
+}
+
+/// Every item in the volume's trash, most recently deleted first.
+pub async fn list(root: &FsPath) -> io::Result<Vec<TrashItem>> {
+    let mut items = Vec::new();
+    let mut entries = match tokio::fs::read_dir(root.join(TRASH_DIR)).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(items),
+        Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(item) = read_item(&entry.path()).await {
+            items.push(item);
+        }
+    }
+    items.sort_by_key(|item| std::cmp::Reverse(item.deleted_at));
+    Ok(items)
+}
+
+/// Deletes items older than `retention` for good. Returns how many went.
+pub async fn purge_expired(root: &FsPath, retention: Duration) -> io::Result<usize> {
+    let cutoff = now() - retention.as_secs() as i64;
+    let mut purged = 0;
+    for item in list(root).await? {
+        if item.deleted_at < cutoff {
+            if let Some(dir) = item_dir(root, &item.id) {
+                tokio::fs::remove_dir_all(dir).await?;
+                purged += 1;
+            }
+        }
+    }
+    Ok(purged)
+}
+
+/// Whether the item and, for a folder, everything in it may be written at its
+/// original location. Walks the folder, so call it from blocking code.
+fn can_write_item(acl: &PathAcl, volume: &str, root: &FsPath, item: &TrashItem) -> bool {
+    if !acl.can_write(volume, &item.path) {
+        return false;
+    }
+    if !item.is_dir || !acl.restricts(volume) {
+        return true;
+    }
+    let data = root.join(TRASH_DIR).join(&item.id).join(DATA_NAME);
+    walkdir::WalkDir::new(&data)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .all(|entry| {
+            let inner = relative_path(&data, entry.path());
+            acl.can_write(volume, &format!("{}/{}", item.path, inner))
+        })
+}
+
+fn trash_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    eprintln!("Trash operation failed: {}", e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Trash operation failed".to_string(),
+    )
+}
+
+/// Looks up an item the caller may write, treating any other as missing.
+async fn writable_item(
+    acl: PathAcl,
+    volume: &str,
+    root: &FsPath,
+    id: &str,
+) -> ApiResult<TrashItem> {
+    let not_found = || (StatusCode::NOT_FOUND, "Item not found".to_string());
+    let dir = item_dir(root, id).ok_or_else(not_found)?;
+    let item = read_item(&dir).await.ok_or_else(not_found)?;
+    if !acl.can_read(volume, &item.path) {
+        return Err(not_found());
+    }
+    let allowed = tokio::task::spawn_blocking({
+        let (volume, root, item) = (volume.to_string(), root.to_path_buf(), item.clone());
+        move || can_write_item(&acl, &volume, &root, &item)
+    })
+    .await
+    .unwrap_or(false);
+    if !allowed {
+        return Err((StatusCode::FORBIDDEN, "Access denied".to_string()));
+    }
+    Ok(item)
+}
+
+/// Items in the volume's trash that the caller could read where they were.
+pub async fn list_trash(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path(volume): Path<String>,
+) -> ApiResult<Json<Vec<TrashItem>>> {
+    let root = state.volume_root(&volume)?;
+    let items = list(&root).await.map_err(trash_error)?;
+    Ok(Json(
+        items
+            .into_iter()
+            .filter(|item| acl.can_read(&volume, &item.path))
+            .collect(),
+    ))
+}
+
+/// Moves an item back to where it was deleted from.
+pub async fn restore_item(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path((volume, id)): Path<(String, String)>,
+) -> ApiResult<(Extension<Detail>, Json<TrashItem>)> {
+    let root = state.volume_root(&volume)?;
+    let item = writable_item(acl, &volume, &root, &id).await?;
+    let target = root.join(&item.path);
+    if tokio::fs::try_exists(&target).await.unwrap_or(true) {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("'{}' already exists", item.path),
+        ));
+    }
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(trash_error)?;
+    }
+    let dir = root.join(TRASH_DIR).join(&item.id);
+    tokio::fs::rename(dir.join(DATA_NAME), &target)
+        .await
+        .map_err(trash_error)?;
+    tokio::fs::remove_dir_all(&dir).await.map_err(trash_error)?;
+    Ok((Extension(Detail(item.path.clone())), Json(item)))
+}
+
+/// Deletes one item for good.
+pub async fn purge_item(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path((volume, id)): Path<(String, String)>,
+) -> ApiResult<(Extension<Detail>, StatusCode)> {
+    let root = state.volume_root(&volume)?;
+    let item = writable_item(acl, &volume, &root, &id).await?;
+    tokio::fs::remove_dir_all(root.join(TRASH_DIR).join(&item.id))
+        .await
+        .map_err(trash_error)?;
+    Ok((Extension(Detail(item.path)), StatusCode::NO_CONTENT))
+}
+
+/// Deletes for good every item the caller may write.
+pub async fn empty_trash(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path(volume): Path<String>,
+) -> ApiResult<(Extension<Detail>, StatusCode)> {
+    let root = state.volume_root(&volume)?;
+    let mut purged = 0;
+    for item in list(&root).await.map_err(trash_error)? {
+        if writable_item(acl.clone(), &volume, &root, &item.id)
+            .await
+            .is_ok()
+        {
+            tokio::fs::remove_dir_all(root.join(TRASH_DIR).join(&item.id))
+                .await
+                .map_err(trash_error)?;
+            purged += 1;
+        }
+    }
+    let detail = Detail(format!("{} item(s)", purged));
+    Ok((Extension(detail), StatusCode::NO_CONTENT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::http::Method;
+
+    #[test]
+    fn test_is_trash_path() {
+        assert!(is_trash_path(".trash"));
+        assert!(is_trash_path("/.trash/1-ab/data"));
+        assert!(is_trash_path("./.trash"));
+        assert!(!is_trash_path("notes/.trash"));
+        assert!(!is_trash_path(".trashy"));
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired_keeps_recent_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("old.md"), "old").unwrap();
+        std::fs::write(root.join("new.md"), "new").unwrap();
+        let old = move_to_trash(root, "old.md", false, "alice").await.unwrap();
+        move_to_trash(root, "new.md", false, "alice").await.unwrap();
+
+        let meta = root.join(TRASH_DIR).join(&old.id).join(META_FILE);
+        let aged = TrashItem {
+            deleted_at: old.deleted_at - 2 * 24 * 60 * 60,
+            ..old
+        };
+        std::fs::write(&meta, serde_json::to_vec(&aged).unwrap()).unwrap();
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(purge_expired(root, day).await.unwrap(), 1);
+        let left = list(root).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].path, "new.md");
+    }
+
+    #[tokio::test]
+    async fn test_deletes_go_to_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let personal = dir.path().join("personal");
+        std::fs::create_dir_all(personal.join("notes")).unwrap();
+        std::fs::write(personal.join("notes/a.md"), "draft").unwrap();
+
+        assert_eq!(
+            send(
+                &app,
+                &cookie,
+                Method::DELETE,
+                "/api/wiki/personal/notes/a.md"
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert!(!personal.join("notes/a.md").exists());
+        assert_eq!(
+            std::fs::read_to_string(personal.join(".trash/.gitignore")).unwrap(),
+            "*\n"
+        );
+
+        let (status, items) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/trash/personal",
+            String::new(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(items[0]["path"], "notes/a.md");
+        assert_eq!(items[0]["deleted_by"], "alice");
+        let id = items[0]["id"].as_str().unwrap().to_string();
+
+        // Trashed files are out of reach of the normal routes.
+        let trashed = format!("/api/wiki/personal/.trash/{}/data", id);
+        assert_eq!(
+            send(&app, &cookie, Method::GET, &trashed).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            send(&app, &cookie, Method::PUT, "/api/wiki/personal/.trash/x.md").await,
+            StatusCode::FORBIDDEN
+        );
+
+        let restore = format!("/api/trash/personal/{}/restore", id);
+        assert_eq!(
+            send(&app, &cookie, Method::POST, &restore).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            std::fs::read_to_string(personal.join("notes/a.md")).unwrap(),
+            "draft"
+        );
+        assert_eq!(
+            send(&app, &cookie, Method::POST, &restore).await,
+            StatusCode::NOT_FOUND
+        );
+
+        // Restoring over a file that has since been recreated is refused.
+        send(&app, &cookie, Method::DELETE, "/api/wiki/personal/notes").await;
+        std::fs::create_dir_all(personal.join("notes")).unwrap();
+        let (_, items) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/trash/personal",
+            String::new(),
+        )
+        .await;
+        assert_eq!(items[0]["is_dir"], true);
+        let id = items[0]["id"].as_str().unwrap().to_string();
+        assert_eq!(
+            send(
+                &app,
+                &cookie,
+                Method::POST,
+                &format!("/api/trash/personal/{}/restore", id)
+            )
+            .await,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            send(
+                &app,
+                &cookie,
+                Method::DELETE,
+                &format!("/api/trash/personal/{}", id)
+            )
+            .await,
+            StatusCode::NO_CONTENT
+        );
+
+        send(&app, &cookie, Method::DELETE, "/api/wiki/personal/index.md").await;
+        assert_eq!(
+            send(&app, &cookie, Method::DELETE, "/api/trash/personal").await,
+            StatusCode::NO_CONTENT
+        );
+        let (_, items) = send_json(
+            &app,
+            &cookie,
+            Method::GET,
+            "/api/trash/personal",
+            String::new(),
+        )
+        .await;
+        assert_eq!(items, serde_json::json!([]));
+
+        // Read-only volumes can be browsed but not emptied.
+        assert_eq!(
+            send(&app, &cookie, Method::GET, "/api/trash/work").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&app, &cookie, Method::DELETE, "/api/trash/work").await,
+            StatusCode::FORBIDDEN
+        );
+
+        let log = std::fs::read_to_string(dir.path().join("audit.log")).unwrap();
+        assert!(log.contains(r#""action":"trash.restore""#));
+        assert!(log.contains(r#""detail":"notes/a.md""#));
+    }
+}
@@ -0,0 +1,433 @@
+//! Folder listings for the file drawer, one folder (and optionally a few
+//! levels below it) and one page at a time, so large wikis are never walked
+//! in full just to show their top level.
+
+use crate::acl::{normalize, PathAcl};
+use crate::git::status_label;
+use crate::tokens::ApiResult;
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use common::{DirectoryListing, FileNode};
+use git2::{Repository, StatusOptions};
+use serde::Deserialize;
+use std::path::Path as FsPath;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+const DEFAULT_LIMIT: usize = 200;
+const MAX_LIMIT: usize = 1000;
+const MAX_DEPTH: usize = 3;
+
+#[derive(Deserialize, Default)]
+pub struct ListParams {
+    /// Levels of folders to list; 1 lists only the folder's own entries.
+    depth: Option<usize>,
+    offset: Option<usize>,
+    /// Entries per page, which also caps the entries listed in subfolders.
+    limit: Option<usize>,
+}
+
+/// Uncommitted changes, as paths relative to the volume root with their
+/// labels. Untracked folders are listed once, with a trailing `/`.
+type Changes = Vec<(String, &'static str)>;
+
+/// The changes to or inside `paths`, the entries of `dir`, or none if the
+/// volume is not a git repository. Only these paths are walked, not the rest
+/// of the volume.
+fn changes_in(root: &FsPath, dir: &str, paths: &[&str]) -> Changes {
+    if paths.is_empty() {
+        return Vec::new();
+    }
+    let Ok(repo) = Repository::open(root) else {
+        return Vec::new();
+    };
+    // Git only reports an untracked folder as a whole, so its entries would
+    // have no status of their own.
+    if !dir.is_empty() && is_untracked_folder(&repo, dir) {
+        return vec![(format!("{}/", dir), "New")];
+    }
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true);
+    // Literal paths, which libgit2 also uses to skip other folders.
+    opts.disable_pathspec_match(true);
+    for path in paths {
+        opts.pathspec(path);
+    }
+    let Ok(statuses) = repo.statuses(Some(&mut opts)) else {
+        return Vec::new();
+    };
+    statuses
+        .iter()
+        .filter_map(|entry| Some((entry.path()?.to_string(), status_label(entry.status()))))
+        .collect()
+}
+
+/// Whether nothing in the folder `dir` is tracked or ignored.
+fn is_untracked_folder(repo: &Repository, dir: &str) -> bool {
+    let tracked = repo
+        .index()
+        .is_ok_and(|index| index.find_prefix(format!("{}/", dir)).is_ok());
+    !tracked && !repo.is_path_ignored(dir).unwrap_or(true)
+}
+
+/// The status of `path`: its own change, `New` if it lies in an untracked
+/// folder, or `Modified` for a folder with changes inside.
+fn status_of(changes: &Changes, path: &str, is_dir: bool) -> Option<String> {
+    let mut inside = false;
+    for (changed, label) in changes {
+        let changed_path = changed.trim_end_matches('/');
+        if changed_path == path || (changed.ends_with('/') && path.starts_with(changed.as_str())) {
+            return Some(label.to_string());
+        }
+        inside |= is_dir
+            && changed_path
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('/'));
+    }
+    inside.then(|| "Modified".to_string())
+}
+
+/// An entry of a folder, before its metadata is read.
+struct Entry {
+    name: String,
+    path: String,
+    is_dir: bool,
+}
+
+fn child_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// The visible entries of a folder with their names and paths, unsorted.
+fn visible_entries<'a>(
+    root: &FsPath,
+    dir: &'a str,
+    visible: &'a dyn Fn(&str) -> bool,
+) -> impl Iterator<Item = (std::fs::DirEntry, String, String)> + 'a {
+    std::fs::read_dir(root.join(dir))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(move |entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Skip hidden files/dirs (like .git)
+            if name.starts_with('.') {
+                return None;
+            }
+            let path = child_path(dir, &name);
+            visible(&path).then_some((entry, name, path))
+        })
+}
+
+/// Whether an entry is a folder, or `None` if it is a broken link. Only
+/// symlinks are looked up, as the listing itself says what everything else is.
+fn is_dir(entry: &std::fs::DirEntry) -> Option<bool> {
+    let file_type = entry.file_type().ok()?;
+    // Follows symlinks, as the rest of the wiki does.
+    if file_type.is_symlink() {
+        Some(std::fs::metadata(entry.path()).ok()?.is_dir())
+    } else {
+        Some(file_type.is_dir())
+    }
+}
+
+/// The visible entries of a folder, folders first.
+fn entries(root: &FsPath, dir: &str, visible: &dyn Fn(&str) -> bool) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = visible_entries(root, dir, visible)
+        .filter_map(|(entry, name, path)| {
+            let is_dir = is_dir(&entry)?;
+            Some(Entry { name, path, is_dir })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    entries
+}
+
+/// The node for an entry, with its size and modification time.
+fn node(root: &FsPath, entry: Entry) -> FileNode {
+    let meta = std::fs::metadata(root.join(&entry.path)).ok();
+    let modified = meta
+        .as_ref()
+        .and_then(|meta| meta.modified().ok())
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64);
+    FileNode {
+        size: meta
+            .as_ref()
+            .filter(|_| !entry.is_dir)
+            .map(|meta| meta.len()),
+        modified,
+        name: entry.name,
+        path: entry.path,
+        is_dir: entry.is_dir,
+        ..Default::default()
+    }
+}
+
+/// Fills in the status, entry count and, while `depth` allows, the first
+/// `limit` children of each node.
+fn fill(
+    root: &FsPath,
+    nodes: &mut [FileNode],
+    depth: usize,
+    limit: usize,
+    changes: &Changes,
+    visible: &dyn Fn(&str) -> bool,
+) {
+    for node in nodes {
+        node.git_status = status_of(changes, &node.path, node.is_dir);
+        if !node.is_dir {
+            continue;
+        }
+        if depth > 1 {
+            let children = entries(root, &node.path, visible);
+            node.child_count = Some(children.len());
+            let mut children: Vec<FileNode> = children
+                .into_iter()
+                .take(limit)
+                .map(|entry| self::node(root, entry))
+                .collect();
+            fill(root, &mut children, depth - 1, limit, changes, visible);
+            node.children = Some(children);
+        } else {
+            let count = visible_entries(root, &node.path, visible)
+                .filter(|(entry, _, _)| is_dir(entry).is_some())
+                .count();
+            node.child_count = Some(count);
+        }
+    }
+}
+
+fn list(
+    root: &FsPath,
+    dir: &str,
+    params: &ListParams,
+    visible: &dyn Fn(&str) -> bool,
+) -> DirectoryListing {
+    let depth = params.depth.unwrap_or(1).clamp(1, MAX_DEPTH);
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = params.offset.unwrap_or(0);
+
+    let all = entries(root, dir, visible);
+    let total = all.len();
+    let mut page: Vec<FileNode> = all
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|entry| node(root, entry))
+        .collect();
+    let paths: Vec<&str> = page.iter().map(|node| node.path.as_str()).collect();
+    let changes = changes_in(root, dir, &paths);
+    fill(root, &mut page, depth, limit, &changes, visible);
+    DirectoryListing {
+        path: dir.to_string(),
+        entries: page,
+        total,
+        next_offset: (offset + limit < total).then_some(offset + limit),
+    }
+}
+
+/// Lists the root folder of a volume.
+pub async fn list_root(
+    state: State<Arc<AppState>>,
+    acl: Extension<PathAcl>,
+    Path(volume): Path<String>,
+    params: Query<ListParams>,
+) -> ApiResult<Json<DirectoryListing>> {
+    list_directory(state, acl, Path((volume, String::new())), params).await
+}
+
+/// Lists a folder: `?depth=` levels deep (1 to 3, default 1), `?limit=`
+/// entries (at most 1000, default 200) from `?offset=`.
+pub async fn list_directory(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path((volume, path)): Path<(String, String)>,
+    Query(params): Query<ListParams>,
+) -> ApiResult<Json<DirectoryListing>> {
+    let root = state.volume_root(&volume)?;
+    if path.contains("..") {
+        return Err((StatusCode::FORBIDDEN, "Invalid path".to_string()));
+    }
+    let dir = normalize(&path);
+    if !tokio::fs::metadata(root.join(&dir))
+        .await
+        .is_ok_and(|meta| meta.is_dir())
+    {
+        return Err((StatusCode::NOT_FOUND, "Folder not found".to_string()));
+    }
+
+    let listing = tokio::task::spawn_blocking(move || {
+        list(&root, &dir, &params, &|path| acl.can_read(&volume, path))
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(listing))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::http::Method;
+
+    #[test]
+    fn test_statuses_of_files_and_folders() {
+        let changes: Changes = vec![
+            ("notes/a.md".to_string(), "Modified"),
+            ("drafts/".to_string(), "New"),
+        ];
+        assert_eq!(
+            status_of(&changes, "notes/a.md", false).as_deref(),
+            Some("Modified")
+        );
+        assert_eq!(
+            status_of(&changes, "notes", true).as_deref(),
+            Some("Modified")
+        );
+        assert_eq!(status_of(&changes, "notes-old", true), None);
+        assert_eq!(status_of(&changes, "drafts", true).as_deref(), Some("New"));
+        assert_eq!(
+            status_of(&changes, "drafts/x/y.md", false).as_deref(),
+            Some("New")
+        );
+        assert_eq!(status_of(&changes, "index.md", false), None);
+    }
+
+    #[test]
+    fn test_statuses_of_listed_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let repo = Repository::init(root).unwrap();
+        std::fs::create_dir_all(root.join("notes")).unwrap();
+        std::fs::create_dir_all(root.join("drafts")).unwrap();
+        for file in ["index.md", "notes/a.md", "notes/b.md"] {
+            std::fs::write(root.join(file), "x").unwrap();
+        }
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("alice", "alice").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+        std::fs::write(root.join("notes/a.md"), "changed").unwrap();
+        std::fs::write(root.join("drafts/new.md"), "x").unwrap();
+        let params = ListParams {
+            depth: Some(2),
+            ..Default::default()
+        };
+        let all = |_: &str| true;
+
+        let listing = list(root, "", &params, &all);
+        let statuses: Vec<(&str, Option<&str>)> = listing
+            .entries
+            .iter()
+            .map(|node| (node.path.as_str(), node.git_status.as_deref()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("drafts", Some("New")),
+                ("notes", Some("Modified")),
+                ("index.md", None)
+            ]
+        );
+
+        let listing = list(root, "drafts", &params, &all);
+        assert_eq!(listing.entries[0].git_status.as_deref(), Some("New"));
+        let listing = list(root, "notes", &params, &all);
+        let statuses: Vec<Option<&str>> = listing
+            .entries
+            .iter()
+            .map(|node| node.git_status.as_deref())
+            .collect();
+        assert_eq!(statuses, [Some("Modified"), None]);
+    }
+
+    #[test]
+    fn test_listings_are_paged_and_limited_in_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("b/c/d")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        for file in ["a.md", "z.md", "b/one.md", "b/two.md", "b/c/d/deep.md"] {
+            std::fs::write(root.join(file), "x").unwrap();
+        }
+        let params = |depth, offset, limit| ListParams {
+            depth: Some(depth),
+            offset: Some(offset),
+            limit: Some(limit),
+        };
+        let all = |_: &str| true;
+
+        let listing = list(root, "", &params(1, 0, 2), &all);
+        let names: Vec<&str> = listing.entries.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, ["b", "a.md"]);
+        assert_eq!((listing.total, listing.next_offset), (3, Some(2)));
+        assert_eq!(listing.entries[0].child_count, Some(3));
+        assert_eq!(listing.entries[0].children, None);
+        assert_eq!(listing.entries[1].size, Some(1));
+        assert!(listing.entries[1].modified.is_some());
+
+        let listing = list(root, "", &params(1, 2, 2), &all);
+        assert_eq!(listing.entries[0].path, "z.md");
+        assert_eq!(listing.next_offset, None);
+
+        let listing = list(root, "b", &params(2, 0, 10), &|path: &str| {
+            path != "b/two.md"
+        });
+        let c = &listing.entries[0];
+        assert_eq!((c.path.as_str(), c.child_count), ("b/c", Some(1)));
+        let d = &c.children.as_ref().unwrap()[0];
+        assert_eq!(
+            (d.path.as_str(), d.child_count, &d.children),
+            ("b/c/d", Some(1), &None)
+        );
+        assert_eq!(listing.total, 2);
+    }
+
+    #[tokio::test]
+    async fn test_folders_are_listed_with_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+        let personal = dir.path().join("personal");
+        std::fs::create_dir_all(personal.join("notes")).unwrap();
+        std::fs::write(personal.join("notes/a.md"), "hello").unwrap();
+        // Nothing is committed yet, so everything is new.
+        git2::Repository::init(&personal).unwrap();
+
+        let get = |uri: &'static str| send_json(&app, &cookie, Method::GET, uri, String::new());
+        let (status, listing) = get("/api/list/personal?depth=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listing["total"], 2);
+        let notes = &listing["entries"][0];
+        assert_eq!(notes["path"], "notes");
+        assert_eq!(notes["child_count"], 1);
+        assert_eq!(notes["git_status"], "New");
+        assert_eq!(notes["children"][0]["size"], 5);
+        assert_eq!(listing["entries"][1]["path"], "index.md");
+
+        let (status, listing) = get("/api/list/personal/notes?limit=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listing["entries"][0]["name"], "a.md");
+        assert_eq!(listing["next_offset"], serde_json::Value::Null);
+
+        assert_eq!(
+            get("/api/list/personal/missing").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(get("/api/list/secret").await.0, StatusCode::FORBIDDEN);
+    }
+}
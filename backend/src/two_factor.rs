@@ -0,0 +1,389 @@
+use crate::auth::{check_throttle, login_failed, start_session, AuthSource};
+use crate::sessions::SessionMeta;
+use crate::tokens::{internal_error, no_account, ApiResult};
+use crate::{audit, AppState};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use common::auth::TotpConfig;
+use common::{
+    totp, LoginResponse, RecoveryCodes, TotpCodeRequest, TotpEnrollment, TwoFactorStatus, User,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower_sessions::Session;
+
+const PENDING_LOGIN_KEY: &str = "pending_login";
+/// How long the code prompt stays valid after the password was accepted.
+const PENDING_LOGIN_SECS: i64 = 5 * 60;
+/// Wrong codes allowed before the password has to be entered again.
+const MAX_CODE_ATTEMPTS: u32 = 5;
+const TOTP_ISSUER: &str = "WebWiki";
+
+/// A login that passed the password check and is waiting for a second factor.
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    username: String,
+    stay_signed_in: bool,
+    expires_at: i64,
+    attempts: u32,
+}
+
+fn now() -> i64 {
+    time::OffsetDateTime::now_utc().unix_timestamp()
+}
+
+pub(crate) async fn begin_pending_login(
+    session: &Session,
+    username: &str,
+    stay_signed_in: bool,
+) -> Result<(), StatusCode> {
+    let pending = PendingLogin {
+        username: username.to_string(),
+        stay_signed_in,
+        expires_at: now() + PENDING_LOGIN_SECS,
+        attempts: 0,
+    };
+    session
+        .insert(PENDING_LOGIN_KEY, pending)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Second step of the login flow: checks a TOTP or recovery code for the
+/// user whose password was accepted by `auth::login`.
+pub async fn login_totp(
+    State(state): State<Arc<AppState>>,
+    session: Session,
+    headers: HeaderMap,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<Json<LoginResponse>, Response> {
+    let mut pending: PendingLogin = session
+        .get(PENDING_LOGIN_KEY)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?
+        .ok_or_else(|| StatusCode::UNAUTHORIZED.into_response())?;
+    if pending.expires_at < now() || pending.attempts >= MAX_CODE_ATTEMPTS {
+        let _ = session.remove_value(PENDING_LOGIN_KEY).await;
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr);
+    let ip = state.login_throttle.config().client_ip(&headers, peer);
+    check_throttle(&state, &pending.username, ip).await?;
+
+    let username = pending.username.clone();
+    let code = payload.code;
+    let user = state
+        .users
+        .update(move |store| {
+            let record = store.users.get_mut(&username)?;
+            record
+                .verify_second_factor(&code, now())
+                .then(|| record.to_user(&username))
+        })
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to update users file: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
+
+    let Some(user) = user else {
+        login_failed(&state, &pending.username, ip, "login.totp_failed").await;
+        pending.attempts += 1;
+        session
+            .insert(PENDING_LOGIN_KEY, pending)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    };
+
+    let _ = session.remove_value(PENDING_LOGIN_KEY).await;
+    state.login_throttle.record_success(&user.username);
+    state
+        .audit
+        .record(audit::event("login.success", Some(&user.username), ip))
+        .await;
+    let meta = SessionMeta::new(&headers, ip);
+    start_session(&session, user, pending.stay_signed_in, meta)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    Ok(Json(LoginResponse::default()))
+}
+
+/// Second-factor settings cannot be changed with an API token.
+fn require_interactive(source: AuthSource) -> ApiResult<()> {
+    if source.is_interactive() {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::FORBIDDEN,
+            "Two-factor settings can only be changed by a signed-in user".to_string(),
+        ))
+    }
+}
+
+fn invalid_code() -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, "Invalid code".to_string())
+}
+
+pub async fn status(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Json<TwoFactorStatus> {
+    let status = state
+        .users
+        .with_user(&user.username, |record| TwoFactorStatus {
+            enabled: record.totp_enabled(),
+            recovery_codes_remaining: record
+                .totp
+                .as_ref()
+                .map_or(0, |totp| totp.recovery_codes.len()),
+        })
+        .await
+        .unwrap_or(TwoFactorStatus {
+            enabled: false,
+            recovery_codes_remaining: 0,
+        });
+    Json(status)
+}
+
+/// Starts enrollment with a new secret. The second factor is only enforced
+/// once the user confirms it with a valid code.
+pub async fn enroll(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+) -> ApiResult<Json<TotpEnrollment>> {
+    require_interactive(source)?;
+    match state
+        .users
+        .with_user(&user.username, |record| record.totp_enabled())
+        .await
+    {
+        None => return Err(no_account()),
+        Some(true) => {
+            return Err((
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled".to_string(),
+            ))
+        }
+        Some(false) => {}
+    }
+
+    let secret = totp::generate_secret();
+    let username = user.username.clone();
+    let config = TotpConfig::new(secret.clone());
+    state
+        .users
+        .update(move |store| {
+            if let Some(record) = store.users.get_mut(&username) {
+                record.totp = Some(config);
+            }
+        })
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Json(TotpEnrollment {
+        otpauth_uri: totp::otpauth_uri(TOTP_ISSUER, &user.username, &secret),
+        secret,
+    }))
+}
+
+/// Enables the second factor after checking a code from the newly enrolled
+/// authenticator, and returns the one-time recovery codes.
+pub async fn confirm(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> ApiResult<Json<RecoveryCodes>> {
+    require_interactive(source)?;
+    let username = user.username.clone();
+    let codes = state
+        .users
+        .update(move |store| {
+            let totp = store
+                .users
+                .get_mut(&username)?
+                .totp
+                .as_mut()
+                .filter(|totp| !totp.confirmed)?;
+            if !totp.verify_code(&payload.code, now()) {
+                return None;
+            }
+            totp.confirmed = true;
+            Some(totp.regenerate_recovery_codes())
+        })
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(invalid_code)?;
+
+    Ok(Json(RecoveryCodes {
+        recovery_codes: codes,
+    }))
+}
+
+/// Replaces the recovery codes; needs a current code or an unused recovery code.
+pub async fn regenerate_recovery_codes(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> ApiResult<Json<RecoveryCodes>> {
+    require_interactive(source)?;
+    let username = user.username.clone();
+    let codes = state
+        .users
+        .update(move |store| {
+            let record = store.users.get_mut(&username)?;
+            if !record.verify_second_factor(&payload.code, now()) {
+                return None;
+            }
+            record
+                .totp
+                .as_mut()
+                .map(TotpConfig::regenerate_recovery_codes)
+        })
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(invalid_code)?;
+
+    Ok(Json(RecoveryCodes {
+        recovery_codes: codes,
+    }))
+}
+
+/// Turns the second factor off; needs a current code or an unused recovery code.
+pub async fn disable(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(source): Extension<AuthSource>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> ApiResult<StatusCode> {
+    require_interactive(source)?;
+    let username = user.username.clone();
+    let disabled = state
+        .users
+        .update(move |store| {
+            let Some(record) = store.users.get_mut(&username) else {
+                return false;
+            };
+            if !record.verify_second_factor(&payload.code, now()) {
+                return false;
+            }
+            record.totp = None;
+            true
+        })
+        .await
+        .map_err(internal_error)?;
+
+    if disabled {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(invalid_code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use axum::body::Body;
+    use axum::http::{header, Method};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_totp_login_flow() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = crate::app(test_state(dir.path()));
+        let cookie = login_cookie(&app).await;
+
+        let (status, enrollment) = post_json(&app, &cookie, "/api/2fa/enroll", String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(enrollment["otpauth_uri"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/WebWiki:alice?"));
+        let key = common::totp::base32_decode(enrollment["secret"].as_str().unwrap()).unwrap();
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let code = common::totp::code_at(&key, common::totp::step_at(now));
+
+        let (status, _) = post_json(
+            &app,
+            &cookie,
+            "/api/2fa/confirm",
+            r#"{"code":"000000x"}"#.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, codes) = post_json(
+            &app,
+            &cookie,
+            "/api/2fa/confirm",
+            format!(r#"{{"code":"{}"}}"#, code),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let recovery = codes["recovery_codes"][0].as_str().unwrap().to_string();
+
+        // The password alone no longer logs in.
+        let req = axum::http::Request::post("/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"username":"alice","password":"pw"}"#))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let pending = set_cookie(&resp, "id").unwrap().to_string();
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: common::LoginResponse = serde_json::from_slice(&bytes).unwrap();
+        assert!(body.totp_required);
+        assert_eq!(
+            send(&app, &pending, Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // The code used for enrollment cannot be replayed.
+        let (status, _) = post_json(
+            &app,
+            &pending,
+            "/api/login/totp",
+            format!(r#"{{"code":"{}"}}"#, code),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let req = axum::http::Request::post("/api/login/totp")
+            .header(header::COOKIE, &pending)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"code":"{}"}}"#, recovery)))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // Completing the login moves to a new session id.
+        let session = set_cookie(&resp, "id").unwrap().to_string();
+        assert_ne!(session, pending);
+        assert_eq!(
+            send(&app, &session, Method::GET, "/api/tree").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&app, &pending, Method::GET, "/api/tree").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let (status, _) = post_json(
+            &app,
+            "",
+            "/api/login/totp",
+            format!(r#"{{"code":"{}"}}"#, recovery),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,869 @@
+//! File uploads, streamed to disk from either a raw request body (one file,
+//! named by the URL) or a `multipart/form-data` form with any number of files
+//! (stored in the folder named by the URL).
+//!
+//! Each file is checked against the volume's size limit while it arrives, and
+//! its content against an allowlist of types once it has. Uploads never
+//! replace a file: identical content already in the folder is reused, and a
+//! name that is taken gets a number.
+
+use crate::acl::{normalize, PathAcl};
+use crate::atomic::write_error;
+use crate::audit::Detail;
+use crate::tokens::ApiResult;
+use crate::AppState;
+use axum::{
+    body::{Body, Bytes},
+    extract::{
+        multipart::{Field, MultipartError},
+        FromRequest, Multipart, Path, Request, State,
+    },
+    http::{header, StatusCode},
+    Extension, Json,
+};
+use common::UploadedFile;
+use http_body_util::BodyExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+const MB: u64 = 1024 * 1024;
+const DEFAULT_MAX_MB: u64 = 25;
+const DEFAULT_MAX_REQUEST_MB: u64 = 100;
+const DEFAULT_ALLOWED_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "text/plain",
+    "text/markdown",
+    "text/csv",
+    "audio/*",
+    "video/*",
+];
+/// How much of a file is looked at to tell its type.
+const SNIFF_BYTES: usize = 8192;
+
+/// Limits on what may be uploaded.
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub max_bytes: u64,
+    /// Overrides `max_bytes` for some volumes.
+    pub volume_max_bytes: HashMap<String, u64>,
+    /// The most all files of one request may add up to.
+    pub max_request_bytes: u64,
+    /// MIME types, or `type/*` for a whole family.
+    pub allowed_types: Vec<String>,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_MB * MB,
+            volume_max_bytes: HashMap::new(),
+            max_request_bytes: DEFAULT_MAX_REQUEST_MB * MB,
+            allowed_types: DEFAULT_ALLOWED_TYPES
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl UploadConfig {
+    /// Reads `UPLOAD_MAX_MB`, `UPLOAD_MAX_MB_PER_VOLUME` (JSON such as
+    /// `{"work": 100}`), `UPLOAD_MAX_REQUEST_MB` and `UPLOAD_ALLOWED_TYPES`,
+    /// keeping defaults for unset values.
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Ok(value) = std::env::var("UPLOAD_MAX_MB") {
+            let mb: u64 = value
+                .trim()
+                .parse()
+                .map_err(|_| "UPLOAD_MAX_MB must be a number".to_string())?;
+            config.max_bytes = mb * MB;
+        }
+        if let Ok(value) = std::env::var("UPLOAD_MAX_REQUEST_MB") {
+            let mb: u64 = value
+                .trim()
+                .parse()
+                .map_err(|_| "UPLOAD_MAX_REQUEST_MB must be a number".to_string())?;
+            config.max_request_bytes = mb * MB;
+        }
+        if let Ok(value) = std::env::var("UPLOAD_MAX_MB_PER_VOLUME") {
+            let limits: HashMap<String, u64> = serde_json::from_str(&value)
+                .map_err(|e| format!("Invalid JSON in UPLOAD_MAX_MB_PER_VOLUME: {}", e))?;
+            config.volume_max_bytes = limits
+                .into_iter()
+                .map(|(volume, mb)| (volume, mb * MB))
+                .collect();
+        }
+        if let Ok(value) = std::env::var("UPLOAD_ALLOWED_TYPES") {
+            config.allowed_types = value
+                .split(',')
+                .map(|t| t.trim().to_ascii_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect();
+        }
+        Ok(config)
+    }
+
+    pub fn max_bytes(&self, volume: &str) -> u64 {
+        self.volume_max_bytes
+            .get(volume)
+            .copied()
+            .unwrap_or(self.max_bytes)
+    }
+
+    pub fn allows(&self, mime: &str) -> bool {
+        let family = mime.split('/').next().unwrap_or(mime);
+        self.allowed_types.iter().any(|allowed| {
+            allowed == mime || allowed == "*/*" || allowed.strip_suffix("/*") == Some(family)
+        })
+    }
+}
+
+/// The type of a file from its first bytes, for the formats uploads usually
+/// come in. Valid UTF-8 without NUL bytes counts as text. `complete` says
+/// whether `head` is the whole file.
+fn sniff(head: &[u8], complete: bool) -> Option<&'static str> {
+    let starts = |magic: &[u8]| head.starts_with(magic);
+    let at = |range: std::ops::Range<usize>| head.get(range).unwrap_or_default();
+    let sniffed = if starts(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if starts(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        "image/gif"
+    } else if starts(b"RIFF") && at(8..12) == b"WEBP" {
+        "image/webp"
+    } else if starts(b"RIFF") && at(8..12) == b"WAVE" {
+        "audio/wav"
+    } else if starts(b"%PDF-") {
+        "application/pdf"
+    } else if at(4..8) == b"ftyp" {
+        match at(8..12) {
+            b"M4A " | b"M4B " => "audio/mp4",
+            b"qt  " => "video/quicktime",
+            _ => "video/mp4",
+        }
+    } else if starts(b"\x1a\x45\xdf\xa3") {
+        "video/webm"
+    } else if starts(b"OggS") {
+        "audio/ogg"
+    } else if starts(b"fLaC") {
+        "audio/flac"
+    } else if starts(b"ID3") || (head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0) {
+        "audio/mpeg"
+    } else {
+        let text = !head.contains(&0)
+            && match std::str::from_utf8(head) {
+                Ok(_) => true,
+                // The head may end part way through a character.
+                Err(e) => !complete && e.error_len().is_none(),
+            };
+        return text.then_some("text/plain");
+    };
+    Some(sniffed)
+}
+
+/// The type to report for a file: what its content looks like, provided
+/// that agrees with its extension and is allowed.
+fn check_type(
+    config: &UploadConfig,
+    name: &str,
+    head: &[u8],
+    complete: bool,
+) -> ApiResult<&'static str> {
+    let rejected = |reason: String| Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, reason));
+    let by_name = mime_guess::from_path(name).first_raw();
+    let mime = match (sniff(head, complete), by_name) {
+        (None, _) => return rejected(format!("'{}' is not a supported kind of file", name)),
+        // Text is whatever its extension says, unless that is a binary format.
+        (Some("text/plain"), Some(by_name)) if sniff_family(by_name) => {
+            return rejected(format!("'{}' does not look like a {} file", name, by_name))
+        }
+        (Some("text/plain"), by_name) => by_name.unwrap_or("text/plain"),
+        (Some(sniffed), Some(by_name)) if same_format(sniffed, by_name) => sniffed,
+        (Some(sniffed), _) => {
+            return rejected(format!(
+                "'{}' contains {} but its name says otherwise",
+                name, sniffed
+            ))
+        }
+    };
+    if !config.allows(mime) {
+        return rejected(format!("{} files may not be uploaded", mime));
+    }
+    Ok(mime)
+}
+
+/// Whether `sniff` can recognise files of this type by their content.
+fn sniff_family(mime: &str) -> bool {
+    mime.starts_with("image/") && mime != "image/svg+xml"
+        || mime.starts_with("audio/")
+        || mime.starts_with("video/")
+        || mime == "application/pdf"
+}
+
+/// Whether a sniffed type and the type named by an extension are the same
+/// format. Audio and video containers go by several names.
+fn same_format(sniffed: &str, by_name: &str) -> bool {
+    let family = |mime: &str| mime.split('/').next().unwrap_or_default().to_string();
+    sniffed == by_name
+        || (family(sniffed) == family(by_name)
+            && matches!(family(sniffed).as_str(), "audio" | "video"))
+        || (sniffed == "video/mp4" && by_name == "audio/mp4")
+}
+
+/// The name a file is stored under, from the one the client sent, which
+/// browsers on some systems send with a path.
+fn file_name(sent: &str) -> ApiResult<String> {
+    let name = sent.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() || name.starts_with('.') || name.chars().any(char::is_control) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid file name '{}'", sent),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// `name` with `-n` before its extension.
+fn numbered(name: &str, n: u32) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}-{}.{}", stem, n, ext),
+        _ => format!("{}-{}", name, n),
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// `path` as an absolute URL path. Every segment is percent-encoded, so
+/// characters such as `#`, `?`, `%` or `)` stay part of the file name.
+fn url_path(path: &str) -> String {
+    let mut url = String::new();
+    for segment in path.split('/') {
+        url.push('/');
+        for byte in segment.bytes() {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                url.push(byte as char);
+            } else {
+                url.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    url
+}
+
+/// The Markdown that shows (for images) or links to the stored file.
+fn markdown(name: &str, path: &str, mime: &str) -> String {
+    let label = name.replace(['[', ']'], "");
+    let url = url_path(path);
+    if mime.starts_with("image/") {
+        format!("![{}]({})", label, url)
+    } else {
+        format!("[{}]({})", label, url)
+    }
+}
+
+fn read_error(e: impl std::fmt::Display) -> (StatusCode, String) {
+    (
+        StatusCode::BAD_REQUEST,
+        format!("Failed to read the upload: {}", e),
+    )
+}
+
+fn multipart_error(e: MultipartError) -> (StatusCode, String) {
+    (e.status(), e.body_text())
+}
+
+/// Where the content of one file comes from.
+enum Source<'a, 'f> {
+    Raw(&'a mut Body),
+    Part(&'a mut Field<'f>),
+}
+
+impl Source<'_, '_> {
+    /// The next piece of the file, or `None` at its end.
+    async fn chunk(&mut self) -> ApiResult<Option<Bytes>> {
+        match self {
+            Source::Raw(body) => {
+                // Skip trailers.
+                while let Some(frame) = body.frame().await {
+                    if let Ok(data) = frame.map_err(read_error)?.into_data() {
+                        return Ok(Some(data));
+                    }
+                }
+                Ok(None)
+            }
+            Source::Part(field) => field.chunk().await.map_err(multipart_error),
+        }
+    }
+}
+
+/// Where uploads of one request go, and what they may be.
+struct Target<'a> {
+    root: PathBuf,
+    volume: &'a str,
+    acl: &'a PathAcl,
+    config: &'a UploadConfig,
+    max_bytes: u64,
+}
+
+fn request_too_large(config: &UploadConfig) -> (StatusCode, String) {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!(
+            "The files add up to more than the {} MB limit for one upload",
+            config.max_request_bytes / MB
+        ),
+    )
+}
+
+fn hash_file(path: &FsPath) -> io::Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// A file in `dir` (preferring `name`) with exactly this content.
+fn find_duplicate(dir: &FsPath, name: &str, size: u64, hash: &[u8]) -> Option<String> {
+    let mut candidates: Vec<String> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .metadata()
+                .is_ok_and(|meta| meta.is_file() && meta.len() == size)
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|candidate| !candidate.starts_with('.'))
+        .collect();
+    candidates.sort_by_key(|candidate| candidate != name);
+    candidates
+        .into_iter()
+        .find(|candidate| hash_file(&dir.join(candidate)).is_ok_and(|h| h == hash))
+}
+
+/// Gives the finished upload at `temp` a name in `dir` that is not taken:
+/// `name`, else `name-1.ext`, `name-2.ext`... Hard links make the check and
+/// the claim one step where the file system supports them.
+fn place(
+    temp: &FsPath,
+    dir: &FsPath,
+    name: &str,
+    writable: impl Fn(&str) -> bool,
+) -> io::Result<String> {
+    for n in 0..1000 {
+        let candidate = if n == 0 {
+            name.to_string()
+        } else {
+            numbered(name, n)
+        };
+        if !writable(&candidate) {
+            continue;
+        }
+        let dest = dir.join(&candidate);
+        match std::fs::hard_link(temp, &dest) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(_) if !dest.exists() => {
+                std::fs::rename(temp, &dest)?;
+                return Ok(candidate);
+            }
+            Err(_) => continue,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("Too many files named like '{}'", name),
+    ))
+}
+
+/// Streams one file into `dir`, then checks and stores it. `received` counts
+/// the bytes of all files of the request so far.
+async fn store(
+    target: &Target<'_>,
+    dir: &str,
+    name: &str,
+    mut source: Source<'_, '_>,
+    received: &mut u64,
+) -> ApiResult<UploadedFile> {
+    if !target.acl.can_write(target.volume, &join(dir, name)) {
+        return Err((StatusCode::FORBIDDEN, "Access denied".to_string()));
+    }
+    let dir_path = target.root.join(dir);
+    tokio::fs::create_dir_all(&dir_path)
+        .await
+        .map_err(|e| write_error(&e))?;
+    // Hidden, so the tree and search skip it if a crash leaves it behind.
+    let temp = dir_path.join(format!(".{}.{:08x}.upload", name, rand::random::<u32>()));
+
+    let result = async {
+        let mut file = tokio::fs::File::create_new(&temp)
+            .await
+            .map_err(|e| write_error(&e))?;
+        let mut hasher = Sha256::new();
+        let mut head = Vec::new();
+        let mut size = 0u64;
+        while let Some(chunk) = source.chunk().await? {
+            size += chunk.len() as u64;
+            *received += chunk.len() as u64;
+            if *received > target.config.max_request_bytes {
+                return Err(request_too_large(target.config));
+            }
+            if size > target.max_bytes {
+                return Err((
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "'{}' is larger than the {} MB upload limit",
+                        name,
+                        target.max_bytes / MB
+                    ),
+                ));
+            }
+            let wanted = SNIFF_BYTES.saturating_sub(head.len()).min(chunk.len());
+            head.extend_from_slice(&chunk[..wanted]);
+            hasher.update(&chunk);
+            file.write_all(&chunk).await.map_err(|e| write_error(&e))?;
+        }
+        let mime = check_type(target.config, name, &head, size as usize == head.len())?;
+        file.sync_all().await.map_err(|e| write_error(&e))?;
+        drop(file);
+
+        let hash = hasher.finalize().to_vec();
+        let (temp, dir_path, name) = (temp.clone(), dir_path.clone(), name.to_string());
+        let (volume, dir, acl) = (
+            target.volume.to_string(),
+            dir.to_string(),
+            target.acl.clone(),
+        );
+        let (stored, deduplicated) = tokio::task::spawn_blocking(move || {
+            let readable = |candidate: &str| acl.can_read(&volume, &join(&dir, candidate));
+            if let Some(existing) = find_duplicate(&dir_path, &name, size, &hash) {
+                if readable(&existing) {
+                    return Ok((existing, true));
+                }
+            }
+            let writable = |candidate: &str| acl.can_write(&volume, &join(&dir, candidate));
+            place(&temp, &dir_path, &name, writable).map(|stored| (stored, false))
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|placed| placed)
+        .map_err(|e| write_error(&e))?;
+        Ok((stored, deduplicated, mime, size))
+    }
+    .await;
+    // After a hard link the temporary name is still there.
+    let _ = tokio::fs::remove_file(&temp).await;
+
+    let (stored, deduplicated, mime, size) = result?;
+    let path = join(dir, &stored);
+    Ok(UploadedFile {
+        name: name.to_string(),
+        markdown: markdown(&stored, &path, mime),
+        path,
+        size,
+        content_type: mime.to_string(),
+        deduplicated,
+    })
+}
+
+/// Removes the files a failed request stored, other than existing files it
+/// found the same content in.
+async fn discard(root: &FsPath, uploaded: &[UploadedFile]) {
+    for file in uploaded.iter().filter(|file| !file.deduplicated) {
+        let _ = tokio::fs::remove_file(root.join(&file.path)).await;
+    }
+}
+
+/// Stores the uploaded file(s). A multipart form puts every file part in the
+/// folder `path`; any other body is one file stored at `path`. Either every
+/// file is stored or none is.
+pub async fn upload_file(
+    State(state): State<Arc<AppState>>,
+    Extension(acl): Extension<PathAcl>,
+    Path((volume, path)): Path<(String, String)>,
+    request: Request,
+) -> ApiResult<(Extension<Detail>, Json<Vec<UploadedFile>>)> {
+    let root = state.volume_root(&volume)?;
+    if path.contains("..") {
+        return Err((StatusCode::FORBIDDEN, "Invalid path".to_string()));
+    }
+    let target = Target {
+        root: root.clone(),
+        volume: &volume,
+        acl: &acl,
+        config: &state.uploads,
+        max_bytes: state.uploads.max_bytes(&volume),
+    };
+    let path = normalize(&path);
+
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .trim_start()
+                .to_ascii_lowercase()
+                .starts_with("multipart/form-data")
+        });
+    let length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if length.is_some_and(|length| length > state.uploads.max_request_bytes) {
+        return Err(request_too_large(&state.uploads));
+    }
+    let mut uploaded = Vec::new();
+    let mut received = 0;
+    if is_form {
+        let mut form = Multipart::from_request(request, &state)
+            .await
+            .map_err(|e| (e.status(), e.body_text()))?;
+        let result = async {
+            while let Some(mut field) = form.next_field().await.map_err(multipart_error)? {
+                // Plain form fields carry no file.
+                let Some(sent) = field.file_name() else {
+                    continue;
+                };
+                let name = file_name(sent)?;
+                let source = Source::Part(&mut field);
+                uploaded.push(store(&target, &path, &name, source, &mut received).await?);
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            discard(&root, &uploaded).await;
+            return Err(e);
+        }
+        if uploaded.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "No files were sent".to_string()));
+        }
+    } else {
+        let (dir, sent) = path.rsplit_once('/').unwrap_or(("", &path));
+        let name = file_name(sent)?;
+        if length.is_some_and(|length| length > target.max_bytes) {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "'{}' is larger than the {} MB upload limit",
+                    name,
+                    target.max_bytes / MB
+                ),
+            ));
+        }
+        let mut body = request.into_body();
+        let source = Source::Raw(&mut body);
+        uploaded.push(store(&target, dir, &name, source, &mut received).await?);
+    }
+
+    let stored: Vec<&str> = uploaded.iter().map(|file| file.path.as_str()).collect();
+    let detail = Detail(stored.join(", "));
+    Ok((Extension(detail), Json(uploaded)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csrf;
+    use crate::test_support::*;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_sniffing_and_type_checks() {
+        let config = UploadConfig::default();
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(check_type(&config, "a.png", png, true), Ok("image/png"));
+        assert_eq!(
+            check_type(&config, "notes.md", b"# Hi", true),
+            Ok("text/markdown")
+        );
+        assert_eq!(
+            check_type(&config, "x.pdf", b"%PDF-1.7\n", false),
+            Ok("application/pdf")
+        );
+        // Renamed files and unknown or disallowed content are refused.
+        for (name, content) in [
+            ("a.jpg", &png[..]),
+            ("a.png", b"just text"),
+            ("page.html", b"<script>alert(1)</script>"),
+            ("a.svg", b"<svg onload='alert(1)'/>"),
+            ("a.bin", b"\0\x01\x02"),
+        ] {
+            let status = check_type(&config, name, content, true).map_err(|e| e.0);
+            assert_eq!(status, Err(StatusCode::UNSUPPORTED_MEDIA_TYPE), "{}", name);
+        }
+        // A head cut in the middle of a character is still text.
+        assert_eq!(
+            sniff("é".as_bytes().get(..1).unwrap(), false),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(file_name("C:\\Users\\me\\photo.png").unwrap(), "photo.png");
+        assert!(file_name("../.env").is_err());
+        assert_eq!(numbered("photo.png", 2), "photo-2.png");
+        assert_eq!(numbered("README", 1), "README-1");
+        assert_eq!(
+            markdown("my pic.png", "assets/my pic.png", "image/png"),
+            "![my pic.png](/assets/my%20pic.png)"
+        );
+        assert_eq!(
+            markdown("a.pdf", "docs/a.pdf", "application/pdf"),
+            "[a.pdf](/docs/a.pdf)"
+        );
+        assert_eq!(
+            markdown("notes#1.pdf", "docs/notes#1.pdf", "application/pdf"),
+            "[notes#1.pdf](/docs/notes%231.pdf)"
+        );
+        assert_eq!(
+            markdown("a?b%c.txt", "a?b%c.txt", "text/plain"),
+            "[a?b%c.txt](/a%3Fb%25c.txt)"
+        );
+        assert_eq!(
+            markdown("<x> (1.png", "img/<x> (1.png", "image/png"),
+            "![<x> (1.png](/img/%3Cx%3E%20%281.png)"
+        );
+        assert_eq!(
+            markdown("v).png", "v).png", "image/png"),
+            "![v).png](/v%29.png)"
+        );
+    }
+
+    async fn upload(
+        app: &axum::Router,
+        cookie: &str,
+        uri: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> (StatusCode, serde_json::Value) {
+        let req = axum::http::Request::post(uri)
+            .header(header::COOKIE, cookie)
+            .header(csrf::CSRF_HEADER, csrf_token(cookie))
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let status = resp.status();
+        let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null),
+        )
+    }
+
+    fn multipart(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, content) in files {
+            body.extend_from_slice(
+                format!(
+                    "--BOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n",
+                    name
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--BOUNDARY--\r\n");
+        body
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_checked_and_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path());
+        Arc::get_mut(&mut state).unwrap().uploads.max_bytes = 1024;
+        let app = crate::app(state);
+        let cookie = login_cookie(&app).await;
+        let form = "multipart/form-data; boundary=BOUNDARY";
+        let png: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        let (status, stored) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/assets",
+            form,
+            multipart(&[("logo.png", png), ("notes.txt", b"first")]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stored[0]["path"], "assets/logo.png");
+        assert_eq!(stored[0]["content_type"], "image/png");
+        assert_eq!(stored[0]["markdown"], "![logo.png](/assets/logo.png)");
+        assert_eq!(stored[1]["markdown"], "[notes.txt](/assets/notes.txt)");
+
+        // The same content is reused; a taken name with new content is numbered.
+        let (_, stored) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/assets",
+            form,
+            multipart(&[("copy.png", png), ("notes.txt", b"second")]),
+        )
+        .await;
+        assert_eq!(stored[0]["path"], "assets/logo.png");
+        assert_eq!(stored[0]["deduplicated"], true);
+        assert_eq!(stored[1]["path"], "assets/notes-1.txt");
+        let assets = dir.path().join("personal/assets");
+        assert_eq!(std::fs::read(assets.join("notes.txt")).unwrap(), b"first");
+        assert_eq!(
+            std::fs::read(assets.join("notes-1.txt")).unwrap(),
+            b"second"
+        );
+
+        // A raw body is stored at the path in the URL.
+        let (status, stored) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/docs/report.pdf",
+            "application/octet-stream",
+            b"%PDF-1.7\n".to_vec(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stored[0]["path"], "docs/report.pdf");
+
+        for (name, content, expected) in [
+            ("big.txt", vec![b'a'; 2048], StatusCode::PAYLOAD_TOO_LARGE),
+            (
+                "fake.png",
+                b"<script>".to_vec(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (
+                "page.html",
+                b"<script>".to_vec(),
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+        ] {
+            let (status, _) = upload(
+                &app,
+                &cookie,
+                "/api/upload/personal/assets",
+                form,
+                multipart(&[(name, &content)]),
+            )
+            .await;
+            assert_eq!(status, expected, "{}", name);
+            assert!(!assets.join(name).exists());
+        }
+        // A rejected part undoes the files stored before it, but not existing
+        // files whose content was reused.
+        let (status, _) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/assets",
+            form,
+            multipart(&[
+                ("again.png", png),
+                ("fresh.txt", b"new"),
+                ("fake.png", b"<script>"),
+            ]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(assets.join("logo.png").exists());
+        assert!(!assets.join("fresh.txt").exists());
+        // Nothing is left behind by rejected uploads.
+        assert_eq!(std::fs::read_dir(&assets).unwrap().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_files_of_one_request_are_limited_in_total() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path());
+        let uploads = &mut Arc::get_mut(&mut state).unwrap().uploads;
+        uploads.max_bytes = 1024;
+        uploads.max_request_bytes = 2048;
+        let app = crate::app(state);
+        let cookie = login_cookie(&app).await;
+        let [a, b, c, d] = [b'a', b'b', b'c', b'd'].map(|byte| vec![byte; 1000]);
+        let form = "multipart/form-data; boundary=BOUNDARY";
+
+        let (status, _) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/assets",
+            form,
+            multipart(&[("a.txt", &a), ("b.txt", &b)]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        // Each file is within the limit, but not all three together.
+        let (status, _) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/assets",
+            form,
+            multipart(&[("c.txt", &c), ("d.txt", &d), ("e.txt", &a)]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let assets = dir.path().join("personal/assets");
+        assert_eq!(std::fs::read_dir(&assets).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_not_bound_by_the_default_body_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = test_state(dir.path());
+        let uploads = &mut Arc::get_mut(&mut state).unwrap().uploads;
+        uploads.max_bytes = 4 * MB;
+        let app = crate::app(state);
+        let cookie = login_cookie(&app).await;
+        let content = vec![b'a'; 3 * MB as usize];
+
+        let (status, stored) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/assets",
+            "multipart/form-data; boundary=BOUNDARY",
+            multipart(&[("big.txt", &content)]),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", stored);
+        assert_eq!(stored[0]["size"], 3 * MB);
+        let (status, _) = upload(
+            &app,
+            &cookie,
+            "/api/upload/personal/raw.txt",
+            "text/plain",
+            content,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let personal = dir.path().join("personal");
+        assert_eq!(
+            std::fs::metadata(personal.join("assets/big.txt"))
+                .unwrap()
+                .len(),
+            3 * MB
+        );
+        assert_eq!(
+            std::fs::metadata(personal.join("raw.txt")).unwrap().len(),
+            3 * MB
+        );
+    }
+}
@@ -0,0 +1,602 @@
+use crate::totp;
+use crate::{ApiToken, User};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+/// PBKDF2 rounds used for both password hashes and the users file key.
+pub const PBKDF2_ITERATIONS: u32 = 100_000;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const STORE_VERSION: u32 = 1;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum AuthError {
+    #[error("invalid users file: {0}")]
+    Format(String),
+    #[error("failed to decrypt users file (is AUTH_SECRET correct?)")]
+    Decrypt,
+    #[error("failed to encrypt users file")]
+    Encrypt,
+    #[error("invalid permissions: {0}")]
+    Permissions(String),
+}
+
+/// A single account as persisted in the encrypted users file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserRecord {
+    pub password_hash: String,
+    pub permissions: HashMap<String, String>, // volume_name -> "r" or "rw"
+    #[serde(default)]
+    pub api_tokens: Vec<ApiTokenRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<TotpConfig>,
+    /// May manage other accounts through the admin API.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub admin: bool,
+    /// Groups that path rules can refer to as `@name`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// Disabled accounts cannot log in and their sessions and tokens stop working.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+}
+
+impl UserRecord {
+    pub fn new(password: &str, permissions: HashMap<String, String>) -> Self {
+        Self {
+            password_hash: hash_password(password),
+            permissions,
+            api_tokens: Vec::new(),
+            totp: None,
+            admin: false,
+            groups: Vec::new(),
+            disabled: false,
+        }
+    }
+
+    /// Whether login requires a second factor.
+    pub fn totp_enabled(&self) -> bool {
+        self.totp.as_ref().is_some_and(|totp| totp.confirmed)
+    }
+
+    /// Accepts a current TOTP code or an unused recovery code. Recovery codes
+    /// are consumed and TOTP steps cannot be replayed, so the caller must
+    /// persist the record afterwards.
+    pub fn verify_second_factor(&mut self, code: &str, now: i64) -> bool {
+        let Some(totp) = self.totp.as_mut().filter(|totp| totp.confirmed) else {
+            return false;
+        };
+        if totp.verify_code(code, now) {
+            return true;
+        }
+
+        let Some(code) = normalize_recovery_code(code) else {
+            return false;
+        };
+        let hash = hash_token_secret(&code);
+        let before = totp.recovery_codes.len();
+        totp.recovery_codes
+            .retain(|stored| !bool::from(stored.as_bytes().ct_eq(hash.as_bytes())));
+        totp.recovery_codes.len() != before
+    }
+
+    pub fn to_user(&self, username: &str) -> User {
+        User {
+            username: username.to_string(),
+            permissions: self.permissions.clone(),
+            admin: self.admin,
+            groups: self.groups.clone(),
+        }
+    }
+}
+
+/// Decrypted contents of `users.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UserStore {
+    pub users: BTreeMap<String, UserRecord>,
+    /// Path-level rules inside volumes, applied in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_rules: Vec<PathRule>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleEffect {
+    Allow,
+    Deny,
+}
+
+/// Allows or denies access to part of a volume.
+///
+/// Among the rules that match a path and the user, the last one that covers
+/// an operation decides it; paths no rule covers keep the volume permission.
+/// Rules never grant more than the user's permission on the volume.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PathRule {
+    pub volume: String,
+    /// Glob relative to the volume root, e.g. `hr` or `finance/**/*.xlsx`. A
+    /// rule on a directory also covers everything inside it.
+    pub pattern: String,
+    /// Usernames, group names prefixed with `@`, or `*` for everyone.
+    pub subjects: Vec<String>,
+    pub effect: RuleEffect,
+    /// The operations the rule decides: `r`, `w` or `rw`. Writing also needs
+    /// read access.
+    pub access: String,
+}
+
+impl PathRule {
+    pub fn applies_to(&self, user: &User) -> bool {
+        self.subjects
+            .iter()
+            .any(|subject| match subject.strip_prefix('@') {
+                Some(group) => user.groups.iter().any(|g| g == group),
+                None => subject == "*" || *subject == user.username,
+            })
+    }
+}
+
+impl UserStore {
+    /// Verifies the credentials and returns the session user on success.
+    ///
+    /// Unknown usernames still pay for a hash so that response timing does not
+    /// reveal which accounts exist.
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<User> {
+        match self.users.get(username) {
+            Some(record) => (verify_password(password, &record.password_hash) && !record.disabled)
+                .then(|| record.to_user(username)),
+            None => {
+                let _ = verify_password(password, DUMMY_HASH);
+                None
+            }
+        }
+    }
+}
+
+impl UserStore {
+    /// Resolves a bearer token of the form `wk_<id>_<secret>` to the user it
+    /// belongs to, with permissions narrowed to the token's scope.
+    pub fn authenticate_token(&self, token: &str, now: i64) -> Option<User> {
+        let (id, secret) = token.strip_prefix(TOKEN_PREFIX)?.split_once('_')?;
+        self.users.iter().find_map(|(username, record)| {
+            if record.disabled {
+                return None;
+            }
+            let token = record.api_tokens.iter().find(|t| t.id == id)?;
+            if !token.verify(secret, now) {
+                return None;
+            }
+            let mut user = record.to_user(username);
+            if let Some(scope) = &token.scope {
+                user.permissions = intersect_permissions(&user.permissions, scope);
+            }
+            Some(user)
+        })
+    }
+}
+
+// Hash of a random password, only used to equalise timing for unknown users.
+const DUMMY_HASH: &str =
+    "pbkdf2-sha256$100000$AAAAAAAAAAAAAAAAAAAAAA==$AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+/// Second-factor settings for an account.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TotpConfig {
+    /// Base32 shared secret.
+    pub secret: String,
+    /// Set once the user has entered a valid code; until then login ignores it.
+    pub confirmed: bool,
+    /// SHA-256 hashes of the unused recovery codes.
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    /// Last accepted time step, to reject replayed codes.
+    #[serde(default)]
+    pub last_step: Option<i64>,
+}
+
+impl TotpConfig {
+    pub fn new(secret: String) -> Self {
+        Self {
+            secret,
+            confirmed: false,
+            recovery_codes: Vec::new(),
+            last_step: None,
+        }
+    }
+
+    /// Checks a TOTP code, refusing any step at or before the last one used.
+    pub fn verify_code(&mut self, code: &str, now: i64) -> bool {
+        match totp::verify(&self.secret, code, now) {
+            Some(step) if self.last_step.is_none_or(|last| step > last) => {
+                self.last_step = Some(step);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the recovery codes, returning the new plaintext codes.
+    pub fn regenerate_recovery_codes(&mut self) -> Vec<String> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let code = totp::base32_encode(&random_bytes::<RECOVERY_CODE_BYTES>());
+                format!("{}-{}", &code[..5], &code[5..RECOVERY_CODE_LEN]).to_lowercase()
+            })
+            .collect();
+        self.recovery_codes = codes
+            .iter()
+            .filter_map(|code| normalize_recovery_code(code))
+            .map(|code| hash_token_secret(&code))
+            .collect();
+        codes
+    }
+}
+
+const RECOVERY_CODE_COUNT: usize = 10;
+// Ten base32 characters (50 bits), printed as two groups of five.
+const RECOVERY_CODE_BYTES: usize = 7;
+const RECOVERY_CODE_LEN: usize = 10;
+
+fn normalize_recovery_code(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    (code.len() == RECOVERY_CODE_LEN).then_some(code)
+}
+
+const TOKEN_PREFIX: &str = "wk_";
+const TOKEN_ID_LEN: usize = 8;
+const TOKEN_SECRET_LEN: usize = 32;
+
+/// A personal access token as persisted in the users file. Only a SHA-256
+/// hash of the secret part is kept; the full token is shown once on creation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiTokenRecord {
+    pub id: String,
+    pub name: String,
+    pub secret_hash: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    /// Volumes the token may use. `None` means the owner's full permissions.
+    pub scope: Option<HashMap<String, String>>,
+}
+
+impl ApiTokenRecord {
+    /// Creates a new token, returning the plaintext token alongside the record.
+    pub fn generate(
+        name: &str,
+        created_at: i64,
+        expires_at: Option<i64>,
+        scope: Option<HashMap<String, String>>,
+    ) -> (String, Self) {
+        let id = to_hex(&random_bytes::<TOKEN_ID_LEN>());
+        let secret = to_hex(&random_bytes::<TOKEN_SECRET_LEN>());
+        let token = format!("{}{}_{}", TOKEN_PREFIX, id, secret);
+        let record = Self {
+            id,
+            name: name.to_string(),
+            secret_hash: hash_token_secret(&secret),
+            created_at,
+            expires_at,
+            scope,
+        };
+        (token, record)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn verify(&self, secret: &str, now: i64) -> bool {
+        let hash = hash_token_secret(secret);
+        let matches: bool = hash.as_bytes().ct_eq(self.secret_hash.as_bytes()).into();
+        matches && !self.is_expired(now)
+    }
+
+    pub fn to_api_token(&self) -> ApiToken {
+        ApiToken {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            scope: self.scope.clone(),
+        }
+    }
+}
+
+// Token secrets are 256 random bits, so a plain hash is enough; PBKDF2 would
+// only slow down every API request.
+fn hash_token_secret(secret: &str) -> String {
+    BASE64.encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Permissions granted by both maps, using the weaker mode for each volume.
+pub fn intersect_permissions(
+    granted: &HashMap<String, String>,
+    scope: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    scope
+        .iter()
+        .filter_map(|(volume, mode)| {
+            let owner_mode = granted.get(volume)?;
+            let mode = if mode == "rw" && owner_mode == "rw" {
+                "rw"
+            } else {
+                "r"
+            };
+            Some((volume.clone(), mode.to_string()))
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedStore {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut buf = [0u8; N];
+    OsRng.fill_bytes(&mut buf);
+    buf
+}
+
+fn derive_key(secret: &str, salt: &[u8], iterations: u32) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(secret.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+/// Hashes a password as `pbkdf2-sha256$<iterations>$<salt>$<hash>`.
+pub fn hash_password(password: &str) -> String {
+    let salt = random_bytes::<SALT_LEN>();
+    let hash = derive_key(password, &salt, PBKDF2_ITERATIONS);
+    format!(
+        "{}${}${}${}",
+        HASH_SCHEME,
+        PBKDF2_ITERATIONS,
+        BASE64.encode(salt),
+        BASE64.encode(hash)
+    )
+}
+
+/// Checks a password against a hash produced by [`hash_password`] in constant time.
+pub fn verify_password(password: &str, encoded: &str) -> bool {
+    let parts: Vec<&str> = encoded.split('$').collect();
+    if parts.len() != 4 || parts[0] != HASH_SCHEME {
+        return false;
+    }
+    let Ok(iterations) = parts[1].parse::<u32>() else {
+        return false;
+    };
+    let (Ok(salt), Ok(expected)) = (BASE64.decode(parts[2]), BASE64.decode(parts[3])) else {
+        return false;
+    };
+    if expected.len() != KEY_LEN {
+        return false;
+    }
+
+    let actual = derive_key(password, &salt, iterations);
+    actual.ct_eq(&expected).into()
+}
+
+/// Serialises and encrypts the store with AES-256-GCM under a key derived from `secret`.
+pub fn encrypt_store(store: &UserStore, secret: &str) -> Result<String, AuthError> {
+    let plaintext = serde_json::to_vec(store).map_err(|e| AuthError::Format(e.to_string()))?;
+    let salt = random_bytes::<SALT_LEN>();
+    let nonce = random_bytes::<NONCE_LEN>();
+    let key = derive_key(secret, &salt, PBKDF2_ITERATIONS);
+
+    let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| AuthError::Encrypt)?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|_| AuthError::Encrypt)?;
+
+    let envelope = EncryptedStore {
+        version: STORE_VERSION,
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    };
+    serde_json::to_string_pretty(&envelope).map_err(|e| AuthError::Format(e.to_string()))
+}
+
+/// Decrypts a users file produced by [`encrypt_store`].
+pub fn decrypt_store(data: &str, secret: &str) -> Result<UserStore, AuthError> {
+    let envelope: EncryptedStore =
+        serde_json::from_str(data).map_err(|e| AuthError::Format(e.to_string()))?;
+    if envelope.version != STORE_VERSION {
+        return Err(AuthError::Format(format!(
+            "unsupported version {}",
+            envelope.version
+        )));
+    }
+
+    let decode = |field: &str, value: &str| {
+        BASE64
+            .decode(value)
+            .map_err(|_| AuthError::Format(format!("{} is not valid base64", field)))
+    };
+    let salt = decode("salt", &envelope.salt)?;
+    let nonce = decode("nonce", &envelope.nonce)?;
+    let ciphertext = decode("ciphertext", &envelope.ciphertext)?;
+    if nonce.len() != NONCE_LEN {
+        return Err(AuthError::Format("nonce has the wrong length".to_string()));
+    }
+
+    let key = derive_key(secret, &salt, PBKDF2_ITERATIONS);
+    let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| AuthError::Decrypt)?;
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| AuthError::Decrypt)?;
+
+    serde_json::from_slice(&plaintext).map_err(|e| AuthError::Format(e.to_string()))
+}
+
+/// Parses a `volume:mode,...` permission list, where mode is `r` or `rw`.
+pub fn parse_permissions(spec: &str) -> Result<HashMap<String, String>, AuthError> {
+    let mut permissions = HashMap::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (volume, mode) = entry
+            .split_once(':')
+            .ok_or_else(|| AuthError::Permissions(format!("'{}' is not volume:mode", entry)))?;
+        let (volume, mode) = (volume.trim(), mode.trim());
+        if volume.is_empty() {
+            return Err(AuthError::Permissions(format!(
+                "'{}' has no volume name",
+                entry
+            )));
+        }
+        if mode != "r" && mode != "rw" {
+            return Err(AuthError::Permissions(format!(
+                "mode for '{}' must be 'r' or 'rw'",
+                volume
+            )));
+        }
+        permissions.insert(volume.to_string(), mode.to_string());
+    }
+    Ok(permissions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hash_roundtrip() {
+        let hash = hash_password("hunter2");
+        assert!(hash.starts_with("pbkdf2-sha256$"));
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "plaintext"));
+    }
+
+    #[test]
+    fn test_store_encryption_roundtrip() {
+        let mut store = UserStore::default();
+        store.users.insert(
+            "alice".to_string(),
+            UserRecord::new("secret", parse_permissions("personal:rw,work:r").unwrap()),
+        );
+
+        let encrypted = encrypt_store(&store, "server-secret").unwrap();
+        assert!(!encrypted.contains("alice"));
+
+        let decrypted = decrypt_store(&encrypted, "server-secret").unwrap();
+        assert_eq!(decrypted, store);
+        assert_eq!(
+            decrypt_store(&encrypted, "wrong-secret"),
+            Err(AuthError::Decrypt)
+        );
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let mut store = UserStore::default();
+        store.users.insert(
+            "alice".to_string(),
+            UserRecord::new("secret", parse_permissions("personal:rw").unwrap()),
+        );
+
+        let user = store.authenticate("alice", "secret").unwrap();
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.permissions.get("personal").unwrap(), "rw");
+        assert!(store.authenticate("alice", "wrong").is_none());
+        assert!(store.authenticate("bob", "secret").is_none());
+
+        store.users.get_mut("alice").unwrap().disabled = true;
+        assert!(store.authenticate("alice", "secret").is_none());
+    }
+
+    #[test]
+    fn test_api_token_scope_and_expiry() {
+        let mut store = UserStore::default();
+        let mut record =
+            UserRecord::new("secret", parse_permissions("personal:rw,work:r").unwrap());
+        let (full, full_record) = ApiTokenRecord::generate("cron", 100, None, None);
+        let scope = parse_permissions("personal:r,work:rw,secret:rw").unwrap();
+        let (scoped, scoped_record) =
+            ApiTokenRecord::generate("plugin", 100, Some(200), Some(scope));
+        record.api_tokens = vec![full_record, scoped_record];
+        store.users.insert("alice".to_string(), record);
+
+        let user = store.authenticate_token(&full, 150).unwrap();
+        assert_eq!(user.username, "alice");
+        assert!(user.can_write("personal"));
+
+        let user = store.authenticate_token(&scoped, 150).unwrap();
+        assert!(user.can_read("personal") && !user.can_write("personal"));
+        assert!(user.can_read("work") && !user.can_write("work"));
+        assert!(!user.can_read("secret"));
+
+        assert!(store.authenticate_token(&scoped, 200).is_none());
+        assert!(store
+            .authenticate_token(&format!("{}x", full), 150)
+            .is_none());
+        assert!(store.authenticate_token("wk_nope_nope", 150).is_none());
+        assert!(!serde_json::to_string(&store).unwrap().contains(&full[20..]));
+
+        store.users.get_mut("alice").unwrap().disabled = true;
+        assert!(store.authenticate_token(&full, 150).is_none());
+    }
+
+    #[test]
+    fn test_second_factor() {
+        let mut record = UserRecord::new("secret", HashMap::new());
+        let key = b"12345678901234567890";
+        let mut config = TotpConfig::new(totp::base32_encode(key));
+        assert!(!record.verify_second_factor("287082", 59));
+
+        let codes = config.regenerate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(codes[0].len(), RECOVERY_CODE_LEN + 1);
+        config.confirmed = true;
+        record.totp = Some(config);
+        assert!(record.totp_enabled());
+
+        let code = totp::code_at(key, totp::step_at(1000));
+        assert!(record.verify_second_factor(&code, 1000));
+        // The same code cannot be used twice.
+        assert!(!record.verify_second_factor(&code, 1000));
+
+        assert!(record.verify_second_factor(&codes[0].to_uppercase(), 1000));
+        assert!(!record.verify_second_factor(&codes[0], 1000));
+        assert!(!record.verify_second_factor("", 1000));
+        assert_eq!(
+            record.totp.as_ref().unwrap().recovery_codes.len(),
+            RECOVERY_CODE_COUNT - 1
+        );
+    }
+
+    #[test]
+    fn test_parse_permissions() {
+        let perms = parse_permissions("personal:rw, work:r").unwrap();
+        assert_eq!(perms.len(), 2);
+        assert_eq!(perms["work"], "r");
+
+        assert!(parse_permissions("personal").is_err());
+        assert!(parse_permissions("personal:w").is_err());
+        assert!(parse_permissions(":rw").is_err());
+        assert!(parse_permissions("").unwrap().is_empty());
+    }
+}
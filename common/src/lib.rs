@@ -1,10 +1,33 @@
+pub mod auth;
+pub mod merge;
+pub mod totp;
+
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct User {
     pub username: String,
     pub permissions: HashMap<String, String>, // volume_name -> "r" or "rw"
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+impl User {
+    pub fn can_read(&self, volume: &str) -> bool {
+        self.permissions
+            .get(volume)
+            .is_some_and(|mode| mode.contains('r'))
+    }
+
+    pub fn can_write(&self, volume: &str) -> bool {
+        self.permissions
+            .get(volume)
+            .is_some_and(|mode| mode.contains('w'))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -13,12 +36,67 @@ pub struct WikiPage {
     pub content: String,
 }
 
+/// Strong `ETag` of a file's bytes, quoted as sent in the header.
+pub fn content_etag(content: &[u8]) -> String {
+    let digest = Sha256::digest(content);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Body of the `409 Conflict` returned when a page changed since the
+/// `If-Match` version the client edited.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PageConflict {
+    pub path: String,
+    /// The page as it is now, or `None` if it was deleted.
+    pub content: Option<String>,
+    pub etag: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct FileNode {
     pub name: String,
     pub path: String,
     pub is_dir: bool,
     pub children: Option<Vec<FileNode>>,
+    /// Size in bytes, for files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Last modification, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<i64>,
+    /// The uncommitted change to a file (`New`, `Modified`, ...), or
+    /// `Modified` for a folder with changes inside.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_status: Option<String>,
+    /// How many entries a folder has, which is more than `children` holds
+    /// when those were not all listed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child_count: Option<usize>,
+}
+
+/// One page of a folder's entries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectoryListing {
+    /// The folder, relative to the volume root.
+    pub path: String,
+    pub entries: Vec<FileNode>,
+    /// Entries in the folder in all.
+    pub total: usize,
+    /// Where the next page starts, if there is one.
+    pub next_offset: Option<usize>,
+}
+
+/// A deleted file or folder waiting in a volume's trash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub id: String,
+    /// Where it was, relative to the volume root.
+    pub path: String,
+    pub is_dir: bool,
+    pub deleted_by: String,
+    /// Unix seconds.
+    pub deleted_at: i64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -50,6 +128,233 @@ pub struct RestoreRequest {
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct RenameRequest {
     pub new_path: String,
+    /// Report the link updates the rename would make without making them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CopyRequest {
+    pub new_path: String,
+    /// The volume to copy into; the source volume if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<String>,
+    /// Rewrite relative links in copied pages so they still point at the same
+    /// pages, or at their copies for pages copied along with them.
+    #[serde(default)]
+    pub rewrite_links: bool,
+}
+
+/// A page template, a Markdown file in a volume's `.templates` folder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateInfo {
+    /// The file name without `.md`.
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CreateFromTemplate {
+    pub template: String,
+    /// The creator's offset from UTC, so `{{date}}` and `{{time}}` are local.
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+/// A file stored by an upload.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadedFile {
+    /// The name the file was sent with.
+    pub name: String,
+    /// Where it is stored in the volume, which differs from the name sent when
+    /// that was taken or the same content was already there.
+    pub path: String,
+    pub size: u64,
+    pub content_type: String,
+    /// True when an existing file with the same content was reused.
+    pub deduplicated: bool,
+    /// A Markdown image or link for the file.
+    pub markdown: String,
+}
+
+/// One link rewritten by a rename, as written in the page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkEdit {
+    pub before: String,
+    pub after: String,
+}
+
+/// The link edits made, or to be made, in one page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkUpdate {
+    pub volume: String,
+    /// Where the page is after the rename.
+    pub path: String,
+    pub edits: Vec<LinkEdit>,
+}
+
+/// A page whose links could not be updated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkUpdateFailure {
+    pub volume: String,
+    pub path: String,
+    pub error: String,
+}
+
+/// The answer to a rename: the pages whose links were (or, for a dry run,
+/// would be) updated, and those that could not be.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RenameReport {
+    pub dry_run: bool,
+    pub updated: Vec<LinkUpdate>,
+    pub failed: Vec<LinkUpdateFailure>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShareLink {
+    pub url: String,
+    pub expires_at: i64,
+}
+
+/// Sign-in methods available to the current request.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuthMode {
+    /// False when a trusted proxy is the only way to sign in.
+    pub password_login: bool,
+    /// Identity asserted by a trusted reverse proxy for this request, if any.
+    pub proxy_user: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct LoginResponse {
+    /// The password was accepted but a code must be sent to `/api/login/totp`.
+    pub totp_required: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TotpCodeRequest {
+    /// A six digit TOTP code, or a recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_remaining: usize,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditEvent {
+    /// Unix seconds.
+    pub timestamp: i64,
+    /// What happened, e.g. `login.failed`.
+    pub action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// `success`, `denied`, `rejected` or `error`, for requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
+    /// HTTP status of the response, for requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+}
+
+/// One of the caller's browser sessions, as listed by `GET /api/sessions`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionInfo {
+    /// Identifies the session for revocation; not the session cookie.
+    pub id: String,
+    /// Unix seconds; unknown for sessions started before this was recorded.
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub last_seen: Option<i64>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    pub expires_at: i64,
+    /// The session making this request.
+    #[serde(default)]
+    pub current: bool,
+}
+
+/// Public view of a personal access token (never includes the secret).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub scope: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub expires_in_days: Option<i64>,
+    /// Volume permissions, e.g. `{"work": "r"}`. Capped by the owner's own permissions.
+    pub scope: Option<HashMap<String, String>>,
+}
+
+/// Returned once when a token is created; `token` cannot be retrieved again.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CreatedToken {
+    pub token: String,
+    pub info: ApiToken,
+}
+
+/// An account as shown to administrators.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserAccount {
+    pub username: String,
+    pub permissions: HashMap<String, String>,
+    pub admin: bool,
+    pub groups: Vec<String>,
+    pub disabled: bool,
+    pub two_factor: bool,
+    /// Number of live login sessions.
+    pub sessions: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub password: String,
+    pub permissions: HashMap<String, String>,
+    #[serde(default)]
+    pub admin: bool,
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+/// Changes to an account; fields left out stay as they are.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UpdateUserRequest {
+    pub permissions: Option<HashMap<String, String>>,
+    pub admin: Option<bool>,
+    pub groups: Option<Vec<String>>,
+    pub disabled: Option<bool>,
+    pub password: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -118,12 +423,22 @@ mod tests {
                 path: "foo/bar.md".to_string(),
                 is_dir: false,
                 children: None,
+                size: Some(42),
+                ..Default::default()
             }]),
+            ..Default::default()
         };
 
         let serialized = serde_json::to_value(&node).unwrap();
         assert_eq!(serialized["name"], "foo");
         assert_eq!(serialized["is_dir"], true);
         assert_eq!(serialized["children"][0]["name"], "bar.md");
+        assert_eq!(serialized["children"][0]["size"], 42);
+        // Metadata that is not known is left out.
+        assert!(serialized.get("size").is_none());
+        let old: FileNode =
+            serde_json::from_str(r#"{"name":"a","path":"a","is_dir":false,"children":null}"#)
+                .unwrap();
+        assert_eq!(old.modified, None);
     }
 }
@@ -0,0 +1,224 @@
+//! Line-based diffs and three-way merges of page text, used to reconcile a
+//! save that raced with another change to the same page.
+
+/// Start, middle and end of a conflict in `merge3` output.
+pub const CONFLICT_START: &str = "<<<<<<< yours\n";
+pub const CONFLICT_SEPARATOR: &str = "=======\n";
+pub const CONFLICT_END: &str = ">>>>>>> server\n";
+
+/// Above this many cells in the LCS table, the changed middle of two texts is
+/// treated as entirely different rather than compared line by line.
+const MAX_TABLE_CELLS: usize = 4_000_000;
+
+/// One line of a two-way diff, including its trailing newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The outcome of a three-way merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+    /// The merged text, with conflict markers around regions changed on both sides.
+    pub text: String,
+    pub conflicts: usize,
+}
+
+fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+/// For each line of `a`, the line of `b` it is paired with in a longest
+/// common subsequence of the two.
+fn lcs_map(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    let mut map = vec![None; a.len()];
+    // Most edits leave the start and end alone; matching those directly keeps
+    // the table small.
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    for (i, slot) in map.iter_mut().enumerate().take(prefix) {
+        *slot = Some(i);
+    }
+    for i in 0..suffix {
+        map[a.len() - 1 - i] = Some(b.len() - 1 - i);
+    }
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n == 0 || m == 0 || (n + 1) * (m + 1) > MAX_TABLE_CELLS {
+        return map;
+    }
+
+    // lengths[i * (m + 1) + j] is the LCS length of a_mid[i..] and b_mid[j..].
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[at(i, j)] = if a_mid[i] == b_mid[j] {
+                lengths[at(i + 1, j + 1)] + 1
+            } else {
+                lengths[at(i + 1, j)].max(lengths[at(i, j + 1)])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a_mid[i] == b_mid[j] {
+            map[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lengths[at(i + 1, j)] >= lengths[at(i, j + 1)] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    map
+}
+
+/// The lines of `old` and `new` in order, marking which were removed and added.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let (a, b) = (lines(old), lines(new));
+    let mut out = Vec::with_capacity(a.len().max(b.len()));
+    let mut j = 0;
+    for (line, matched) in a.iter().zip(lcs_map(&a, &b)) {
+        match matched {
+            Some(k) => {
+                out.extend(b[j..k].iter().map(|l| DiffLine::Added(l)));
+                out.push(DiffLine::Same(line));
+                j = k + 1;
+            }
+            None => out.push(DiffLine::Removed(line)),
+        }
+    }
+    out.extend(b[j..].iter().map(|l| DiffLine::Added(l)));
+    out
+}
+
+fn push_lines(out: &mut String, lines: &[&str]) {
+    for line in lines {
+        out.push_str(line);
+    }
+}
+
+fn end_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Merges one region between lines all three texts share. Returns 1 when
+/// both sides changed it differently.
+fn merge_region(out: &mut String, base: &[&str], local: &[&str], remote: &[&str]) -> usize {
+    if local == remote || remote == base {
+        push_lines(out, local);
+        0
+    } else if local == base {
+        push_lines(out, remote);
+        0
+    } else {
+        end_line(out);
+        out.push_str(CONFLICT_START);
+        push_lines(out, local);
+        end_line(out);
+        out.push_str(CONFLICT_SEPARATOR);
+        push_lines(out, remote);
+        end_line(out);
+        out.push_str(CONFLICT_END);
+        1
+    }
+}
+
+/// Combines the changes `local` and `remote` each made to `base`. Regions
+/// changed on only one side take that side's version; regions changed on both
+/// sides are kept from both, between conflict markers.
+pub fn merge3(base: &str, local: &str, remote: &str) -> Merge {
+    let (base, local, remote) = (lines(base), lines(local), lines(remote));
+    let to_local = lcs_map(&base, &local);
+    let to_remote = lcs_map(&base, &remote);
+
+    let mut text = String::new();
+    let mut conflicts = 0;
+    let (mut b, mut l, mut r) = (0, 0, 0);
+    loop {
+        // The next base line kept by both sides anchors the end of a region.
+        let anchor = (b..base.len()).find_map(|k| Some((k, to_local[k]?, to_remote[k]?)));
+        let (b_end, l_end, r_end) = anchor.unwrap_or((base.len(), local.len(), remote.len()));
+        conflicts += merge_region(
+            &mut text,
+            &base[b..b_end],
+            &local[l..l_end],
+            &remote[r..r_end],
+        );
+        if anchor.is_none() {
+            break;
+        }
+        text.push_str(base[b_end]);
+        (b, l, r) = (b_end + 1, l_end + 1, r_end + 1);
+    }
+    Merge { text, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let diff = diff_lines("a\nb\nc\n", "a\nx\nc\nd\n");
+        assert_eq!(
+            diff,
+            vec![
+                DiffLine::Same("a\n"),
+                DiffLine::Removed("b\n"),
+                DiffLine::Added("x\n"),
+                DiffLine::Same("c\n"),
+                DiffLine::Added("d\n"),
+            ]
+        );
+        assert!(diff_lines("", "").is_empty());
+    }
+
+    #[test]
+    fn test_merge3_combines_separate_edits() {
+        let base = "title\n\none\ntwo\nthree\n";
+        let local = "title\n\nONE\ntwo\nthree\n";
+        let remote = "title\n\none\ntwo\nthree\nfour\n";
+        assert_eq!(
+            merge3(base, local, remote),
+            Merge {
+                text: "title\n\nONE\ntwo\nthree\nfour\n".to_string(),
+                conflicts: 0,
+            }
+        );
+        // The same change on both sides is not a conflict.
+        assert_eq!(merge3(base, local, local).text, local);
+        assert_eq!(merge3(base, base, remote).text, remote);
+    }
+
+    #[test]
+    fn test_merge3_marks_conflicts() {
+        let merge = merge3("a\nb\nc", "a\nmine\nc", "a\ntheirs\nc");
+        assert_eq!(merge.conflicts, 1);
+        assert_eq!(
+            merge.text,
+            format!(
+                "a\n{}mine\n{}theirs\n{}c",
+                CONFLICT_START, CONFLICT_SEPARATOR, CONFLICT_END
+            )
+        );
+
+        // Deleting a line one side edited keeps both versions for review.
+        let merge = merge3("a\nb\n", "a\n", "a\nB\n");
+        assert_eq!(merge.conflicts, 1);
+        assert!(merge.text.contains("B\n"));
+    }
+}
@@ -102,7 +102,7 @@ pub fn commit_modal(props: &CommitModalProps) -> Html {
                     }
                 };
 
-                let request = match Request::post(&url)
+                let request = match crate::csrf::post(&url)
                     .header("Content-Type", "application/json")
                     .body(body_str)
                 {
@@ -164,7 +164,7 @@ pub fn commit_modal(props: &CommitModalProps) -> Html {
                     }
                 };
 
-                let request = match Request::post(&url)
+                let request = match crate::csrf::post(&url)
                     .header("Content-Type", "application/json")
                     .body(body_str)
                 {
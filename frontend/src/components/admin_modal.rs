@@ -0,0 +1,296 @@
+use crate::csrf;
+use crate::hooks::{error_text, use_remote_list};
+use common::auth::parse_permissions;
+use common::{CreateUserRequest, UpdateUserRequest, UserAccount};
+use std::collections::HashMap;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub on_close: Callback<()>,
+}
+
+fn permissions_summary(permissions: &HashMap<String, String>) -> String {
+    let mut entries: Vec<String> = permissions
+        .iter()
+        .map(|(volume, mode)| format!("{}:{}", volume, mode))
+        .collect();
+    entries.sort();
+    entries.join(",")
+}
+
+async fn update_user(username: &str, update: UpdateUserRequest) -> Result<(), String> {
+    let url = format!("/api/admin/users/{}", username);
+    let request = csrf::put(&url).json(&update).map_err(|e| e.to_string())?;
+    match request.send().await {
+        Ok(resp) if resp.ok() => Ok(()),
+        Ok(resp) => Err(error_text(resp).await),
+        Err(e) => Err(format!("Network error: {}", e)),
+    }
+}
+
+async fn force_logout(username: &str) -> Result<(), String> {
+    let url = format!("/api/admin/users/{}/logout", username);
+    match csrf::post(&url).send().await {
+        Ok(resp) if resp.ok() => Ok(()),
+        Ok(resp) => Err(error_text(resp).await),
+        Err(e) => Err(format!("Network error: {}", e)),
+    }
+}
+
+async fn create_user(payload: CreateUserRequest) -> Result<(), String> {
+    let request = csrf::post("/api/admin/users")
+        .json(&payload)
+        .map_err(|e| e.to_string())?;
+    match request.send().await {
+        Ok(resp) if resp.ok() => Ok(()),
+        Ok(resp) => Err(error_text(resp).await),
+        Err(e) => Err(format!("Network error: {}", e)),
+    }
+}
+
+#[function_component(AdminModal)]
+pub fn admin_modal(props: &Props) -> Html {
+    let list = use_remote_list::<UserAccount>("/api/admin/users".to_string());
+    let users = list.items.clone();
+    let loading = list.loading.clone();
+    let error = list.error.clone();
+
+    let new_username = use_state(String::new);
+    let new_password = use_state(String::new);
+    let new_permissions = use_state(String::new);
+    let new_admin = use_state(|| false);
+
+    // Runs a change and reloads the list, showing any error.
+    let apply = move |change: std::pin::Pin<
+        Box<dyn std::future::Future<Output = Result<(), String>>>,
+    >| { list.apply(change) };
+
+    let on_create = {
+        let apply = apply.clone();
+        let error = error.clone();
+        let new_username = new_username.clone();
+        let new_password = new_password.clone();
+        let new_permissions = new_permissions.clone();
+        let new_admin = new_admin.clone();
+        Callback::from(move |_| {
+            let permissions = match parse_permissions(&new_permissions) {
+                Ok(permissions) => permissions,
+                Err(e) => {
+                    error.set(Some(e.to_string()));
+                    return;
+                }
+            };
+            let payload = CreateUserRequest {
+                username: (*new_username).clone(),
+                password: (*new_password).clone(),
+                permissions,
+                admin: *new_admin,
+                groups: Vec::new(),
+            };
+            new_username.set(String::new());
+            new_password.set(String::new());
+            new_permissions.set(String::new());
+            new_admin.set(false);
+            apply(Box::pin(create_user(payload)));
+        })
+    };
+
+    let on_input = |state: &UseStateHandle<String>| {
+        let state = state.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            state.set(input.value());
+        })
+    };
+
+    let on_admin_toggle = {
+        let new_admin = new_admin.clone();
+        Callback::from(move |_| new_admin.set(!*new_admin))
+    };
+
+    let on_close = props.on_close.clone();
+
+    html! {
+        <div class="modal-overlay" onclick={move |_| on_close.emit(())}>
+            <div class="modal admin-modal" onclick={|e: MouseEvent| e.stop_propagation()}>
+                <div class="modal-header">
+                    <h2>{"Users"}</h2>
+                    <button class="btn-icon" onclick={let on_close = props.on_close.clone(); move |_| on_close.emit(())} title="Close" aria-label="Close Users">{"×"}</button>
+                </div>
+
+                <div class="modal-body">
+                    if let Some(err) = &*error {
+                        <div class="error-msg" role="alert">{ err }</div>
+                    }
+                    if *loading {
+                        <p>{"Loading users..."}</p>
+                    } else {
+                        <div class="flex flex-col gap-4">
+                            {for users.iter().map(|account| {
+                                let username = account.username.clone();
+
+                                let on_permissions = {
+                                    let apply = apply.clone();
+                                    let error = error.clone();
+                                    let username = username.clone();
+                                    let current = permissions_summary(&account.permissions);
+                                    Callback::from(move |_| {
+                                        let Some(spec) = gloo_dialogs::prompt("Permissions (e.g. personal:rw,work:r):", Some(&current)) else {
+                                            return;
+                                        };
+                                        match parse_permissions(&spec) {
+                                            Ok(permissions) => {
+                                                let username = username.clone();
+                                                apply(Box::pin(async move {
+                                                    update_user(&username, UpdateUserRequest { permissions: Some(permissions), ..Default::default() }).await
+                                                }));
+                                            }
+                                            Err(e) => error.set(Some(e.to_string())),
+                                        }
+                                    })
+                                };
+
+                                let on_groups = {
+                                    let apply = apply.clone();
+                                    let username = username.clone();
+                                    let current = account.groups.join(",");
+                                    Callback::from(move |_| {
+                                        let Some(spec) = gloo_dialogs::prompt("Groups (e.g. hr,finance):", Some(&current)) else {
+                                            return;
+                                        };
+                                        let groups = spec.split(',').map(str::trim).filter(|g| !g.is_empty()).map(String::from).collect();
+                                        let username = username.clone();
+                                        apply(Box::pin(async move {
+                                            update_user(&username, UpdateUserRequest { groups: Some(groups), ..Default::default() }).await
+                                        }));
+                                    })
+                                };
+
+                                let on_toggle_admin = {
+                                    let apply = apply.clone();
+                                    let username = username.clone();
+                                    let admin = !account.admin;
+                                    Callback::from(move |_| {
+                                        let username = username.clone();
+                                        apply(Box::pin(async move {
+                                            update_user(&username, UpdateUserRequest { admin: Some(admin), ..Default::default() }).await
+                                        }));
+                                    })
+                                };
+
+                                let on_toggle_disabled = {
+                                    let apply = apply.clone();
+                                    let username = username.clone();
+                                    let disabled = !account.disabled;
+                                    Callback::from(move |_| {
+                                        if disabled && !gloo_dialogs::confirm(&format!("Disable '{}' and end their sessions?", username)) {
+                                            return;
+                                        }
+                                        let username = username.clone();
+                                        apply(Box::pin(async move {
+                                            update_user(&username, UpdateUserRequest { disabled: Some(disabled), ..Default::default() }).await
+                                        }));
+                                    })
+                                };
+
+                                let on_reset_password = {
+                                    let apply = apply.clone();
+                                    let username = username.clone();
+                                    Callback::from(move |_| {
+                                        let Some(password) = gloo_dialogs::prompt(&format!("New password for '{}':", username), None) else {
+                                            return;
+                                        };
+                                        let username = username.clone();
+                                        apply(Box::pin(async move {
+                                            update_user(&username, UpdateUserRequest { password: Some(password), ..Default::default() }).await
+                                        }));
+                                    })
+                                };
+
+                                let on_logout = {
+                                    let apply = apply.clone();
+                                    let username = username.clone();
+                                    Callback::from(move |_| {
+                                        let username = username.clone();
+                                        apply(Box::pin(async move { force_logout(&username).await }));
+                                    })
+                                };
+
+                                let mut flags = Vec::new();
+                                if account.admin {
+                                    flags.push("admin");
+                                }
+                                if account.two_factor {
+                                    flags.push("2FA");
+                                }
+                                if account.disabled {
+                                    flags.push("disabled");
+                                }
+
+                                html! {
+                                    <div class="border rounded p-2">
+                                        <div class="font-bold mb-1">
+                                            { &account.username }
+                                            if !flags.is_empty() {
+                                                <span class="text-sm text-muted">{ format!(" ({})", flags.join(", ")) }</span>
+                                            }
+                                        </div>
+                                        <div class="text-sm text-muted">
+                                            { format!("Permissions: {}", if account.permissions.is_empty() { "(none)".to_string() } else { permissions_summary(&account.permissions) }) }
+                                        </div>
+                                        if !account.groups.is_empty() {
+                                            <div class="text-sm text-muted">
+                                                { format!("Groups: {}", account.groups.join(", ")) }
+                                            </div>
+                                        }
+                                        <div class="text-sm text-muted mb-1">
+                                            { format!("Active sessions: {}", account.sessions) }
+                                        </div>
+                                        <div class="flex gap-2">
+                                            <button class="btn" onclick={on_permissions} aria-label={format!("Edit permissions of {}", username)}>{"Permissions"}</button>
+                                            <button class="btn" onclick={on_groups} aria-label={format!("Edit groups of {}", username)}>{"Groups"}</button>
+                                            <button class="btn" onclick={on_toggle_admin} aria-label={format!("Toggle admin for {}", username)}>
+                                                { if account.admin { "Revoke admin" } else { "Make admin" } }
+                                            </button>
+                                            <button class="btn" onclick={on_reset_password} aria-label={format!("Reset password of {}", username)}>{"Reset password"}</button>
+                                            <button class="btn" onclick={on_toggle_disabled} aria-label={format!("Enable or disable {}", username)}>
+                                                { if account.disabled { "Enable" } else { "Disable" } }
+                                            </button>
+                                            if account.sessions > 0 {
+                                                <button class="btn" onclick={on_logout} aria-label={format!("Log out {}", username)}>{"Log out"}</button>
+                                            }
+                                        </div>
+                                    </div>
+                                }
+                            })}
+                        </div>
+
+                        <hr class="border-0 border-t mt-3 mb-3" />
+                        <h3>{"Add User"}</h3>
+                        <div class="form-group">
+                            <label for="new-username">{"Username:"}</label>
+                            <input id="new-username" type="text" value={(*new_username).clone()} oninput={on_input(&new_username)} />
+                        </div>
+                        <div class="form-group">
+                            <label for="new-password">{"Password:"}</label>
+                            <input id="new-password" type="password" value={(*new_password).clone()} oninput={on_input(&new_password)} />
+                        </div>
+                        <div class="form-group">
+                            <label for="new-permissions">{"Permissions:"}</label>
+                            <input id="new-permissions" type="text" value={(*new_permissions).clone()} oninput={on_input(&new_permissions)} placeholder="e.g. personal:rw,work:r" />
+                        </div>
+                        <div class="form-group flex gap-2">
+                            <input id="new-admin" type="checkbox" checked={*new_admin} onclick={on_admin_toggle} />
+                            <label for="new-admin">{"Administrator"}</label>
+                        </div>
+                        <div class="actions">
+                            <button class="btn btn-primary" onclick={on_create} aria-label="Create user">{"Create"}</button>
+                        </div>
+                    }
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -23,6 +23,8 @@ pub struct BottomBarProps {
     pub commits_behind: usize,
     pub uncommitted_files: usize,
     pub is_drawer_open: bool,
+    /// Shows New File and the git controls.
+    pub can_write: bool,
 }
 
 #[function_component(BottomBar)]
@@ -71,93 +73,95 @@ pub fn bottom_bar(props: &BottomBarProps) -> Html {
                 <span>{"Search files... (Ctrl+K)"}</span>
             </button>
 
-            // New File
-            <button class="bottom-bar-btn" onclick={move |_| on_new_file.emit(())} title="New File" aria-label="New File">
-                <IconPlus />
-            </button>
+            if props.can_write {
+                // New File
+                <button class="bottom-bar-btn" onclick={move |_| on_new_file.emit(())} title="New File" aria-label="New File">
+                    <IconPlus />
+                </button>
 
-            // Git Controls Group (Responsive)
-            <div class="git-menu-container">
-                // Desktop: Show individual buttons
-                <div class="desktop-git-controls">
-                    <button class="bottom-bar-btn" onclick={move |_| on_pull.emit(())} title="Pull" aria-label={if props.commits_behind > 0 { format!("Pull ({} pending)", props.commits_behind) } else { "Pull".to_string() }}>
-                        <IconDownload />
-                        if props.commits_behind > 0 {
-                            <span class="badge">{ props.commits_behind }</span>
-                        }
-                    </button>
-                    <button class="bottom-bar-btn" onclick={move |_| on_commit.emit(())} title="Commit" aria-label={if props.uncommitted_files > 0 { format!("Commit ({} uncommitted files)", props.uncommitted_files) } else { "Commit".to_string() }}>
+                // Git Controls Group (Responsive)
+                <div class="git-menu-container">
+                    // Desktop: Show individual buttons
+                    <div class="desktop-git-controls">
+                        <button class="bottom-bar-btn" onclick={move |_| on_pull.emit(())} title="Pull" aria-label={if props.commits_behind > 0 { format!("Pull ({} pending)", props.commits_behind) } else { "Pull".to_string() }}>
+                            <IconDownload />
+                            if props.commits_behind > 0 {
+                                <span class="badge">{ props.commits_behind }</span>
+                            }
+                        </button>
+                        <button class="bottom-bar-btn" onclick={move |_| on_commit.emit(())} title="Commit" aria-label={if props.uncommitted_files > 0 { format!("Commit ({} uncommitted files)", props.uncommitted_files) } else { "Commit".to_string() }}>
+                            <IconGitCommit />
+                            if props.uncommitted_files > 0 {
+                                <span class="badge bg-accent">{ props.uncommitted_files }</span>
+                            }
+                        </button>
+                        <button class="bottom-bar-btn" onclick={move |_| on_push.emit(())} title="Push" aria-label={if props.commits_ahead > 0 { format!("Push ({} pending)", props.commits_ahead) } else { "Push".to_string() }}>
+                            <IconUpload />
+                            if props.commits_ahead > 0 {
+                                <span class="badge">{ props.commits_ahead }</span>
+                            }
+                        </button>
+                    </div>
+
+                    // Mobile: Show Menu Toggle
+                    <button
+                        class={classes!("bottom-bar-btn", "mobile-git-toggle", if *is_git_menu_open { "active" } else { "" })}
+                        onclick={toggle_git_menu}
+                        title="Git Actions" aria-label={if props.commits_ahead > 0 || props.commits_behind > 0 || props.uncommitted_files > 0 { "Git Actions (Attention required)" } else { "Git Actions" }}
+                        aria-expanded={(*is_git_menu_open).to_string()}
+                    >
                         <IconGitCommit />
-                        if props.uncommitted_files > 0 {
-                            <span class="badge bg-accent">{ props.uncommitted_files }</span>
-                        }
-                    </button>
-                    <button class="bottom-bar-btn" onclick={move |_| on_push.emit(())} title="Push" aria-label={if props.commits_ahead > 0 { format!("Push ({} pending)", props.commits_ahead) } else { "Push".to_string() }}>
-                        <IconUpload />
-                        if props.commits_ahead > 0 {
-                            <span class="badge">{ props.commits_ahead }</span>
+                        if props.commits_ahead > 0 || props.commits_behind > 0 || props.uncommitted_files > 0 {
+                            <span class="badge">{"!"}</span>
                         }
                     </button>
-                </div>
 
-                // Mobile: Show Menu Toggle
-                <button
-                    class={classes!("bottom-bar-btn", "mobile-git-toggle", if *is_git_menu_open { "active" } else { "" })}
-                    onclick={toggle_git_menu}
-                    title="Git Actions" aria-label={if props.commits_ahead > 0 || props.commits_behind > 0 || props.uncommitted_files > 0 { "Git Actions (Attention required)" } else { "Git Actions" }}
-                    aria-expanded={(*is_git_menu_open).to_string()}
-                >
-                    <IconGitCommit />
-                    if props.commits_ahead > 0 || props.commits_behind > 0 || props.uncommitted_files > 0 {
-                        <span class="badge">{"!"}</span>
+                    // Mobile Menu Popup
+                    if *is_git_menu_open {
+                        <>
+                            <div
+                                class="menu-backdrop fixed inset-0 z-dropdown cursor-default"
+                                onclick={let close = close_git_menu.clone(); move |_| close.emit(())}
+                            />
+                            <div class="git-menu-popup">
+                                <button onclick={
+                                    let on_pull = props.on_pull.clone();
+                                    let close = close_git_menu.clone();
+                                    move |_| { on_pull.emit(()); close.emit(()); }
+                                } title="Pull" aria-label={if props.commits_behind > 0 { format!("Pull ({} pending)", props.commits_behind) } else { "Pull".to_string() }}>
+                                    <IconDownload />
+                                    <span>{"Pull"}</span>
+                                    if props.commits_behind > 0 {
+                                        <span class="badge ml-auto">{ props.commits_behind }</span>
+                                    }
+                                </button>
+                                <button onclick={
+                                    let on_commit = props.on_commit.clone();
+                                    let close = close_git_menu.clone();
+                                    move |_| { on_commit.emit(()); close.emit(()); }
+                                } title="Commit" aria-label={if props.uncommitted_files > 0 { format!("Commit ({} uncommitted files)", props.uncommitted_files) } else { "Commit".to_string() }}>
+                                    <IconGitCommit />
+                                    <span>{"Commit"}</span>
+                                    if props.uncommitted_files > 0 {
+                                        <span class="badge ml-auto">{ props.uncommitted_files }</span>
+                                    }
+                                </button>
+                                <button onclick={
+                                    let on_push = props.on_push.clone();
+                                    let close = close_git_menu.clone();
+                                    move |_| { on_push.emit(()); close.emit(()); }
+                                } title="Push" aria-label={if props.commits_ahead > 0 { format!("Push ({} pending)", props.commits_ahead) } else { "Push".to_string() }}>
+                                    <IconUpload />
+                                    <span>{"Push"}</span>
+                                    if props.commits_ahead > 0 {
+                                        <span class="badge ml-auto">{ props.commits_ahead }</span>
+                                    }
+                                </button>
+                            </div>
+                        </>
                     }
-                </button>
-
-                // Mobile Menu Popup
-                if *is_git_menu_open {
-                    <>
-                        <div
-                            class="menu-backdrop fixed inset-0 z-dropdown cursor-default"
-                            onclick={let close = close_git_menu.clone(); move |_| close.emit(())}
-                        />
-                        <div class="git-menu-popup">
-                            <button onclick={
-                                let on_pull = props.on_pull.clone();
-                                let close = close_git_menu.clone();
-                                move |_| { on_pull.emit(()); close.emit(()); }
-                            } title="Pull" aria-label={if props.commits_behind > 0 { format!("Pull ({} pending)", props.commits_behind) } else { "Pull".to_string() }}>
-                                <IconDownload />
-                                <span>{"Pull"}</span>
-                                if props.commits_behind > 0 {
-                                    <span class="badge ml-auto">{ props.commits_behind }</span>
-                                }
-                            </button>
-                            <button onclick={
-                                let on_commit = props.on_commit.clone();
-                                let close = close_git_menu.clone();
-                                move |_| { on_commit.emit(()); close.emit(()); }
-                            } title="Commit" aria-label={if props.uncommitted_files > 0 { format!("Commit ({} uncommitted files)", props.uncommitted_files) } else { "Commit".to_string() }}>
-                                <IconGitCommit />
-                                <span>{"Commit"}</span>
-                                if props.uncommitted_files > 0 {
-                                    <span class="badge ml-auto">{ props.uncommitted_files }</span>
-                                }
-                            </button>
-                            <button onclick={
-                                let on_push = props.on_push.clone();
-                                let close = close_git_menu.clone();
-                                move |_| { on_push.emit(()); close.emit(()); }
-                            } title="Push" aria-label={if props.commits_ahead > 0 { format!("Push ({} pending)", props.commits_ahead) } else { "Push".to_string() }}>
-                                <IconUpload />
-                                <span>{"Push"}</span>
-                                if props.commits_ahead > 0 {
-                                    <span class="badge ml-auto">{ props.commits_ahead }</span>
-                                }
-                            </button>
-                        </div>
-                    </>
-                }
-            </div>
+                </div>
+            }
 
             // Edit Action
              <button class="bottom-bar-btn" onclick={let on_copy_link = props.on_copy_link.clone(); move |_| on_copy_link.emit(())} title="Copy Link" aria-label="Copy Link">
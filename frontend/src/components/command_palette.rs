@@ -6,29 +6,9 @@ use yew::prelude::*;
 use yew_router::prelude::*;
 
 use crate::hooks::use_create_file;
-use crate::Route;
+use crate::{use_current_user, Route};
 use common::SearchResult;
 
-fn is_fuzzy_match(text: &str, query: &str) -> bool {
-    let mut query_chars = query.chars().peekable();
-    if query_chars.peek().is_none() {
-        return true;
-    }
-    for c in text.chars() {
-        for lc in c.to_lowercase() {
-            if let Some(&qc) = query_chars.peek() {
-                if lc == qc {
-                    query_chars.next();
-                    if query_chars.peek().is_none() {
-                        return true;
-                    }
-                }
-            }
-        }
-    }
-    false
-}
-
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub is_open: bool,
@@ -61,57 +41,26 @@ struct CommandItem {
     command_type: CommandType,
 }
 
-fn flatten_tree(node: &FileNode, acc: &mut Vec<String>) {
-    if !node.is_dir {
-        acc.push(node.path.clone());
-    }
-    if let Some(children) = &node.children {
-        for child in children {
-            flatten_tree(child, acc);
-        }
-    }
-}
-
 #[function_component(CommandPalette)]
 pub fn command_palette(props: &Props) -> Html {
     let query = use_state(String::new);
     let selected_index = use_state(|| 0);
     let search_results = use_state(Vec::<SearchResult>::new);
-    let file_list = use_state(Vec::<String>::new); // Client-side file list
     let volumes_list = use_state(Vec::<String>::new);
     let navigator = use_navigator();
     let input_ref = use_node_ref();
     let debounce_timer = use_state(|| None::<gloo_timers::callback::Timeout>);
     let last_request_timestamp = use_state(|| 0.0);
 
-    // Fetch file tree when volume changes or palette opens
+    // Fetch the volumes when the palette opens. Files are found through the
+    // server search, which matches file names too, so no volume is listed in
+    // full here.
     {
-        let file_list = file_list.clone();
         let volumes_list = volumes_list.clone();
-        let current_volume = props.current_volume.clone();
         let is_open = props.is_open;
 
-        use_effect_with((current_volume.clone(), is_open), move |(volume, open)| {
+        use_effect_with(is_open, move |open| {
             if *open {
-                let volume = volume.clone();
-
-                // Fetch files in current volume
-                spawn_local(async move {
-                    let url = format!("/api/tree?volume={}", volume);
-                    if let Ok(resp) = Request::get(&url).send().await {
-                        if resp.ok() {
-                            if let Ok(nodes) = resp.json::<Vec<FileNode>>().await {
-                                let mut paths = Vec::new();
-                                for node in nodes {
-                                    flatten_tree(&node, &mut paths);
-                                }
-                                file_list.set(paths);
-                            }
-                        }
-                    }
-                });
-
-                // Fetch available volumes
                 let volumes_list = volumes_list.clone();
                 spawn_local(async move {
                     if let Ok(resp) = Request::get("/api/tree").send().await {
@@ -135,7 +84,13 @@ pub fn command_palette(props: &Props) -> Html {
         crate::hooks::use_rename_file(props.current_volume.clone(), props.current_path.clone());
     let on_delete_file =
         crate::hooks::use_delete_file(props.current_volume.clone(), props.current_path.clone());
+    let on_duplicate_file = crate::hooks::use_duplicate(
+        props.current_volume.clone(),
+        props.current_path.clone(),
+        false,
+    );
 
+    let current_user = use_current_user();
     let static_commands = {
         let on_theme_toggle = props.on_theme_toggle.clone();
         let on_settings = props.on_settings.clone();
@@ -148,8 +103,14 @@ pub fn command_palette(props: &Props) -> Html {
         let on_copy_link = props.on_copy_link.clone();
         let on_rename_file = on_rename_file.clone();
         let on_delete_file = on_delete_file.clone();
-
-        let deps = (props.current_volume.clone(), props.current_path.clone());
+        let on_duplicate_file = on_duplicate_file.clone();
+
+        let can_write = current_user.can_write(&props.current_volume);
+        let deps = (
+            props.current_volume.clone(),
+            props.current_path.clone(),
+            can_write,
+        );
         use_memo(deps.clone(), move |_| {
             let on_theme_toggle = on_theme_toggle.clone();
             let on_settings = on_settings.clone();
@@ -162,6 +123,7 @@ pub fn command_palette(props: &Props) -> Html {
             let on_copy_link = on_copy_link.clone();
             let on_rename_file = on_rename_file.clone();
             let on_delete_file = on_delete_file.clone();
+            let on_duplicate_file = on_duplicate_file.clone();
             let mut commands = vec![
                 CommandItem {
                     title: "Go to Home".to_string(),
@@ -182,50 +144,58 @@ pub fn command_palette(props: &Props) -> Html {
                         on_settings.emit(());
                     })),
                 },
-                CommandItem {
-                    title: "Git Pull".to_string(),
-                    description: "Fetch and merge changes from the remote repository".to_string(),
-                    command_type: CommandType::Action(Callback::from(move |_| {
-                        on_pull.emit(());
-                    })),
-                },
-                CommandItem {
-                    title: "Git Push".to_string(),
-                    description: "Push local commits to the remote repository".to_string(),
-                    command_type: CommandType::Action(Callback::from(move |_| {
-                        on_push.emit(());
-                    })),
-                },
-                CommandItem {
-                    title: "Git Commit".to_string(),
-                    description: "Commit local changes".to_string(),
-                    command_type: CommandType::Action(Callback::from(move |_| {
-                        on_commit.emit(());
-                    })),
-                },
-                CommandItem {
-                    title: "Create New File".to_string(),
-                    description: "Create a new file in the current volume".to_string(),
-                    command_type: CommandType::CreateFile,
-                },
             ];
 
-            let (_current_volume, current_path) = &deps;
+            // Write actions are only offered where the user may write.
+            if can_write {
+                commands.extend([
+                    CommandItem {
+                        title: "Git Pull".to_string(),
+                        description: "Fetch and merge changes from the remote repository".to_string(),
+                        command_type: CommandType::Action(Callback::from(move |_| {
+                            on_pull.emit(());
+                        })),
+                    },
+                    CommandItem {
+                        title: "Git Push".to_string(),
+                        description: "Push local commits to the remote repository".to_string(),
+                        command_type: CommandType::Action(Callback::from(move |_| {
+                            on_push.emit(());
+                        })),
+                    },
+                    CommandItem {
+                        title: "Git Commit".to_string(),
+                        description: "Commit local changes".to_string(),
+                        command_type: CommandType::Action(Callback::from(move |_| {
+                            on_commit.emit(());
+                        })),
+                    },
+                    CommandItem {
+                        title: "Create New File".to_string(),
+                        description: "Create a new file in the current volume".to_string(),
+                        command_type: CommandType::CreateFile,
+                    },
+                ]);
+            }
+
+            let (_current_volume, current_path, _) = &deps;
             if !current_path.is_empty() {
-                commands.push(CommandItem {
-                    title: "Edit Current Page".to_string(),
-                    description: "Open the editor for the current page".to_string(),
-                    command_type: CommandType::Action(Callback::from(move |_| {
-                        on_edit.emit(());
-                    })),
-                });
-                commands.push(CommandItem {
-                    title: "Save Current Page".to_string(),
-                    description: "Save changes to the current page".to_string(),
-                    command_type: CommandType::Action(Callback::from(move |_| {
-                        on_save.emit(());
-                    })),
-                });
+                if can_write {
+                    commands.push(CommandItem {
+                        title: "Edit Current Page".to_string(),
+                        description: "Open the editor for the current page".to_string(),
+                        command_type: CommandType::Action(Callback::from(move |_| {
+                            on_edit.emit(());
+                        })),
+                    });
+                    commands.push(CommandItem {
+                        title: "Save Current Page".to_string(),
+                        description: "Save changes to the current page".to_string(),
+                        command_type: CommandType::Action(Callback::from(move |_| {
+                            on_save.emit(());
+                        })),
+                    });
+                }
                 commands.push(CommandItem {
                     title: "Copy Link".to_string(),
                     description: "Copy a wikilink to the current page to the clipboard".to_string(),
@@ -240,40 +210,44 @@ pub fn command_palette(props: &Props) -> Html {
                         on_history.emit(());
                     })),
                 });
-                commands.push(CommandItem {
-                    title: "Rename Current Page".to_string(),
-                    description: "Rename the file you are currently viewing".to_string(),
-                    command_type: CommandType::Action(on_rename_file),
-                });
-                commands.push(CommandItem {
-                    title: "Delete Current Page".to_string(),
-                    description: "Delete the file you are currently viewing".to_string(),
-                    command_type: CommandType::Action(on_delete_file),
-                });
+                if can_write {
+                    commands.push(CommandItem {
+                        title: "Rename Current Page".to_string(),
+                        description: "Rename the file you are currently viewing".to_string(),
+                        command_type: CommandType::Action(on_rename_file),
+                    });
+                    commands.push(CommandItem {
+                        title: "Duplicate Current Page".to_string(),
+                        description: "Copy the file you are currently viewing to a new path"
+                            .to_string(),
+                        command_type: CommandType::Action(on_duplicate_file),
+                    });
+                    commands.push(CommandItem {
+                        title: "Delete Current Page".to_string(),
+                        description: "Delete the file you are currently viewing".to_string(),
+                        command_type: CommandType::Action(on_delete_file),
+                    });
+                }
             }
 
             commands
         })
     };
 
-    // Filtered commands + file matches + server search results
+    // Filtered commands + server search results
     let filtered_items = {
         let query = query.clone();
         let static_commands = static_commands.clone();
         let search_results = search_results.clone();
-        let file_list = file_list.clone();
         let volumes_list = volumes_list.clone();
-        let current_volume = props.current_volume.clone();
 
         use_memo(
             (
                 (*query).clone(),
                 (*search_results).clone(),
-                (*file_list).clone(),
                 (*volumes_list).clone(),
-                current_volume,
             ),
-            move |(q, results, files, vols, volume)| {
+            move |(q, results, vols)| {
                 let mut items = Vec::new();
                 let q_lower = q.to_lowercase();
 
@@ -305,32 +279,8 @@ pub fn command_palette(props: &Props) -> Html {
                     }
                 }
 
-                // 2. Client-side file matches (if query length > 1)
-                if q.len() > 1 {
-                    let mut file_matches = 0;
-                    for path in files {
-                        if file_matches >= 10 {
-                            break;
-                        } // Limit file results
-                        if is_fuzzy_match(path, &q_lower) {
-                            items.push(CommandItem {
-                                title: path.clone(),
-                                description: format!("File in {}", volume),
-                                command_type: CommandType::Navigation(Route::Wiki {
-                                    volume: volume.clone(),
-                                    path: path.clone(),
-                                }),
-                            });
-                            file_matches += 1;
-                        }
-                    }
-                }
-
-                // 3. Server search results
+                // 2. Server search results, file name matches included
                 for result in results.iter() {
-                    // Avoid duplicates if client-side found it (simple check)
-                    // (Optional optimization: check if path is already in items)
-
                     let title = if let Some(ref v) = result.volume {
                         format!("{}: {}", v, result.path)
                     } else {
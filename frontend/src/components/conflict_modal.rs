@@ -0,0 +1,104 @@
+use common::merge::{diff_lines, merge3, DiffLine, CONFLICT_END, CONFLICT_START};
+use common::PageConflict;
+use yew::prelude::*;
+
+/// How the user chose to settle a save that conflicted with the server.
+#[derive(Clone, PartialEq)]
+pub enum ConflictResolution {
+    /// Keep editing the merged text, based on the server version.
+    Merged(String),
+    /// Save the local text over the server version.
+    KeepMine,
+    /// Drop the local changes and show the server version.
+    TakeServer,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ConflictModalProps {
+    /// The text the edit started from.
+    pub base: String,
+    /// The text that failed to save.
+    pub mine: String,
+    pub conflict: PageConflict,
+    pub on_resolve: Callback<ConflictResolution>,
+    pub on_cancel: Callback<()>,
+}
+
+#[function_component(ConflictModal)]
+pub fn conflict_modal(props: &ConflictModalProps) -> Html {
+    let show_diff = use_state(|| false);
+    let merge = use_memo(
+        (props.base.clone(), props.mine.clone(), props.conflict.content.clone()),
+        |(base, mine, server)| server.as_ref().map(|server| merge3(base, mine, server)),
+    );
+
+    let resolve = |resolution: ConflictResolution| {
+        let on_resolve = props.on_resolve.clone();
+        Callback::from(move |_| on_resolve.emit(resolution.clone()))
+    };
+    let on_cancel = props.on_cancel.clone();
+
+    let body = match (&*merge, &props.conflict.content) {
+        (Some(merge), Some(server)) => {
+            let summary = if merge.conflicts == 0 {
+                "Someone else saved this page while you were editing. Their changes and yours do not overlap and have been combined.".to_string()
+            } else {
+                format!(
+                    "Someone else saved this page while you were editing. {} place(s) were changed on both sides and are marked between \"{}\" and \"{}\".",
+                    merge.conflicts,
+                    CONFLICT_START.trim_end(),
+                    CONFLICT_END.trim_end()
+                )
+            };
+            html! {
+                <>
+                    <p class="mb-4">{ summary }</p>
+                    <div class="flex gap-2 mb-4">
+                        <button class={classes!("btn", (!*show_diff).then_some("btn-primary"))} onclick={let show_diff = show_diff.clone(); move |_| show_diff.set(false)} aria-pressed={(!*show_diff).to_string()}>{"Merged result"}</button>
+                        <button class={classes!("btn", (*show_diff).then_some("btn-primary"))} onclick={let show_diff = show_diff.clone(); move |_| show_diff.set(true)} aria-pressed={(*show_diff).to_string()}>{"Server → yours"}</button>
+                    </div>
+                    <pre class="diff-view border rounded p-2">
+                        if *show_diff {
+                            {for diff_lines(server, &props.mine).into_iter().map(|line| {
+                                // Each line is its own block, so its newline is not needed.
+                                let (class, sign, text) = match line {
+                                    DiffLine::Same(text) => (None, ' ', text),
+                                    DiffLine::Removed(text) => (Some("diff-removed"), '-', text),
+                                    DiffLine::Added(text) => (Some("diff-added"), '+', text),
+                                };
+                                html! { <span class={classes!(class)}>{ format!("{} {}", sign, text.trim_end_matches('\n')) }</span> }
+                            })}
+                        } else {
+                            { &merge.text }
+                        }
+                    </pre>
+                </>
+            }
+        }
+        _ => html! {
+            <p class="mb-4">{"This page was deleted on the server while you were editing. Saving will create it again with your text."}</p>
+        },
+    };
+
+    html! {
+        <div class="modal-overlay" onclick={let on_cancel = on_cancel.clone(); move |_| on_cancel.emit(())}>
+            <div class="modal-content conflict-modal" role="dialog" aria-labelledby="conflict-title" onclick={|e: MouseEvent| e.stop_propagation()}>
+                <div class="modal-header">
+                    <h2 id="conflict-title">{ format!("{} changed on the server", props.conflict.path) }</h2>
+                    <button class="btn-icon" onclick={let on_cancel = on_cancel.clone(); move |_| on_cancel.emit(())} title="Close" aria-label="Close">{"×"}</button>
+                </div>
+                <div class="modal-body">
+                    { body }
+                </div>
+                <div class="modal-footer">
+                    <button class="btn" onclick={move |_| on_cancel.emit(())} aria-label="Keep editing without saving">{"Cancel"}</button>
+                    <button class="btn btn-danger" onclick={resolve(ConflictResolution::TakeServer)} aria-label="Discard your changes">{"Discard mine"}</button>
+                    <button class="btn" onclick={resolve(ConflictResolution::KeepMine)} aria-label="Save your version over the server version">{ if merge.is_some() { "Overwrite with mine" } else { "Save mine" } }</button>
+                    if let Some(merge) = &*merge {
+                        <button class="btn btn-primary" onclick={resolve(ConflictResolution::Merged(merge.text.clone()))} aria-label="Review the merged text in the editor">{"Edit merged"}</button>
+                    }
+                </div>
+            </div>
+        </div>
+    }
+}
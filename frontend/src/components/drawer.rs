@@ -1,7 +1,13 @@
-use crate::components::icons::{IconCopy, IconEdit, IconPlus, IconSearch, IconTrash, IconUpload};
-use crate::hooks::{use_create_file, use_delete_file, use_rename_file};
-use crate::Route;
-use common::FileNode;
+use crate::components::icons::{
+    IconCopy, IconDuplicate, IconEdit, IconPlus, IconSearch, IconTrash, IconUpload,
+};
+use crate::components::trash_list::TrashList;
+use crate::hooks::{
+    file_list, upload_files, use_create_file, use_delete_file, use_duplicate, use_rename_file,
+    use_rename_folder,
+};
+use crate::{use_current_user, Route};
+use common::{DirectoryListing, FileNode};
 use gloo_net::http::Request;
 use web_sys::{Event, HtmlInputElement, KeyboardEvent, MouseEvent};
 use yew::prelude::*;
@@ -24,6 +30,8 @@ pub fn drawer(props: &DrawerProps) -> Html {
         _ => "default".to_string(),
     };
 
+    let can_write = use_current_user().can_write(&current_volume);
+    let show_trash = use_state(|| false);
     let create_file = use_create_file(current_volume.clone());
     let on_new_file = Callback::from(move |_| create_file.emit(()));
 
@@ -43,38 +51,42 @@ pub fn drawer(props: &DrawerProps) -> Html {
         Callback::from(move |e: Event| {
             let input: HtmlInputElement = e.target_unchecked_into();
             if let Some(files) = input.files() {
-                if let Some(file) = files.item(0) {
-                    let file_name = file.name();
-                    let volume = current_volume.clone();
-
-                    wasm_bindgen_futures::spawn_local(async move {
-                        let path = format!("assets/images/{}", file_name);
-                        let url = format!("/api/upload/{}/{}", volume, path);
-
-                        let request = match Request::post(&url).body(file) {
-                            Ok(req) => req,
-                            Err(e) => {
-                                gloo_dialogs::alert(&format!("Failed to construct request: {}", e));
-                                return;
-                            }
-                        };
-
-                        let resp = request.send().await;
-
-                        match resp {
-                            Ok(r) if r.ok() => {
-                                gloo_dialogs::alert(&format!("Successfully uploaded to {}", path));
-                            }
-                            Ok(r) => {
-                                let err = r.text().await.unwrap_or_default();
-                                gloo_dialogs::alert(&format!("Failed to upload: {}", err));
-                            }
-                            Err(e) => {
-                                gloo_dialogs::alert(&format!("Error uploading file: {}", e));
+                let files = file_list(&files);
+                let volume = current_volume.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let uploaded = upload_files(&volume, files).await;
+                    if uploaded.is_empty() {
+                        return;
+                    }
+                    // Put the snippets on the clipboard, ready to paste into a page.
+                    let snippets: Vec<&str> =
+                        uploaded.iter().map(|file| file.markdown.as_str()).collect();
+                    let clipboard = gloo_utils::window().navigator().clipboard();
+                    let copied = wasm_bindgen_futures::JsFuture::from(
+                        clipboard.write_text(&snippets.join("\n")),
+                    )
+                    .await
+                    .is_ok();
+                    let stored: Vec<String> = uploaded
+                        .iter()
+                        .map(|file| {
+                            if file.deduplicated {
+                                format!("{} (already uploaded)", file.path)
+                            } else {
+                                file.path.clone()
                             }
+                        })
+                        .collect();
+                    gloo_dialogs::alert(&format!(
+                        "Uploaded to:\n{}{}",
+                        stored.join("\n"),
+                        if copied {
+                            "\n\nMarkdown for the files was copied to the clipboard."
+                        } else {
+                            ""
                         }
-                    });
-                }
+                    ));
+                });
             }
             // Clear input value so same file can be uploaded again if needed
             input.set_value("");
@@ -94,23 +106,36 @@ pub fn drawer(props: &DrawerProps) -> Html {
                     <button class="btn-icon mr-2" onclick={move |_| on_search.emit(())} title="Search" aria-label="Search">
                         <IconSearch />
                     </button>
-                    <button class="btn-icon mr-2" onclick={on_upload_click} title="Upload Image" aria-label="Upload Image">
-                        <IconUpload />
-                    </button>
-                    <input
-                        type="file"
-                        ref={file_input_ref}
-                        class="d-none"
-                        onchange={on_file_change}
-                        accept="image/*"
-                    />
-                    <button class="btn-icon mr-2" onclick={on_new_file} title="New File" aria-label="New File">
-                        <IconPlus />
+                    if can_write {
+                        <button class="btn-icon mr-2" onclick={on_upload_click} title="Upload Files" aria-label="Upload Files">
+                            <IconUpload />
+                        </button>
+                        <input
+                            type="file"
+                            ref={file_input_ref}
+                            class="d-none"
+                            onchange={on_file_change}
+                            multiple=true
+                        />
+                        <button class="btn-icon mr-2" onclick={on_new_file} title="New File" aria-label="New File">
+                            <IconPlus />
+                        </button>
+                    }
+                    <button
+                        class={classes!("btn-icon", "mr-2", (*show_trash).then_some("active"))}
+                        onclick={let show_trash = show_trash.clone(); move |_| show_trash.set(!*show_trash)}
+                        title="Trash" aria-label="Show Trash" aria-pressed={(*show_trash).to_string()}
+                    >
+                        <IconTrash />
                     </button>
                     <button class="btn-icon" onclick={on_close} title="Close" aria-label="Close Drawer">{"✕"}</button>
                 </div>
                 <div class="drawer-content">
-                    <FileTree />
+                    if *show_trash {
+                        <TrashList volume={current_volume.clone()} can_write={can_write} />
+                    } else {
+                        <FileTree />
+                    }
                 </div>
             </div>
         </>
@@ -171,7 +196,6 @@ fn volume_switcher() -> Html {
 
 #[function_component(FileTree)]
 fn file_tree() -> Html {
-    let tree = use_state(Vec::<FileNode>::new);
     let route = use_route::<Route>();
 
     let current_volume = match route {
@@ -179,36 +203,130 @@ fn file_tree() -> Html {
         _ => "default".to_string(),
     };
 
-    {
-        let tree = tree.clone();
-        let volume = current_volume.clone();
-        use_effect_with(volume, move |volume| {
-            let tree = tree.clone();
-            let volume = volume.clone();
+    html! {
+        <div class="file-tree">
+            <h3>{ "Files" }</h3>
+            <Directory volume={current_volume} path={String::new()} />
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq, Clone)]
+struct DirectoryProps {
+    volume: String,
+    /// The folder to list; empty for the volume root.
+    path: String,
+}
+
+/// The entries of one folder, fetched when it is shown and a page at a time.
+#[function_component(Directory)]
+fn directory(props: &DirectoryProps) -> Html {
+    let entries = use_state(Vec::<FileNode>::new);
+    let next_offset = use_state(|| Option::<usize>::None);
+    let loaded = use_state(|| false);
+
+    let load = {
+        let entries = entries.clone();
+        let next_offset = next_offset.clone();
+        let loaded = loaded.clone();
+        let volume = props.volume.clone();
+        let path = props.path.clone();
+        Callback::from(move |offset: usize| {
+            let entries = entries.clone();
+            let next_offset = next_offset.clone();
+            let loaded = loaded.clone();
+            let url = if path.is_empty() {
+                format!("/api/list/{}?offset={}", volume, offset)
+            } else {
+                format!("/api/list/{}/{}?offset={}", volume, path, offset)
+            };
             wasm_bindgen_futures::spawn_local(async move {
-                let url = format!("/api/tree?volume={}", volume);
                 if let Ok(response) = Request::get(&url).send().await {
-                    let fetched_tree: Vec<FileNode> = response.json().await.unwrap_or_default();
-                    tree.set(fetched_tree);
+                    if let Ok(listing) = response.json::<DirectoryListing>().await {
+                        let mut shown = if offset == 0 {
+                            Vec::new()
+                        } else {
+                            (*entries).clone()
+                        };
+                        shown.extend(listing.entries);
+                        entries.set(shown);
+                        next_offset.set(listing.next_offset);
+                    }
                 }
+                loaded.set(true);
             });
+        })
+    };
+
+    {
+        let load = load.clone();
+        use_effect_with((props.volume.clone(), props.path.clone()), move |_| {
+            load.emit(0);
             || ()
         });
     }
 
+    if !*loaded {
+        return html! {};
+    }
+    if entries.is_empty() && props.path.is_empty() {
+        return html! {
+            <div class="p-4 text-muted text-sm">
+                {"No files found. Use the + button above to create one."}
+            </div>
+        };
+    }
     html! {
-        <div class="file-tree">
-            <h3>{ "Files" }</h3>
-            if tree.is_empty() {
-                <div class="p-4 text-muted text-sm">
-                    {"No files found. Use the + button above to create one."}
-                </div>
-            } else {
-                <ul>
-                    { for tree.iter().map(|node| html! { <FileTreeNode node={node.clone()} volume={current_volume.clone()} /> }) }
-                </ul>
+        <ul>
+            { for entries.iter().map(|node| html! { <FileTreeNode key={node.path.clone()} node={node.clone()} volume={props.volume.clone()} /> }) }
+            if let Some(offset) = *next_offset {
+                <li>
+                    <button class="btn text-sm" onclick={move |_| load.emit(offset)}>
+                        { "Show more" }
+                    </button>
+                </li>
             }
-        </div>
+        </ul>
+    }
+}
+
+/// A tooltip with what is known about an entry.
+fn describe(node: &FileNode) -> String {
+    let mut parts = Vec::new();
+    if let Some(size) = node.size {
+        parts.push(if size < 1024 {
+            format!("{} bytes", size)
+        } else if size < 1024 * 1024 {
+            format!("{:.1} KB", size as f64 / 1024.0)
+        } else {
+            format!("{:.1} MB", size as f64 / (1024.0 * 1024.0))
+        });
+    }
+    if let Some(count) = node.child_count {
+        parts.push(format!("{} items", count));
+    }
+    if let Some(modified) = node.modified {
+        let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(modified as f64 * 1000.0));
+        parts.push(format!(
+            "modified {}",
+            String::from(date.to_locale_string("default", &wasm_bindgen::JsValue::UNDEFINED))
+        ));
+    }
+    if let Some(status) = &node.git_status {
+        parts.push(format!("{} (uncommitted)", status));
+    }
+    parts.join(" · ")
+}
+
+/// A one-letter marker for uncommitted changes.
+fn git_badge(node: &FileNode) -> Html {
+    match &node.git_status {
+        Some(status) => html! {
+            <span class={classes!("git-badge", format!("git-{}", status.to_lowercase()))} title={status.clone()} aria-label={status.clone()}>
+                { status.chars().next().unwrap_or('?') }
+            </span>
+        },
+        None => html! {},
     }
 }
 
@@ -225,8 +343,12 @@ fn file_tree_node(props: &FileTreeNodeProps) -> Html {
 
     // Hooks must be at the top level of function components
     let on_rename_hook = use_rename_file(volume.clone(), node.path.clone());
+    let on_rename_folder_hook = use_rename_folder(volume.clone(), node.path.clone());
+    let on_duplicate_hook = use_duplicate(volume.clone(), node.path.clone(), node.is_dir);
     let on_delete_hook = use_delete_file(volume.clone(), node.path.clone());
 
+    let can_write = use_current_user().can_write(volume);
+
     // Default to collapsed for directories
     let is_expanded = use_state(|| false);
 
@@ -238,6 +360,15 @@ fn file_tree_node(props: &FileTreeNodeProps) -> Html {
         })
     };
 
+    let on_duplicate_click = {
+        let on_duplicate_hook = on_duplicate_hook.clone();
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            e.stop_propagation();
+            on_duplicate_hook.emit(());
+        })
+    };
+
     if node.is_dir {
         let icon = if *is_expanded { "▼" } else { "▶" };
         let on_keydown = {
@@ -251,27 +382,44 @@ fn file_tree_node(props: &FileTreeNodeProps) -> Html {
             })
         };
         let dir_name = node.name.clone();
+        let on_rename_folder_click = {
+            let on_rename_folder_hook = on_rename_folder_hook.clone();
+            Callback::from(move |e: MouseEvent| {
+                e.prevent_default();
+                e.stop_propagation();
+                on_rename_folder_hook.emit(());
+            })
+        };
 
         html! {
             <li>
-                <div
-                    onclick={toggle_expanded}
-                    onkeydown={on_keydown}
-                    role="button"
-                    tabindex="0"
-                    aria-expanded={if *is_expanded { "true" } else { "false" }}
-                    aria-label={format!("Toggle directory {}", dir_name)}
-                    class="flex items-center rounded-md cursor-pointer"
-                >
-                    <span class="tree-toggle">{ icon }</span>
-                    <span class="folder-label folder">{ &node.name }</span>
+                <div class="file-tree-item">
+                    <div
+                        onclick={toggle_expanded}
+                        onkeydown={on_keydown}
+                        role="button"
+                        tabindex="0"
+                        aria-expanded={if *is_expanded { "true" } else { "false" }}
+                        aria-label={format!("Toggle directory {}", dir_name)}
+                        class="flex flex-1 min-w-0 items-center rounded-md cursor-pointer"
+                    >
+                        <span class="tree-toggle">{ icon }</span>
+                        <span class="folder-label folder" title={describe(node)}>{ &node.name }</span>
+                        { git_badge(node) }
+                    </div>
+                    if can_write {
+                        <div class="file-tree-actions flex gap-1">
+                            <button class="btn-icon" onclick={on_rename_folder_click} title={format!("Rename {}", dir_name)} aria-label={format!("Rename folder {}", dir_name)}>
+                                <IconEdit />
+                            </button>
+                            <button class="btn-icon" onclick={on_duplicate_click} title={format!("Duplicate {}", dir_name)} aria-label={format!("Duplicate folder {}", dir_name)}>
+                                <IconDuplicate />
+                            </button>
+                        </div>
+                    }
                 </div>
                 if *is_expanded {
-                    if let Some(children) = &node.children {
-                        <ul>
-                            { for children.iter().map(|child| html! { <FileTreeNode node={child.clone()} volume={volume.clone()} /> }) }
-                        </ul>
-                    }
+                    <Directory volume={volume.clone()} path={node.path.clone()} />
                 }
             </li>
         }
@@ -316,15 +464,21 @@ fn file_tree_node(props: &FileTreeNodeProps) -> Html {
 
         // Link to /wiki/path/to/file
         html! {
-            <li class="file-tree-item">
+            <li class="file-tree-item" title={describe(node)}>
                 <Link<Route> to={Route::Wiki { volume: volume.clone(), path: node.path.clone() }}>{ &node.name }</Link<Route>>
+                { git_badge(node) }
                 <div class="file-tree-actions flex gap-1">
-                    <button class="btn-icon" onclick={on_rename_click} title={format!("Rename {}", file_name)} aria-label={format!("Rename {}", file_name)}>
-                        <IconEdit />
-                    </button>
-                    <button class="btn-icon" onclick={on_delete_click} title={format!("Delete {}", file_name)} aria-label={format!("Delete {}", file_name)}>
-                        <IconTrash />
-                    </button>
+                    if can_write {
+                        <button class="btn-icon" onclick={on_rename_click} title={format!("Rename {}", file_name)} aria-label={format!("Rename {}", file_name)}>
+                            <IconEdit />
+                        </button>
+                        <button class="btn-icon" onclick={on_duplicate_click} title={format!("Duplicate {}", file_name)} aria-label={format!("Duplicate {}", file_name)}>
+                            <IconDuplicate />
+                        </button>
+                        <button class="btn-icon" onclick={on_delete_click} title={format!("Delete {}", file_name)} aria-label={format!("Delete {}", file_name)}>
+                            <IconTrash />
+                        </button>
+                    }
                     <button class="btn-icon" onclick={on_copy_link} title={format!("Copy Link to {}", file_name)} aria-label={format!("Copy Link to {}", file_name)}>
                         <IconCopy />
                     </button>
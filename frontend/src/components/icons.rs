@@ -140,6 +140,18 @@ pub fn icon_copy() -> Html {
     }
 }
 
+#[function_component(IconDuplicate)]
+pub fn icon_duplicate() -> Html {
+    html! {
+        <svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24" width="16" height="16" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round">
+            <rect x="9" y="9" width="13" height="13" rx="2" ry="2"></rect>
+            <path d="M5 15H4a2 2 0 0 1-2-2V4a2 2 0 0 1 2-2h9a2 2 0 0 1 2 2v1"></path>
+            <line x1="15.5" y1="12.5" x2="15.5" y2="18.5"></line>
+            <line x1="12.5" y1="15.5" x2="18.5" y2="15.5"></line>
+        </svg>
+    }
+}
+
 #[function_component(IconTrash)]
 pub fn icon_trash() -> Html {
     html! {
@@ -1,6 +1,10 @@
+pub mod admin_modal;
 pub mod bottom_bar;
 pub mod command_palette;
+pub mod conflict_modal;
 pub mod drawer;
 pub mod history_modal;
 pub mod icons;
+pub mod session_list;
 pub mod settings_modal;
+pub mod trash_list;
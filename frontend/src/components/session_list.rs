@@ -0,0 +1,85 @@
+use crate::csrf;
+use crate::hooks::use_remote_list;
+use common::SessionInfo;
+use yew::prelude::*;
+
+fn format_time(timestamp: Option<i64>) -> String {
+    match timestamp {
+        Some(secs) => {
+            let date = js_sys::Date::new_0();
+            date.set_time((secs as f64) * 1000.0);
+            String::from(date.to_locale_string("en-US", &js_sys::Object::new()))
+        }
+        None => "unknown".to_string(),
+    }
+}
+
+async fn revoke(url: &str) -> Result<(), String> {
+    match csrf::delete(url).send().await {
+        Ok(resp) if resp.ok() => Ok(()),
+        Ok(resp) => Err(format!("Server returned {}", resp.status())),
+        Err(e) => Err(format!("Network error: {}", e)),
+    }
+}
+
+/// The signed-in user's browser sessions, with buttons to end them.
+#[function_component(SessionList)]
+pub fn session_list() -> Html {
+    let list = use_remote_list::<SessionInfo>("/api/sessions".to_string());
+    let sessions = list.items.clone();
+    let error = list.error.clone();
+
+    let run = move |url: String, current: bool| {
+        list.apply(async move {
+            revoke(&url).await?;
+            // Ending this session logs the browser out.
+            if current {
+                let _ = gloo_utils::window().location().set_href("/login");
+            }
+            Ok(())
+        })
+    };
+
+    let on_revoke_others = {
+        let run = run.clone();
+        Callback::from(move |_| run("/api/sessions".to_string(), false))
+    };
+
+    html! {
+        <div class="flex flex-col gap-2 mb-4">
+            if let Some(err) = &*error {
+                <div class="error-msg" role="alert">{ err }</div>
+            }
+            {for sessions.iter().map(|info| {
+                let on_revoke = {
+                    let run = run.clone();
+                    let url = format!("/api/sessions/{}", info.id);
+                    let current = info.current;
+                    Callback::from(move |_| run(url.clone(), current))
+                };
+                html! {
+                    <div class="border rounded p-2">
+                        <div class="font-bold mb-1">
+                            { info.user_agent.clone().unwrap_or_else(|| "Unknown browser".to_string()) }
+                            if info.current {
+                                <span class="text-sm text-muted">{" (this browser)"}</span>
+                            }
+                        </div>
+                        <div class="text-sm text-muted">
+                            { format!("Last seen {} from {}", format_time(info.last_seen), info.ip.as_deref().unwrap_or("unknown address")) }
+                        </div>
+                        <div class="text-sm text-muted mb-1">
+                            { format!("Signed in {}", format_time(info.created_at)) }
+                        </div>
+                        <button class="btn" onclick={on_revoke} aria-label={if info.current { "Log out this browser".to_string() } else { format!("Log out session {}", info.id) }}>
+                            { if info.current { "Log out" } else { "Revoke" } }
+                        </button>
+                    </div>
+                }
+            })}
+            if sessions.len() > 1 {
+                <button class="btn" onclick={on_revoke_others} aria-label="Log out all other sessions">{"Log out everywhere else"}</button>
+            }
+        </div>
+    }
+}
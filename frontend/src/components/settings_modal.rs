@@ -1,15 +1,19 @@
 use gloo_storage::{LocalStorage, Storage};
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
+use crate::components::session_list::SessionList;
 use crate::hooks::Shortcuts;
+use crate::use_current_user;
 
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub on_close: Callback<()>,
+    pub on_manage_users: Callback<()>,
 }
 
 #[function_component(SettingsModal)]
 pub fn settings_modal(props: &Props) -> Html {
+    let is_admin = use_current_user().is_admin();
     let shortcuts = use_state(|| LocalStorage::get::<Shortcuts>("shortcuts").unwrap_or_else(|_| Shortcuts::default()));
     
     let author_name = use_state(|| {
@@ -99,6 +103,20 @@ pub fn settings_modal(props: &Props) -> Html {
                         <input id="author-email" type="text" value={(*author_email).clone()} oninput={on_author_email_input} placeholder="e.g. john@example.com" />
                     </div>
                     <hr class="border-0 border-t mt-3 mb-3" />
+                    <h3>{"Sessions"}</h3>
+                    <p class="text-sm text-muted mb-4">
+                        {"Browsers signed in to your account. Log out any you do not recognise."}
+                    </p>
+                    <SessionList />
+                    if is_admin {
+                        <hr class="border-0 border-t mt-3 mb-3" />
+                        <h3>{"Users"}</h3>
+                        <p class="text-sm text-muted mb-4">
+                            {"Add users, change their permissions and end their sessions."}
+                        </p>
+                        <button class="btn mb-4" onclick={let on_manage_users = props.on_manage_users.clone(); move |_| on_manage_users.emit(())} aria-label="Manage users">{"Manage Users"}</button>
+                    }
+                    <hr class="border-0 border-t mt-3 mb-3" />
                     <h3>{"Keyboard Shortcuts"}</h3>
                     <p class="text-sm text-muted mb-4">
                         {"Use modifiers like Ctrl, Cmd, Shift, Alt. Combine with '+'. Example: 'Ctrl+Shift+C'. Separate alternatives with commas."}
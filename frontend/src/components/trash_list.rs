@@ -0,0 +1,122 @@
+use crate::csrf;
+use crate::hooks::use_remote_list;
+use common::TrashItem;
+use yew::prelude::*;
+
+#[derive(Properties, PartialEq)]
+pub struct TrashListProps {
+    pub volume: String,
+    pub can_write: bool,
+}
+
+fn format_time(secs: i64) -> String {
+    let date = js_sys::Date::new_0();
+    date.set_time((secs as f64) * 1000.0);
+    String::from(date.to_locale_string("en-US", &js_sys::Object::new()))
+}
+
+/// Deleted files of a volume, with buttons to restore or purge them.
+#[function_component(TrashList)]
+pub fn trash_list(props: &TrashListProps) -> Html {
+    let list = use_remote_list::<TrashItem>(format!("/api/trash/{}", props.volume));
+    let items = list.items.clone();
+    let error = list.error.clone();
+
+    let on_restore = {
+        let volume = props.volume.clone();
+        let error = error.clone();
+        move |item: &TrashItem| {
+            let url = format!("/api/trash/{}/{}/restore", volume, item.id);
+            let target = if item.is_dir {
+                format!("/wiki/{}", volume)
+            } else {
+                format!("/wiki/{}/{}", volume, item.path)
+            };
+            let error = error.clone();
+            Callback::from(move |_: MouseEvent| {
+                let url = url.clone();
+                let target = target.clone();
+                let error = error.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    match csrf::post(&url).send().await {
+                        // Reload so the file tree shows the restored item.
+                        Ok(resp) if resp.ok() => {
+                            let _ = gloo_utils::window().location().set_href(&target);
+                        }
+                        Ok(resp) => {
+                            let text = resp.text().await.unwrap_or_default();
+                            error.set(Some(format!("Failed to restore: {}", text)));
+                        }
+                        Err(e) => error.set(Some(format!("Network error: {}", e))),
+                    }
+                });
+            })
+        }
+    };
+
+    let purge = move |url: String, prompt: String| {
+        let list = list.clone();
+        Callback::from(move |_: MouseEvent| {
+            if !gloo_dialogs::confirm(&prompt) {
+                return;
+            }
+            let url = url.clone();
+            list.apply(async move {
+                match csrf::delete(&url).send().await {
+                    Ok(resp) if resp.ok() => Ok(()),
+                    Ok(resp) => {
+                        let text = resp.text().await.unwrap_or_default();
+                        Err(format!("Failed to delete: {}", text))
+                    }
+                    Err(e) => Err(format!("Network error: {}", e)),
+                }
+            });
+        })
+    };
+
+    html! {
+        <div class="file-tree trash-list">
+            <h3>{ "Trash" }</h3>
+            if let Some(err) = &*error {
+                <div class="error-msg" role="alert">{ err }</div>
+            }
+            if items.is_empty() {
+                <div class="p-4 text-muted text-sm">{"The trash is empty."}</div>
+            } else {
+                <ul>
+                    {for items.iter().map(|item| html! {
+                        <li class="file-tree-item">
+                            <div class="min-w-0">
+                                <div class={classes!(item.is_dir.then_some("folder"))}>{ &item.path }</div>
+                                <div class="text-sm text-muted">
+                                    { format!("Deleted by {} on {}", item.deleted_by, format_time(item.deleted_at)) }
+                                </div>
+                            </div>
+                            if props.can_write {
+                                <div class="file-tree-actions flex gap-1">
+                                    <button class="btn" onclick={on_restore(item)} aria-label={format!("Restore {}", item.path)}>{"Restore"}</button>
+                                    <button
+                                        class="btn btn-danger"
+                                        onclick={purge(format!("/api/trash/{}/{}", props.volume, item.id), format!("Permanently delete {}?", item.path))}
+                                        aria-label={format!("Permanently delete {}", item.path)}
+                                    >
+                                        {"Delete"}
+                                    </button>
+                                </div>
+                            }
+                        </li>
+                    })}
+                </ul>
+                if props.can_write {
+                    <button
+                        class="btn btn-danger mt-2"
+                        onclick={purge(format!("/api/trash/{}", props.volume), "Permanently delete everything in the trash?".to_string())}
+                        aria-label="Empty trash"
+                    >
+                        {"Empty trash"}
+                    </button>
+                }
+            }
+        </div>
+    }
+}
@@ -0,0 +1,39 @@
+//! Requests that change state must echo the server's `XSRF-TOKEN` cookie in
+//! the `X-CSRF-Token` header; these builders add it.
+
+use gloo_net::http::{Request, RequestBuilder};
+use wasm_bindgen::JsCast;
+
+const CSRF_COOKIE: &str = "XSRF-TOKEN";
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+fn token() -> Option<String> {
+    let document = web_sys::window()?
+        .document()?
+        .dyn_into::<web_sys::HtmlDocument>()
+        .ok()?;
+    let cookies = document.cookie().ok()?;
+    cookies.split(';').find_map(|cookie| {
+        let (name, value) = cookie.trim().split_once('=')?;
+        (name == CSRF_COOKIE).then(|| value.to_string())
+    })
+}
+
+fn with_token(builder: RequestBuilder) -> RequestBuilder {
+    match token() {
+        Some(token) => builder.header(CSRF_HEADER, &token),
+        None => builder,
+    }
+}
+
+pub fn post(url: &str) -> RequestBuilder {
+    with_token(Request::post(url))
+}
+
+pub fn put(url: &str) -> RequestBuilder {
+    with_token(Request::put(url))
+}
+
+pub fn delete(url: &str) -> RequestBuilder {
+    with_token(Request::delete(url))
+}
@@ -6,6 +6,7 @@ use yew::prelude::*;
 use yew_router::prelude::*;
 use serde::{Deserialize, Serialize};
 use gloo_storage::{LocalStorage, Storage};
+use gloo_net::http::Request;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Shortcuts {
@@ -62,6 +63,54 @@ pub fn matches_shortcut(e: &KeyboardEvent, shortcut_def: &str) -> bool {
     false
 }
 
+/// Asks the user for a template if the volume has any, and creates the page
+/// from it. Returns whether to open the page, which is still blank if no
+/// template was picked.
+async fn create_from_template(volume: &str, path: &str) -> bool {
+    let templates = match Request::get(&format!("/api/templates/{}", volume)).send().await {
+        Ok(resp) if resp.ok() => resp.json::<Vec<TemplateInfo>>().await.unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    if templates.is_empty() {
+        return true;
+    }
+    let names: Vec<&str> = templates.iter().map(|t| t.name.as_str()).collect();
+    let choice = gloo_dialogs::prompt(
+        &format!(
+            "Start from a template ({}), or leave empty for a blank page:",
+            names.join(", ")
+        ),
+        None,
+    )
+    .unwrap_or_default();
+    let choice = choice.trim();
+    if choice.is_empty() {
+        return true;
+    }
+    if !names.contains(&choice) {
+        gloo_dialogs::alert(&format!("There is no template named '{}'.", choice));
+        return false;
+    }
+
+    let payload = CreateFromTemplate {
+        template: choice.to_string(),
+        utc_offset_minutes: -(js_sys::Date::new_0().get_timezone_offset() as i32),
+    };
+    let url = format!("/api/templates/{}/{}", volume, path);
+    match csrf::post(&url).json(&payload).unwrap().send().await {
+        Ok(resp) if resp.ok() => true,
+        Ok(resp) => {
+            let text = resp.text().await.unwrap_or_default();
+            gloo_dialogs::alert(&format!("Failed to create the page: {}", text));
+            false
+        }
+        Err(e) => {
+            gloo_dialogs::alert(&format!("Network error: {}", e));
+            false
+        }
+    }
+}
+
 #[hook]
 pub fn use_create_file(current_volume: String) -> Callback<()> {
     let navigator_opt = use_navigator();
@@ -71,9 +120,13 @@ pub fn use_create_file(current_volume: String) -> Callback<()> {
             if let Some(path) = gloo_dialogs::prompt("Enter file path (e.g. folder/note.md):", None)
             {
                 if !path.trim().is_empty() {
-                    navigator.push(&Route::Wiki {
-                        volume: current_volume.clone(),
-                        path: path.trim().to_string(),
+                    let navigator = navigator.clone();
+                    let volume = current_volume.clone();
+                    let path = path.trim().to_string();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if create_from_template(&volume, &path).await {
+                            navigator.push(&Route::Wiki { volume, path });
+                        }
                     });
                 }
             }
@@ -176,8 +229,107 @@ pub fn use_key_handler(props: KeyHandlerProps) {
     }
 }
 
-use common::RenameRequest;
-use gloo_net::http::Request;
+use crate::csrf;
+use common::{
+    CopyRequest, CreateFromTemplate, LinkUpdateFailure, RenameReport, RenameRequest, TemplateInfo,
+    UploadedFile,
+};
+
+/// Sends a rename request, sending the user to the login page if their
+/// session has expired and reporting other failures to them.
+async fn request_rename(
+    volume: &str,
+    path: &str,
+    new_path: &str,
+    dry_run: bool,
+) -> Option<RenameReport> {
+    let url = format!("/api/rename/{}/{}", volume, path);
+    let payload = RenameRequest {
+        new_path: new_path.to_string(),
+        dry_run,
+    };
+    let resp = csrf::post(&url).json(&payload).unwrap().send().await;
+    match resp {
+        Ok(r) if r.status() == 401 => {
+            let current_path = gloo_utils::window()
+                .location()
+                .pathname()
+                .unwrap_or_default();
+            if current_path != "/login" {
+                let _ = gloo_utils::window().location().set_href("/login");
+            }
+            None
+        }
+        Ok(r) if r.ok() => match r.json::<RenameReport>().await {
+            Ok(report) => Some(report),
+            Err(e) => {
+                gloo_dialogs::alert(&format!("Failed to read the rename result: {}", e));
+                None
+            }
+        },
+        Ok(r) => {
+            let text = r.text().await.unwrap_or_default();
+            gloo_dialogs::alert(&format!("Failed to rename: {}", text));
+            None
+        }
+        Err(e) => {
+            gloo_dialogs::alert(&format!("Network error: {}", e));
+            None
+        }
+    }
+}
+
+fn describe_failures(failed: &[LinkUpdateFailure]) -> String {
+    failed
+        .iter()
+        .map(|failure| format!("  {}:{}: {}", failure.volume, failure.path, failure.error))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Moves `path` to `new_path` after showing the user the link edits it will
+/// make and asking them to confirm. Returns whether the move happened.
+async fn send_rename(volume: &str, path: &str, new_path: &str) -> bool {
+    let Some(preview) = request_rename(volume, path, new_path, true).await else {
+        return false;
+    };
+    if !preview.updated.is_empty() || !preview.failed.is_empty() {
+        let mut message = format!("Rename {} to {}?", path, new_path);
+        if !preview.updated.is_empty() {
+            message.push_str(&format!(
+                "\n\nLinks will be updated in {} page(s):",
+                preview.updated.len()
+            ));
+            for update in &preview.updated {
+                message.push_str(&format!("\n\n{}:{}", update.volume, update.path));
+                for edit in &update.edits {
+                    message.push_str(&format!("\n  - {}\n  + {}", edit.before, edit.after));
+                }
+            }
+        }
+        if !preview.failed.is_empty() {
+            message.push_str(&format!(
+                "\n\nLinks cannot be updated in these pages:\n{}",
+                describe_failures(&preview.failed)
+            ));
+        }
+        if !gloo_dialogs::confirm(&message) {
+            return false;
+        }
+    }
+
+    let Some(report) = request_rename(volume, path, new_path, false).await else {
+        return false;
+    };
+    if !report.failed.is_empty() {
+        gloo_dialogs::alert(&format!(
+            "Renamed, and updated links in {} page(s), but not in:\n{}",
+            report.updated.len(),
+            describe_failures(&report.failed)
+        ));
+    }
+    true
+}
 
 #[hook]
 pub fn use_rename_file(current_volume: String, current_path: String) -> Callback<()> {
@@ -190,34 +342,13 @@ pub fn use_rename_file(current_volume: String, current_path: String) -> Callback
                 let volume = current_volume.clone();
                 let navigator = navigator.clone();
                 wasm_bindgen_futures::spawn_local(async move {
-                    let url = format!("/api/rename/{}/{}", volume, path);
-                    let payload = RenameRequest {
-                        new_path: new_path.clone(),
-                    };
-                    let resp = Request::post(&url).json(&payload).unwrap().send().await;
-                    match resp {
-                        Ok(r) if r.status() == 401 => {
-                            let current_path = gloo_utils::window()
-                                .location()
-                                .pathname()
-                                .unwrap_or_default();
-                            if current_path != "/login" {
-                                let _ = gloo_utils::window().location().set_href("/login");
-                            }
-                        }
-                        Ok(r) if r.ok() => {
-                            if let Some(nav) = navigator {
-                                nav.push(&Route::Wiki {
-                                    volume,
-                                    path: new_path,
-                                });
-                            }
+                    if send_rename(&volume, &path, &new_path).await {
+                        if let Some(nav) = navigator {
+                            nav.push(&Route::Wiki {
+                                volume,
+                                path: new_path,
+                            });
                         }
-                        Ok(r) => {
-                            let text = r.text().await.unwrap_or_default();
-                            gloo_dialogs::alert(&format!("Failed to rename: {}", text));
-                        }
-                        Err(e) => gloo_dialogs::alert(&format!("Network error: {}", e)),
                     }
                 });
             }
@@ -225,18 +356,116 @@ pub fn use_rename_file(current_volume: String, current_path: String) -> Callback
     })
 }
 
+/// Like `use_rename_file` for a folder. Links into the folder are updated by
+/// the server; the page reloads so the file tree shows the new layout.
+#[hook]
+pub fn use_rename_folder(current_volume: String, current_path: String) -> Callback<()> {
+    Callback::from(move |_| {
+        if let Some(new_path) =
+            gloo_dialogs::prompt("Enter new folder path:", Some(&current_path))
+        {
+            if new_path != current_path && !new_path.is_empty() {
+                let path = current_path.clone();
+                let volume = current_volume.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    if send_rename(&volume, &path, &new_path).await {
+                        let location = gloo_utils::window().location();
+                        // Follow the open page if it was inside the folder.
+                        let current = location.pathname().unwrap_or_default();
+                        let old_prefix = format!("/wiki/{}/{}/", volume, path);
+                        let target = match current.strip_prefix(&old_prefix) {
+                            Some(rest) => format!("/wiki/{}/{}/{}", volume, new_path, rest),
+                            None => current,
+                        };
+                        let _ = location.set_href(&target);
+                    }
+                });
+            }
+        }
+    })
+}
+
+/// A name for a copy next to `path`: `notes/a.md` becomes `notes/a copy.md`.
+fn copy_name(path: &str) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{} copy.{}", dir, stem, ext),
+        _ => format!("{}{} copy", dir, name),
+    }
+}
+
+/// Copies a file or folder to a path the user enters, optionally prefixed
+/// with another volume as `volume:path`, then opens the copy.
+#[hook]
+pub fn use_duplicate(current_volume: String, current_path: String, is_dir: bool) -> Callback<()> {
+    let navigator = use_navigator();
+
+    Callback::from(move |_| {
+        let Some(target) = gloo_dialogs::prompt(
+            "Copy to (path, or volume:path):",
+            Some(&copy_name(&current_path)),
+        ) else {
+            return;
+        };
+        let target = target.trim().to_string();
+        if target.is_empty() {
+            return;
+        }
+        let (volume, new_path) = match target.split_once(':') {
+            Some((volume, path)) => (volume.to_string(), path.to_string()),
+            None => (current_volume.clone(), target),
+        };
+        let rewrite_links = gloo_dialogs::confirm(
+            "Update relative links in the copy so they still point at the same pages?",
+        );
+        let url = format!("/api/copy/{}/{}", current_volume, current_path);
+        let payload = CopyRequest {
+            new_path: new_path.clone(),
+            volume: (volume != current_volume).then(|| volume.clone()),
+            rewrite_links,
+        };
+        let navigator = navigator.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match csrf::post(&url).json(&payload).unwrap().send().await {
+                Ok(r) if r.status() == 401 => {
+                    let _ = gloo_utils::window().location().set_href("/login");
+                }
+                Ok(r) if r.ok() => {
+                    if is_dir {
+                        // Reload so the file tree shows the copied folder.
+                        let _ = gloo_utils::window().location().reload();
+                    } else if let Some(nav) = navigator {
+                        nav.push(&Route::Wiki {
+                            volume,
+                            path: new_path,
+                        });
+                    }
+                }
+                Ok(r) => {
+                    let text = r.text().await.unwrap_or_default();
+                    gloo_dialogs::alert(&format!("Failed to copy: {}", text));
+                }
+                Err(e) => gloo_dialogs::alert(&format!("Network error: {}", e)),
+            }
+        });
+    })
+}
+
 #[hook]
 pub fn use_delete_file(current_volume: String, current_path: String) -> Callback<()> {
     Callback::from(move |_| {
         if gloo_dialogs::confirm(&format!(
-            "Are you sure you want to delete {}?",
+            "Move {} to the trash?",
             current_path
         )) {
             let path = current_path.clone();
             let volume = current_volume.clone();
             wasm_bindgen_futures::spawn_local(async move {
                 let url = format!("/api/wiki/{}/{}", volume, path);
-                let resp = Request::delete(&url).send().await;
+                let resp = csrf::delete(&url).send().await;
                 match resp {
                     Ok(r) if r.status() == 401 => {
                         let current_path = gloo_utils::window()
@@ -262,3 +491,148 @@ pub fn use_delete_file(current_volume: String, current_path: String) -> Callback
         }
     })
 }
+
+/// The files of a file input or drop, taken out of the list, which the
+/// browser empties when the input is reset.
+pub fn file_list(files: &web_sys::FileList) -> Vec<web_sys::File> {
+    (0..files.length()).filter_map(|i| files.item(i)).collect()
+}
+
+/// Uploads files to the volume, images to `assets/images` and everything else
+/// to `assets/files`, and returns what was stored. Rejected files are
+/// reported to the user.
+pub async fn upload_files(volume: &str, files: Vec<web_sys::File>) -> Vec<UploadedFile> {
+    let (images, others): (Vec<_>, Vec<_>) = files
+        .into_iter()
+        .partition(|file| file.type_().starts_with("image/"));
+
+    let mut uploaded = Vec::new();
+    for (folder, batch) in [("assets/images", images), ("assets/files", others)] {
+        if batch.is_empty() {
+            continue;
+        }
+        let Ok(form) = web_sys::FormData::new() else {
+            continue;
+        };
+        for file in &batch {
+            let _ = form.append_with_blob_and_filename("file", file, &file.name());
+        }
+        let url = format!("/api/upload/{}/{}", volume, folder);
+        let request = match csrf::post(&url).body(form) {
+            Ok(request) => request,
+            Err(e) => {
+                gloo_dialogs::alert(&format!("Failed to construct request: {}", e));
+                continue;
+            }
+        };
+        match request.send().await {
+            Ok(resp) if resp.ok() => match resp.json::<Vec<UploadedFile>>().await {
+                Ok(files) => uploaded.extend(files),
+                Err(e) => gloo_dialogs::alert(&format!("Failed to read upload result: {}", e)),
+            },
+            Ok(resp) => {
+                let text = resp.text().await.unwrap_or_default();
+                gloo_dialogs::alert(&format!("Failed to upload: {}", text));
+            }
+            Err(e) => gloo_dialogs::alert(&format!("Error uploading files: {}", e)),
+        }
+    }
+    uploaded
+}
+
+/// The server's explanation of a failed request, or its status.
+pub async fn error_text(resp: gloo_net::http::Response) -> String {
+    match resp.text().await {
+        Ok(text) if !text.is_empty() => text,
+        _ => format!("Server returned {}", resp.status()),
+    }
+}
+
+/// Counts loads of a `RemoteList`; each dispatch asks for another.
+#[derive(Default)]
+struct Generation(u32);
+
+impl Reducible for Generation {
+    type Action = ();
+
+    fn reduce(self: std::rc::Rc<Self>, _: ()) -> std::rc::Rc<Self> {
+        Generation(self.0 + 1).into()
+    }
+}
+
+/// A list loaded from the server by `use_remote_list`.
+pub struct RemoteList<T> {
+    pub items: UseStateHandle<Vec<T>>,
+    /// Whether the first load is still running.
+    pub loading: UseStateHandle<bool>,
+    /// Why the last load or change failed.
+    pub error: UseStateHandle<Option<String>>,
+    reload: UseReducerDispatcher<Generation>,
+}
+
+impl<T> Clone for RemoteList<T> {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            loading: self.loading.clone(),
+            error: self.error.clone(),
+            reload: self.reload.clone(),
+        }
+    }
+}
+
+impl<T: 'static> RemoteList<T> {
+    /// Runs a change, shows its error if any, and loads the list again.
+    pub fn apply(&self, change: impl std::future::Future<Output = Result<(), String>> + 'static) {
+        let error = self.error.clone();
+        let reload = self.reload.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match change.await {
+                Ok(()) => error.set(None),
+                Err(e) => error.set(Some(e)),
+            }
+            reload.dispatch(());
+        });
+    }
+}
+
+/// Loads a JSON list from `url`, again whenever the URL changes or a change
+/// is made through `RemoteList::apply`.
+#[hook]
+pub fn use_remote_list<T>(url: String) -> RemoteList<T>
+where
+    T: serde::de::DeserializeOwned + 'static,
+{
+    let items = use_state(Vec::new);
+    let loading = use_state(|| true);
+    let error = use_state(|| None);
+    let generation = use_reducer(Generation::default);
+
+    {
+        let items = items.clone();
+        let loading = loading.clone();
+        let error = error.clone();
+        use_effect_with((url, generation.0), move |(url, _)| {
+            let url = url.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match Request::get(&url).send().await {
+                    Ok(resp) if resp.ok() => match resp.json::<Vec<T>>().await {
+                        Ok(list) => items.set(list),
+                        Err(e) => error.set(Some(format!("Failed to read the list: {}", e))),
+                    },
+                    Ok(resp) => error.set(Some(error_text(resp).await)),
+                    Err(e) => error.set(Some(format!("Network error: {}", e))),
+                }
+                loading.set(false);
+            });
+            || ()
+        });
+    }
+
+    RemoteList {
+        items,
+        loading,
+        error,
+        reload: generation.dispatcher(),
+    }
+}
@@ -1,14 +1,17 @@
 mod commit_modal;
 mod components;
+mod csrf;
 mod hooks;
 mod login;
 mod parsers;
 mod search_bar;
 
 use commit_modal::CommitModal;
-use common::WikiPage;
+use common::{PageConflict, ShareLink, User, WikiPage};
+use components::admin_modal::AdminModal;
 use components::bottom_bar::BottomBar;
 use components::command_palette::CommandPalette;
+use components::conflict_modal::{ConflictModal, ConflictResolution};
 use components::drawer::Drawer;
 use components::history_modal::HistoryModal;
 use components::settings_modal::SettingsModal;
@@ -57,6 +60,29 @@ pub(crate) enum Route {
     NotFound,
 }
 
+/// The signed-in user from `/api/me`, provided to every component by `Layout`.
+/// Empty until it has loaded.
+#[derive(Clone, PartialEq, Default)]
+pub(crate) struct CurrentUser(pub Option<User>);
+
+impl CurrentUser {
+    /// Whether to offer write actions on `volume`. The server still checks
+    /// every request, including path rules.
+    pub fn can_write(&self, volume: &str) -> bool {
+        self.0.as_ref().is_some_and(|user| user.can_write(volume))
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.0.as_ref().is_some_and(|user| user.admin)
+    }
+}
+
+/// The current user from context, or an empty one outside `Layout`.
+#[hook]
+pub(crate) fn use_current_user() -> CurrentUser {
+    use_context::<CurrentUser>().unwrap_or_default()
+}
+
 #[function_component(App)]
 pub fn app() -> Html {
     html! {
@@ -75,7 +101,7 @@ async fn perform_git_fetch(
     uncommitted_files: UseStateHandle<usize>,
 ) {
     let url = format!("/api/git/{}/fetch", volume);
-    let resp = Request::post(&url).send().await;
+    let resp = csrf::post(&url).send().await;
     if let Ok(r) = resp {
         if r.status() == 401 {
             let current_path = gloo_utils::window()
@@ -98,7 +124,7 @@ async fn perform_git_fetch(
 fn handle_git_action(volume: String, action: &'static str, refresh: Callback<()>) {
     wasm_bindgen_futures::spawn_local(async move {
         let url = format!("/api/git/{}/{}", volume, action);
-        let resp = Request::post(&url).send().await;
+        let resp = csrf::post(&url).send().await;
         let action_past = match action {
             "pull" => "pulled from",
             "push" => "pushed to",
@@ -138,6 +164,7 @@ fn layout() -> Html {
 
     let show_commit_modal = use_state(|| false);
     let show_settings_modal = use_state(|| false);
+    let show_admin_modal = use_state(|| false);
     let show_history_modal = use_state(|| false);
     let is_drawer_open = use_state(|| false);
     let is_search_open = use_state(|| false);
@@ -147,6 +174,30 @@ fn layout() -> Html {
     let commits_behind = use_state(|| 0);
     let uncommitted_files = use_state(|| 0);
 
+    let current_user = use_state(CurrentUser::default);
+    {
+        let current_user = current_user.clone();
+        use_effect_with((), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                let current_path = gloo_utils::window()
+                    .location()
+                    .pathname()
+                    .unwrap_or_default();
+                if current_path == "/login" {
+                    return;
+                }
+                if let Ok(resp) = Request::get("/api/me").send().await {
+                    if let Ok(user) = resp.json::<User>().await {
+                        current_user.set(CurrentUser(Some(user)));
+                    }
+                }
+            });
+            || ()
+        });
+    }
+    // Git fetch is a write, so read-only volumes are not polled.
+    let can_write = current_user.can_write(&current_volume);
+
     // Reset editing state on navigation
     {
         let is_editing = is_editing.clone();
@@ -196,7 +247,7 @@ fn layout() -> Html {
         let commits_ahead = commits_ahead.clone();
         let commits_behind = commits_behind.clone();
         let uncommitted_files = uncommitted_files.clone();
-        use_effect_with(volume.clone(), move |volume| {
+        use_effect_with((volume.clone(), can_write), move |(volume, can_write)| {
             let volume = volume.clone();
             let commits_ahead = commits_ahead.clone();
             let commits_behind = commits_behind.clone();
@@ -205,7 +256,7 @@ fn layout() -> Html {
             // Single-threaded wasm: Rc<Cell<bool>> is safe and zero-overhead.
             let is_fetching = std::rc::Rc::new(std::cell::Cell::new(false));
 
-            let interval = gloo_timers::callback::Interval::new(10_000, {
+            let interval = can_write.then(|| gloo_timers::callback::Interval::new(10_000, {
                 let is_fetching = is_fetching.clone();
                 move || {
                     // Skip this tick if the previous fetch is still in progress.
@@ -232,7 +283,7 @@ fn layout() -> Html {
                         is_fetching_done.set(false);
                     });
                 }
-            });
+            }));
 
             move || {
                 drop(interval);
@@ -246,8 +297,9 @@ fn layout() -> Html {
         let commits_ahead = commits_ahead.clone();
         let commits_behind = commits_behind.clone();
         let uncommitted_files = uncommitted_files.clone();
-        use_effect_with(volume, move |volume| {
+        use_effect_with((volume, can_write), move |(volume, can_write)| {
             let volume = volume.clone();
+            let can_write = *can_write;
             let commits_ahead = commits_ahead.clone();
             let commits_behind = commits_behind.clone();
             let uncommitted_files = uncommitted_files.clone();
@@ -257,7 +309,7 @@ fn layout() -> Html {
                     .location()
                     .pathname()
                     .unwrap_or_default();
-                if current_path != "/login" {
+                if can_write && current_path != "/login" {
                     perform_git_fetch(volume, commits_ahead, commits_behind, uncommitted_files)
                         .await;
                 }
@@ -307,6 +359,20 @@ fn layout() -> Html {
         let show_settings_modal = show_settings_modal.clone();
         Callback::from(move |_| show_settings_modal.set(false))
     };
+
+    let on_manage_users = {
+        let show_settings_modal = show_settings_modal.clone();
+        let show_admin_modal = show_admin_modal.clone();
+        Callback::from(move |_| {
+            show_settings_modal.set(false);
+            show_admin_modal.set(true);
+        })
+    };
+
+    let on_close_admin = {
+        let show_admin_modal = show_admin_modal.clone();
+        Callback::from(move |_| show_admin_modal.set(false))
+    };
     let on_toggle_history = {
         let show_history_modal = show_history_modal.clone();
         Callback::from(move |_| show_history_modal.set(!*show_history_modal))
@@ -348,7 +414,11 @@ fn layout() -> Html {
 
     let on_edit_trigger = {
         let is_editing = is_editing.clone();
-        Callback::from(move |_| is_editing.set(true))
+        Callback::from(move |_| {
+            if can_write {
+                is_editing.set(true);
+            }
+        })
     };
 
     let on_save_trigger = Callback::from(move |_| triggerSave("code-editor"));
@@ -426,6 +496,7 @@ fn layout() -> Html {
     let is_dark = *theme == "dark";
 
     html! {
+        <ContextProvider<CurrentUser> context={(*current_user).clone()}>
         <div class="container">
             <Drawer
                 is_open={*is_drawer_open}
@@ -457,6 +528,7 @@ fn layout() -> Html {
                 commits_behind={*commits_behind}
                 uncommitted_files={*uncommitted_files}
                 is_drawer_open={*is_drawer_open}
+                can_write={can_write}
                 on_settings={on_toggle_settings.clone()}
                 on_history={on_toggle_history.clone()}
                 on_copy_link={on_copy_link.clone()}
@@ -500,9 +572,14 @@ fn layout() -> Html {
             }
 
             if *show_settings_modal {
-                <SettingsModal on_close={on_close_settings} />
+                <SettingsModal on_close={on_close_settings} on_manage_users={on_manage_users} />
+            }
+
+            if *show_admin_modal {
+                <AdminModal on_close={on_close_admin} />
             }
         </div>
+        </ContextProvider<CurrentUser>>
     }
 }
 
@@ -552,14 +629,30 @@ enum ViewMode {
     Page(WikiPage),
     Image(String),
     Pdf(String),
+    Audio(String),
+    Video(String),
     Error(String),
     NotFound(String),
 }
 
+/// The server's copy of a page that edits are based on, sent back as
+/// `If-Match` so a save cannot overwrite someone else's changes. A page that
+/// did not exist yet is saved with `If-None-Match: *` instead.
+#[derive(Default)]
+struct BaseVersion {
+    etag: Option<String>,
+    content: String,
+}
+
 #[function_component(WikiViewer)]
 fn wiki_viewer(props: &WikiViewerProps) -> Html {
     let view_mode = use_state(|| ViewMode::Loading);
     let is_uncommitted = use_state(|| false);
+    let base = use_mut_ref(BaseVersion::default);
+    // The text that failed to save, and what the server had instead.
+    let conflict = use_state(|| Option::<(String, PageConflict)>::None);
+    // Bumped to restart the editor with new content.
+    let editor_rev = use_state(|| 0u32);
 
     // We use the prop to control editing state
     let is_editing = props.is_editing;
@@ -570,16 +663,23 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
     let path = props.path.clone();
     let volume = props.volume.clone();
     let vim_mode = props.vim_mode;
+    let can_write = use_current_user().can_write(&volume);
 
     {
         let view_mode = view_mode.clone();
+        let base = base.clone();
         let path = path.clone();
         let volume = volume.clone();
         use_effect_with((volume.clone(), path.clone()), move |_| {
             let view_mode = view_mode.clone();
             wasm_bindgen_futures::spawn_local(async move {
                 let url = format!("/api/wiki/{}/{}", volume, path);
-                let resp = Request::get(&url).send().await;
+                // Media is only looked at to learn its type; the viewer streams it.
+                let abort = web_sys::AbortController::new().ok();
+                let resp = Request::get(&url)
+                    .abort_signal(abort.as_ref().map(|a| a.signal()).as_ref())
+                    .send()
+                    .await;
 
                 match resp {
                     Ok(r) if r.status() == 401 => {
@@ -595,23 +695,37 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                     Ok(r) if r.ok() => {
                         let content_type = r.headers().get("Content-Type").unwrap_or_default();
                         if content_type.contains("application/json") {
+                            let etag = r.headers().get("ETag");
                             let page: WikiPage = r.json().await.unwrap_or_else(|_| WikiPage {
                                 path: path.clone(),
                                 content: "Error parsing JSON".to_string(),
                             });
+                            *base.borrow_mut() = BaseVersion {
+                                etag,
+                                content: page.content.clone(),
+                            };
                             view_mode.set(ViewMode::Page(page));
-                        } else if content_type.starts_with("image/") {
-                            view_mode.set(ViewMode::Image(url));
-                        } else if content_type == "application/pdf" {
-                            view_mode.set(ViewMode::Pdf(url));
                         } else {
-                            // Handle known binary types or unknown types that fall through
-                            // If it's not JSON (WikiPage), Image, or PDF, it's something we can't display.
-                            // The backend returns raw bytes for these.
-                            view_mode.set(ViewMode::Error(format!(
-                                "Unsupported file type: {}",
-                                content_type
-                            )));
+                            if let Some(abort) = &abort {
+                                abort.abort();
+                            }
+                            if content_type.starts_with("image/") {
+                                view_mode.set(ViewMode::Image(url));
+                            } else if content_type == "application/pdf" {
+                                view_mode.set(ViewMode::Pdf(url));
+                            } else if content_type.starts_with("audio/") {
+                                view_mode.set(ViewMode::Audio(url));
+                            } else if content_type.starts_with("video/") {
+                                view_mode.set(ViewMode::Video(url));
+                            } else {
+                                // Handle known binary types or unknown types that fall through
+                                // If it's not JSON (WikiPage), Image, PDF, Audio or Video, it's something we can't display.
+                                // The backend returns raw bytes for these.
+                                view_mode.set(ViewMode::Error(format!(
+                                    "Unsupported file type: {}",
+                                    content_type
+                                )));
+                            }
                         }
                     }
                     _ => view_mode.set(ViewMode::NotFound(path.clone())),
@@ -687,6 +801,34 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
         Callback::from(move |_| cb.emit(()))
     };
 
+    let on_share_click = {
+        let volume = volume.clone();
+        let path = path.clone();
+        Callback::from(move |_| {
+            let url = format!("/api/share/{}/{}", volume, path);
+            wasm_bindgen_futures::spawn_local(async move {
+                match Request::get(&url).send().await {
+                    Ok(r) if r.ok() => match r.json::<ShareLink>().await {
+                        Ok(link) => {
+                            let window = gloo_utils::window();
+                            let origin = window.location().origin().unwrap_or_default();
+                            let full_url = format!("{}{}", origin, link.url);
+                            let promise = window.navigator().clipboard().write_text(&full_url);
+                            if wasm_bindgen_futures::JsFuture::from(promise).await.is_ok() {
+                                gloo_dialogs::alert("Share link copied to clipboard. It expires in 24 hours.");
+                            } else {
+                                gloo_dialogs::prompt("Share link (expires in 24 hours):", Some(&full_url));
+                            }
+                        }
+                        Err(e) => gloo_dialogs::alert(&format!("Failed to read share link: {}", e)),
+                    },
+                    Ok(r) => gloo_dialogs::alert(&format!("Failed to create share link: {}", r.status())),
+                    Err(e) => gloo_dialogs::alert(&format!("Network error: {}", e)),
+                }
+            });
+        })
+    };
+
     {
         let volume = volume.clone();
         let path = path.clone();
@@ -731,12 +873,17 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
         let volume = volume.clone();
         let view_mode = view_mode.clone();
         let on_edit_toggle = on_edit_toggle.clone();
+        let base = base.clone();
+        let conflict = conflict.clone();
 
         Callback::from(move |new_content: String| {
             let path = path.clone();
             let volume = volume.clone();
             let view_mode = view_mode.clone();
             let on_edit_toggle = on_edit_toggle.clone();
+            let base = base.clone();
+            let conflict = conflict.clone();
+            let if_match = base.borrow().etag.clone();
 
             wasm_bindgen_futures::spawn_local(async move {
                 let page = WikiPage {
@@ -751,10 +898,13 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                     }
                 };
 
-                let req = match Request::put(&format!("/api/wiki/{}/{}", volume, path))
-                    .header("Content-Type", "application/json")
-                    .body(body_str)
-                {
+                let mut req = csrf::put(&format!("/api/wiki/{}/{}", volume, path))
+                    .header("Content-Type", "application/json");
+                req = match &if_match {
+                    Some(etag) => req.header("If-Match", etag),
+                    None => req.header("If-None-Match", "*"),
+                };
+                let req = match req.body(body_str) {
                     Ok(r) => r,
                     Err(e) => {
                         gloo_dialogs::alert(&format!("Failed to build request: {}", e));
@@ -773,8 +923,17 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                         if current_path != "/login" {
                             let _ = gloo_utils::window().location().set_href("/login");
                         }
+                    } else if r.status() == 409 {
+                        match r.json::<PageConflict>().await {
+                            Ok(server) => conflict.set(Some((new_content, server))),
+                            Err(e) => gloo_dialogs::alert(&format!("Save failed: {}", e)),
+                        }
                     } else if r.ok() {
                         web_sys::console::log_1(&wasm_bindgen::JsValue::from_str("PUT request OK!"));
+                        *base.borrow_mut() = BaseVersion {
+                            etag: r.headers().get("ETag"),
+                            content: new_content.clone(),
+                        };
                         view_mode.set(ViewMode::Page(WikiPage {
                             path: path.clone(),
                             content: new_content.clone(),
@@ -791,6 +950,47 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
         })
     };
 
+    let on_conflict_resolve = {
+        let path = path.clone();
+        let view_mode = view_mode.clone();
+        let on_edit_toggle = on_edit_toggle.clone();
+        let base = base.clone();
+        let conflict = conflict.clone();
+        let editor_rev = editor_rev.clone();
+        let on_save = on_save.clone();
+        Callback::from(move |resolution: ConflictResolution| {
+            let Some((mine, server)) = (*conflict).clone() else {
+                return;
+            };
+            conflict.set(None);
+            // Whatever happens next is based on what the server has now.
+            *base.borrow_mut() = BaseVersion {
+                etag: server.etag,
+                content: server.content.clone().unwrap_or_default(),
+            };
+            match resolution {
+                ConflictResolution::Merged(text) => {
+                    view_mode.set(ViewMode::Page(WikiPage {
+                        path: path.clone(),
+                        content: text,
+                    }));
+                    editor_rev.set(*editor_rev + 1);
+                }
+                ConflictResolution::KeepMine => on_save.emit(mine),
+                ConflictResolution::TakeServer => {
+                    view_mode.set(match server.content {
+                        Some(content) => ViewMode::Page(WikiPage {
+                            path: path.clone(),
+                            content,
+                        }),
+                        None => ViewMode::NotFound(path.clone()),
+                    });
+                    on_edit_toggle.emit(false);
+                }
+            }
+        })
+    };
+
     if is_editing {
         let current_content = match &*view_mode {
             ViewMode::Page(p) => p.content.clone(),
@@ -812,7 +1012,16 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                         <button class="btn btn-primary" onclick={Callback::from(|_| triggerSave("code-editor"))} aria-label="Save changes">{ "Save" }</button>
                     </div>
                 </div>
-                <Editor key={path.clone()} volume={volume} path={path.clone()} content={current_content} on_save={on_save} vim_mode={vim_mode} on_edit_toggle={on_edit_toggle} />
+                <Editor key={format!("{}#{}", path, *editor_rev)} volume={volume} path={path.clone()} content={current_content} on_save={on_save} vim_mode={vim_mode} on_edit_toggle={on_edit_toggle} />
+                if let Some((mine, server)) = &*conflict {
+                    <ConflictModal
+                        base={base.borrow().content.clone()}
+                        mine={mine.clone()}
+                        conflict={server.clone()}
+                        on_resolve={on_conflict_resolve}
+                        on_cancel={let conflict = conflict.clone(); move |_| conflict.set(None)}
+                    />
+                }
              </div>
         }
     } else {
@@ -863,11 +1072,13 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                                     <span class="badge bg-accent ml-2">{ "Draft" }</span>
                                 }
                             </span>
-                            <div class="toolbar-controls">
-                                <button class="btn" onclick={on_edit_click} aria-label={format!("Edit page {}", &path)}>{ "Edit" }</button>
-                                <button class="btn" onclick={on_rename_click.clone()} aria-label={format!("Rename page {}", &path)}>{ "Rename" }</button>
-                                <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
-                            </div>
+                            if can_write {
+                                <div class="toolbar-controls">
+                                    <button class="btn" onclick={on_edit_click} aria-label={format!("Edit page {}", &path)}>{ "Edit" }</button>
+                                    <button class="btn" onclick={on_rename_click.clone()} aria-label={format!("Rename page {}", &path)}>{ "Rename" }</button>
+                                    <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
+                                </div>
+                            }
                         </div>
                         <div class="markdown-body">
                             { render_content }
@@ -880,7 +1091,10 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                     <div class="toolbar">
                         <span class="path">{ &display_path }</span>
                         <div class="toolbar-controls">
-                            <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
+                            <button class="btn" onclick={on_share_click.clone()} aria-label={format!("Copy share link for {}", &path)}>{ "Share Link" }</button>
+                            if can_write {
+                                <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
+                            }
                         </div>
                     </div>
                     <div class="image-viewer">
@@ -893,7 +1107,10 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                      <div class="toolbar">
                         <span class="path">{ &display_path }</span>
                         <div class="toolbar-controls">
-                            <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
+                            <button class="btn" onclick={on_share_click.clone()} aria-label={format!("Copy share link for {}", &path)}>{ "Share Link" }</button>
+                            if can_write {
+                                <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
+                            }
                         </div>
                     </div>
                     <div class="pdf-viewer">
@@ -901,6 +1118,38 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                     </div>
                 </div>
             },
+            ViewMode::Audio(url) => html! {
+                <div class="wiki-viewer">
+                    <div class="toolbar">
+                        <span class="path">{ &display_path }</span>
+                        <div class="toolbar-controls">
+                            <button class="btn" onclick={on_share_click.clone()} aria-label={format!("Copy share link for {}", &path)}>{ "Share Link" }</button>
+                            if can_write {
+                                <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
+                            }
+                        </div>
+                    </div>
+                    <div class="media-viewer">
+                        <audio src={url.clone()} controls=true preload="metadata" aria-label={path.clone()}></audio>
+                    </div>
+                </div>
+            },
+            ViewMode::Video(url) => html! {
+                <div class="wiki-viewer">
+                    <div class="toolbar">
+                        <span class="path">{ &display_path }</span>
+                        <div class="toolbar-controls">
+                            <button class="btn" onclick={on_share_click.clone()} aria-label={format!("Copy share link for {}", &path)}>{ "Share Link" }</button>
+                            if can_write {
+                                <button class="btn btn-danger" onclick={on_delete_click.clone()} aria-label={format!("Delete page {}", &path)}>{ "Delete" }</button>
+                            }
+                        </div>
+                    </div>
+                    <div class="media-viewer">
+                        <video src={url.clone()} controls=true preload="metadata" aria-label={path.clone()}></video>
+                    </div>
+                </div>
+            },
             ViewMode::Error(msg) => html! {
                 <div class="error-viewer" role="alert">
                     <h3>{ "Error displaying file" }</h3>
@@ -915,9 +1164,11 @@ fn wiki_viewer(props: &WikiViewerProps) -> Html {
                     <div class="markdown-body text-center mt-8">
                         <h2>{ "Page Not Found" }</h2>
                         <p>{ format!("The page '{}' does not exist yet.", p) }</p>
-                        <button class="btn btn-primary" onclick={on_edit_click} aria-label={format!("Create page {}", &path)}>
-                            { "Create this page" }
-                        </button>
+                        if can_write {
+                            <button class="btn btn-primary" onclick={on_edit_click} aria-label={format!("Create page {}", &path)}>
+                                { "Create this page" }
+                            </button>
+                        }
                     </div>
                 </div>
             },
@@ -1074,50 +1325,20 @@ fn editor(props: &EditorProps) -> Html {
         let volume = volume.clone();
         Callback::from(move |e: web_sys::DragEvent| {
             e.prevent_default();
-            if let Some(dt) = e.data_transfer() {
-                if let Some(files) = dt.files() {
-                    if files.length() > 0 {
-                        if let Some(file) = files.item(0) {
-                            let filename = file.name();
-                            let volume_clone = volume.clone();
-                            wasm_bindgen_futures::spawn_local(async move {
-                                let url = format!(
-                                    "/api/upload/{}/assets/images/{}",
-                                    volume_clone, filename
-                                );
-                                let req_res = Request::post(&url).body(file);
-
-                                let req = match req_res {
-                                    Ok(r) => r,
-                                    Err(e) => {
-                                        gloo_dialogs::alert(&format!(
-                                            "Failed to build request: {}",
-                                            e
-                                        ));
-                                        return;
-                                    }
-                                };
-
-                                match req.send().await {
-                                    Ok(resp) if resp.ok() => {
-                                        let markdown_image =
-                                            format!("![{}](assets/images/{})", filename, filename);
-                                        insertTextAtCursor("code-editor", &markdown_image);
-                                    }
-                                    Ok(resp) => {
-                                        gloo_dialogs::alert(&format!(
-                                            "Upload failed: {}",
-                                            resp.status()
-                                        ));
-                                    }
-                                    Err(err) => {
-                                        gloo_dialogs::alert(&format!("Upload error: {}", err));
-                                    }
-                                }
-                            });
-                        }
-                    }
+            if let Some(files) = e.data_transfer().and_then(|dt| dt.files()) {
+                let files = hooks::file_list(&files);
+                if files.is_empty() {
+                    return;
                 }
+                let volume = volume.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let uploaded = hooks::upload_files(&volume, files).await;
+                    let snippets: Vec<&str> =
+                        uploaded.iter().map(|file| file.markdown.as_str()).collect();
+                    if !snippets.is_empty() {
+                        insertTextAtCursor("code-editor", &snippets.join("\n"));
+                    }
+                });
             }
         })
     };
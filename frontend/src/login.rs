@@ -1,3 +1,4 @@
+use common::{AuthMode, LoginResponse, TotpCodeRequest};
 use gloo_net::http::Request;
 use serde::Serialize;
 use web_sys::HtmlInputElement;
@@ -17,6 +18,29 @@ pub fn login() -> Html {
     let stay_signed_in = use_state(|| false);
     let error_msg = use_state(|| Option::<String>::None);
     let is_loading = use_state(|| false);
+    // Set once the password is accepted and the server asks for a second factor.
+    let totp_required = use_state(|| false);
+    let code = use_state(String::new);
+    let password_login = use_state(|| true);
+
+    // Behind a trusted SSO proxy the user is already signed in; skip the form.
+    {
+        let password_login = password_login.clone();
+        use_effect_with((), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Ok(r) = Request::get("/api/auth/mode").send().await {
+                    if let Ok(mode) = r.json::<AuthMode>().await {
+                        if mode.proxy_user.is_some() {
+                            let _ = gloo_utils::window().location().set_href("/");
+                        } else if !mode.password_login {
+                            password_login.set(false);
+                        }
+                    }
+                }
+            });
+            || ()
+        });
+    }
 
     let on_username_change = {
         let username = username.clone();
@@ -42,6 +66,18 @@ pub fn login() -> Html {
         })
     };
 
+    let on_code_change = {
+        let code = code.clone();
+        Callback::from(move |e: Event| {
+            let target: Option<web_sys::EventTarget> = e.target();
+            if let Some(target) = target {
+                use wasm_bindgen::JsCast;
+                let input = target.unchecked_into::<HtmlInputElement>();
+                code.set(input.value());
+            }
+        })
+    };
+
     let on_stay_signed_in_change = {
         let stay_signed_in = stay_signed_in.clone();
         Callback::from(move |e: Event| {
@@ -60,6 +96,7 @@ pub fn login() -> Html {
         let stay_signed_in = stay_signed_in.clone();
         let error_msg = error_msg.clone();
         let is_loading = is_loading.clone();
+        let totp_required = totp_required.clone();
 
         Callback::from(move |e: SubmitEvent| {
             e.prevent_default();
@@ -69,6 +106,7 @@ pub fn login() -> Html {
             let stay_signed_in_val = *stay_signed_in;
             let is_loading = is_loading.clone();
             let error_msg = error_msg.clone();
+            let totp_required = totp_required.clone();
 
             is_loading.set(true);
             error_msg.set(None);
@@ -95,11 +133,22 @@ pub fn login() -> Html {
 
                 match resp {
                     Ok(r) if r.ok() => {
-                        let _ = gloo_utils::window().location().set_href("/");
+                        let body = r.json::<LoginResponse>().await.unwrap_or_default();
+                        if body.totp_required {
+                            totp_required.set(true);
+                        } else {
+                            let _ = gloo_utils::window().location().set_href("/");
+                        }
                     }
                     Ok(r) => {
                         if r.status() == 401 {
                             error_msg.set(Some("Invalid username or password".to_string()));
+                        } else if r.status() == 429 {
+                            let wait = r.headers().get("retry-after").unwrap_or_default();
+                            error_msg.set(Some(format!(
+                                "Too many failed attempts. Try again in {} seconds.",
+                                wait
+                            )));
                         } else {
                             error_msg.set(Some(format!("Error: {}", r.status())));
                         }
@@ -112,6 +161,126 @@ pub fn login() -> Html {
         })
     };
 
+    let on_code_submit = {
+        let code = code.clone();
+        let error_msg = error_msg.clone();
+        let is_loading = is_loading.clone();
+
+        Callback::from(move |e: SubmitEvent| {
+            e.prevent_default();
+
+            let code_val = (*code).clone();
+            let is_loading = is_loading.clone();
+            let error_msg = error_msg.clone();
+
+            is_loading.set(true);
+            error_msg.set(None);
+
+            wasm_bindgen_futures::spawn_local(async move {
+                let payload = TotpCodeRequest { code: code_val };
+                let resp = match Request::post("/api/login/totp").json(&payload) {
+                    Ok(req) => req.send().await,
+                    Err(e) => {
+                        is_loading.set(false);
+                        error_msg.set(Some(format!("Failed to prepare request: {}", e)));
+                        return;
+                    }
+                };
+
+                is_loading.set(false);
+
+                match resp {
+                    Ok(r) if r.ok() => {
+                        let _ = gloo_utils::window().location().set_href("/");
+                    }
+                    Ok(r) if r.status() == 401 => {
+                        error_msg.set(Some(
+                            "Invalid code. After several failed attempts you need to sign in again."
+                                .to_string(),
+                        ));
+                    }
+                    Ok(r) if r.status() == 429 => {
+                        let wait = r.headers().get("retry-after").unwrap_or_default();
+                        error_msg.set(Some(format!(
+                            "Too many failed attempts. Try again in {} seconds.",
+                            wait
+                        )));
+                    }
+                    Ok(r) => {
+                        error_msg.set(Some(format!("Error: {}", r.status())));
+                    }
+                    Err(_) => {
+                        error_msg.set(Some("Network error while logging in".to_string()));
+                    }
+                }
+            });
+        })
+    };
+
+    let on_back_to_password = {
+        let totp_required = totp_required.clone();
+        let code = code.clone();
+        let error_msg = error_msg.clone();
+        Callback::from(move |_: MouseEvent| {
+            totp_required.set(false);
+            code.set(String::new());
+            error_msg.set(None);
+        })
+    };
+
+    if !*password_login {
+        return html! {
+            <div class="login-wrapper">
+                <div class="login-card">
+                    <h2>{ "Login to WebWiki" }</h2>
+                    <p class="text-center">
+                        { "Sign-in is handled by your organisation's single sign-on. Reload the page through the SSO gateway to continue." }
+                    </p>
+                </div>
+            </div>
+        };
+    }
+
+    if *totp_required {
+        return html! {
+            <div class="login-wrapper">
+                <div class="login-card">
+                    <h2>{ "Two-Factor Authentication" }</h2>
+                    if let Some(msg) = (*error_msg).as_ref() {
+                        <div class="error-message text-danger text-center mb-4" role="alert">{ msg }</div>
+                    }
+                    <form onsubmit={on_code_submit}>
+                        <div class="form-group mb-4">
+                            <label for="totp-code" class="d-block mb-2">
+                                { "Enter the code from your authenticator app, or a recovery code" }
+                            </label>
+                            <input
+                                type="text"
+                                id="totp-code"
+                                inputmode="numeric"
+                                autocomplete="one-time-code"
+                                value={(*code).clone()}
+                                onchange={on_code_change}
+                                required=true
+                                class="w-full"
+                            />
+                        </div>
+                        <button type="submit" disabled={*is_loading} aria-busy={(*is_loading).to_string()} class="btn btn-primary w-full p-3 mb-4">
+                            if *is_loading {
+                                { "Verifying..." }
+                            } else {
+                                { "Verify" }
+                            }
+                        </button>
+                        <button type="button" onclick={on_back_to_password} class="btn w-full p-3">
+                            { "Back" }
+                        </button>
+                    </form>
+                </div>
+            </div>
+        };
+    }
+
     html! {
         <div class="login-wrapper">
             <div class="login-card">
@@ -18,6 +18,50 @@ fn is_image_link(link: &str) -> bool {
         || lower.ends_with(".webp")
 }
 
+/// Widths of the resized variants the browser can pick from for wiki images.
+const VARIANT_WIDTHS: [u32; 3] = [480, 960, 1920];
+
+/// Whether the server can serve resized variants of this image. GIFs are
+/// served as they are so they keep their animation.
+fn is_resizable_image(url: &str) -> bool {
+    let lower = url.to_lowercase();
+    lower.starts_with("/wiki/")
+        && !lower.contains('?')
+        && [".png", ".jpg", ".jpeg", ".webp"]
+            .iter()
+            .any(|ext| lower.ends_with(ext))
+}
+
+fn escape_attr(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// An `<img>` that lets the browser load a variant of a wiki image sized for
+/// the preview instead of the full-resolution original.
+fn responsive_image(url: &str, alt: &str, title: &str) -> String {
+    let url = escape_attr(url);
+    let srcset: Vec<String> = VARIANT_WIDTHS
+        .iter()
+        .map(|w| format!("{}?w={} {}w", url, w, w))
+        .collect();
+    let title = if title.is_empty() {
+        String::new()
+    } else {
+        format!(r#" title="{}""#, escape_attr(title))
+    };
+    format!(
+        r#"<img src="{}?w=960" srcset="{}" sizes="(max-width: 960px) 100vw, 960px" alt="{}"{} loading="lazy" />"#,
+        url,
+        srcset.join(", "),
+        escape_attr(alt),
+        title
+    )
+}
+
 impl<'a> WikiLinkParser<'a> {
     pub fn new(parser: Parser<'a>, volume: String, current_path: String) -> Self {
         Self {
@@ -121,6 +165,21 @@ impl<'a> Iterator for WikiLinkParser<'a> {
             let dest_str = dest_url.as_ref();
             if !dest_str.starts_with("http://") && !dest_str.starts_with("https://") {
                 let link_url = self.resolve_link_url(dest_str);
+                if is_resizable_image(&link_url) {
+                    // The alt text comes as the events up to the end of the image.
+                    let mut alt = String::new();
+                    for inner in self.parser.by_ref() {
+                        match inner {
+                            pulldown_cmark::Event::End(TagEnd::Image) => break,
+                            pulldown_cmark::Event::Text(text)
+                            | pulldown_cmark::Event::Code(text) => alt.push_str(&text),
+                            _ => {}
+                        }
+                    }
+                    return Some(pulldown_cmark::Event::InlineHtml(CowStr::from(
+                        responsive_image(&link_url, &alt, title),
+                    )));
+                }
                 return Some(pulldown_cmark::Event::Start(Tag::Image {
                     link_type: *link_type,
                     dest_url: CowStr::from(link_url),
@@ -188,7 +247,12 @@ impl<'a> Iterator for WikiLinkParser<'a> {
 
                     let is_image = is_image_link(link) || is_image_link(&link_url);
 
-                    if is_image {
+                    if is_image && is_resizable_image(&link_url) {
+                        self.events
+                            .push_back(pulldown_cmark::Event::InlineHtml(CowStr::from(
+                                responsive_image(&link_url, &label_text, ""),
+                            )));
+                    } else if is_image {
                         self.events
                             .push_back(pulldown_cmark::Event::Start(Tag::Image {
                                 link_type: LinkType::Inline,
@@ -460,15 +524,28 @@ mod tests {
     fn test_wikilink_image() {
         let input = "[[assets/image.png|Alt Text]]";
         let output = render(input, "default", "Folder/File.md");
-        assert!(output
-            .contains(r#"<img src="/wiki/default/Folder/assets/image.png" alt="Alt Text" />"#));
+        assert!(output.contains(r#"<img src="/wiki/default/Folder/assets/image.png?w=960""#));
+        assert!(output.contains(r#"alt="Alt Text""#));
     }
 
     #[test]
     fn test_standard_markdown_image() {
-        let input = "![Alt Text](assets/image.png)";
+        let input = "![Alt *Text*](assets/image.png \"A title\")";
         let output = render(input, "default", "Folder/File.md");
-        assert!(output
-            .contains(r#"<img src="/wiki/default/Folder/assets/image.png" alt="Alt Text" />"#));
+        assert!(output.contains(
+            r#"<img src="/wiki/default/Folder/assets/image.png?w=960" srcset="/wiki/default/Folder/assets/image.png?w=480 480w, /wiki/default/Folder/assets/image.png?w=960 960w, /wiki/default/Folder/assets/image.png?w=1920 1920w" sizes="(max-width: 960px) 100vw, 960px" alt="Alt Text" title="A title" loading="lazy" />"#
+        ));
+    }
+
+    #[test]
+    fn test_gif_and_external_images_are_not_resized() {
+        let output = render("![Anim](assets/anim.gif)", "default", "File.md");
+        assert!(output.contains(r#"<img src="/wiki/default/assets/anim.gif" alt="Anim" />"#));
+        let output = render(
+            "![Logo](https://example.com/logo.png)",
+            "default",
+            "File.md",
+        );
+        assert!(output.contains(r#"<img src="https://example.com/logo.png" alt="Logo" />"#));
     }
 }